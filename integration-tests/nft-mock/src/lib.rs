@@ -0,0 +1,46 @@
+// Minimal NEP-171 contract for integration tests of NFT bridging
+use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+use near_contract_standards::non_fungible_token::{NonFungibleToken, Token, TokenId};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::{near_bindgen, AccountId, BorshStorageKey, PanicOnDefault, PromiseOrValue};
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    TokenOwners,
+    TokenMetadata,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct NftMock {
+    tokens: NonFungibleToken,
+}
+
+#[near_bindgen]
+impl NftMock {
+    #[init]
+    pub fn new(owner_id: AccountId) -> Self {
+        Self {
+            tokens: NonFungibleToken::new(
+                StorageKey::TokenOwners,
+                owner_id,
+                Some(StorageKey::TokenMetadata),
+                None::<StorageKey>,
+                None::<StorageKey>,
+            ),
+        }
+    }
+
+    // Anyone can mint, storage is paid by the contract balance
+    pub fn nft_mint(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        token_metadata: TokenMetadata,
+    ) -> Token {
+        self.tokens
+            .internal_mint_with_refund(token_id, receiver_id, Some(token_metadata), None)
+    }
+}
+
+near_contract_standards::impl_non_fungible_token_core!(NftMock, tokens);
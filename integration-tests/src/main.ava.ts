@@ -7,6 +7,8 @@ import {
   KeyPairEd25519,
 } from 'near-workspaces'
 import anyTest, { TestFn } from 'ava'
+import { createHash } from 'crypto'
+import { readFileSync } from 'fs'
 
 const ONE_NEAR = new BN(new BN('10').pow(new BN('24')))
 const DELTA = new BN(new BN('10').pow(new BN('22')))
@@ -50,6 +52,20 @@ async function storageDeposit(
   await user.call(bridge, 'storage_deposit', {}, { attachedDeposit: amount })
 }
 
+// Signature of the bond hash of the owner by the relayer key
+async function signBondHash(
+  bridge: NearAccount,
+  relayer: NearAccount,
+  owner: NearAccount,
+) {
+  const hash = (await bridge.view('get_bond_hash', {
+    owner: owner.accountId,
+  })) as Uint8Array
+  return Array.from(
+    (await relayer.getKey())!.sign(Uint8Array.from(hash)).signature,
+  )
+}
+
 test.beforeEach(async (t) => {
   const worker = await Worker.init()
 
@@ -261,9 +277,12 @@ test('storage_withdraw() is correct', async (t) => {
   const { bridge, user } = t.context.accounts
 
   let error = await t.throwsAsync(
-    user.call(bridge, 'storage_withdraw', {
-      amount: new BN(1).toString(),
-    }),
+    user.call(
+      bridge,
+      'storage_withdraw',
+      { amount: new BN(1).toString() },
+      { attachedDeposit: '1' },
+    ),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
@@ -273,9 +292,12 @@ test('storage_withdraw() is correct', async (t) => {
   await storageDeposit(user, bridge, ONE_NEAR)
 
   error = await t.throwsAsync(
-    user.call(bridge, 'storage_withdraw', {
-      amount: ONE_NEAR.sub(payForRegister).add(new BN(1)).toString(),
-    }),
+    user.call(
+      bridge,
+      'storage_withdraw',
+      { amount: ONE_NEAR.sub(payForRegister).add(new BN(1)).toString() },
+      { attachedDeposit: '1' },
+    ),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
@@ -293,9 +315,12 @@ test('storage_withdraw() is correct', async (t) => {
       user: user.accountId,
     })) as any)[1],
   )
-  await user.call(bridge, 'storage_withdraw', {
-    amount: ONE_NEAR.sub(payForRegister).toString(),
-  })
+  await user.call(
+    bridge,
+    'storage_withdraw',
+    { amount: ONE_NEAR.sub(payForRegister).toString() },
+    { attachedDeposit: '1' },
+  )
   t.true(
     new BN(
       ((await bridge.view('get_storage_paid_info', {
@@ -322,9 +347,12 @@ test('storage_withdraw() is correct', async (t) => {
   )
 
   error = await t.throwsAsync(
-    user.call(bridge, 'storage_withdraw', {
-      amount: new BN(1).toString(),
-    }),
+    user.call(
+      bridge,
+      'storage_withdraw',
+      { amount: new BN(1).toString() },
+      { attachedDeposit: '1' },
+    ),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
@@ -332,6 +360,157 @@ test('storage_withdraw() is correct', async (t) => {
   )
 })
 
+test('NEP-145 storage management is correct', async (t) => {
+  const { bridge, user, relayer } = t.context.accounts
+
+  t.deepEqual(await bridge.view('storage_balance_bounds'), {
+    min: payForRegister.toString(),
+    max: null,
+  })
+  t.is(
+    await bridge.view('storage_balance_of', { account_id: relayer.accountId }),
+    null,
+  )
+
+  // deposit on behalf of another account
+  await user.call(
+    bridge,
+    'storage_deposit',
+    { account_id: relayer.accountId },
+    { attachedDeposit: payForRegister.add(ONE_NEAR) },
+  )
+  t.deepEqual(
+    await bridge.view('storage_balance_of', { account_id: relayer.accountId }),
+    {
+      total: payForRegister.add(ONE_NEAR).toString(),
+      available: ONE_NEAR.toString(),
+    },
+  )
+  t.is(
+    await bridge.view('storage_balance_of', { account_id: user.accountId }),
+    null,
+  )
+
+  // registration only refunds everything above the minimum
+  const userBalanceBefore = await (await user.balance()).available
+  await user.call(
+    bridge,
+    'storage_deposit',
+    { registration_only: true },
+    { attachedDeposit: payForRegister.add(ONE_NEAR) },
+  )
+  t.deepEqual(
+    await bridge.view('storage_balance_of', { account_id: user.accountId }),
+    {
+      total: payForRegister.toString(),
+      available: '0',
+    },
+  )
+  t.true(
+    userBalanceBefore
+      .sub(await (await user.balance()).available)
+      .sub(payForRegister)
+      .lt(DELTA),
+  )
+
+  // withdraw of all available storage without amount
+  await relayer.call(
+    bridge,
+    'storage_withdraw',
+    {},
+    { attachedDeposit: '1' },
+  )
+  t.deepEqual(
+    await bridge.view('storage_balance_of', { account_id: relayer.accountId }),
+    {
+      total: payForRegister.toString(),
+      available: '0',
+    },
+  )
+
+  const error = await t.throwsAsync(
+    relayer.call(bridge, 'storage_withdraw', {}),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Requires attached deposit of exactly 1 yoctoNEAR',
+  )
+
+  t.true(
+    await relayer.call(
+      bridge,
+      'storage_unregister',
+      {},
+      { attachedDeposit: '1' },
+    ),
+  )
+  t.is(
+    await bridge.view('storage_balance_of', { account_id: relayer.accountId }),
+    null,
+  )
+  t.false(
+    await relayer.call(
+      bridge,
+      'storage_unregister',
+      {},
+      { attachedDeposit: '1' },
+    ),
+  )
+})
+
+test('storage_unregister() with data needs force', async (t) => {
+  const { bridge, token, user } = t.context.accounts
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+  await user.call(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: TRANSFER_AMOUNT.toString(),
+      msg: ETH_ADDR + CHAIN,
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+
+  for (const args of [{}, { force: false }]) {
+    const error = await t.throwsAsync(
+      user.call(bridge, 'storage_unregister', args, { attachedDeposit: '1' }),
+    )
+    t.is(
+      panicMessageFromThrowsAsync(error),
+      'Smart contract panicked: Account still has data, unregister it with force to remove it',
+    )
+  }
+
+  const result = await user.callRaw(
+    bridge,
+    'storage_unregister',
+    { force: true },
+    { attachedDeposit: '1' },
+  )
+  t.true(
+    result.logs.includes(
+      `Data of ${user.accountId} is removed by forced unregister`,
+    ),
+  )
+  t.is(
+    await bridge.view('storage_balance_of', { account_id: user.accountId }),
+    null,
+  )
+  t.is(
+    await bridge.view('get_transactions_amount_by_user', {
+      user: user.accountId,
+    }),
+    '0',
+  )
+  // the record itself stays for its status
+  t.is(
+    ((await bridge.view('get_transaction_by_nonce', { nonce: '0' })) as any)
+      .status,
+    'pending',
+  )
+})
+
 /*
  ----------------------
  Ft_on_transfer() tests
@@ -341,7 +520,13 @@ test('storage_withdraw() is correct', async (t) => {
 const TRANSFER_AMOUNT = new BN(10).mul(new BN(10).pow(new BN(18)))
 const ETH_ADDR = '0x3Ba6810768c2F4FD3Be2c5508E214E68B514B35f'
 const CHAIN = 'BSC'
+const NEAR_CHAIN = 'NEAR'
 const GAS_REQUIRED = new BN(80).mul(new BN(10).pow(new BN(12)))
+const GAS_REQUIRED_WITH_REGISTRATION = new BN(120).mul(
+  new BN(10).pow(new BN(12)),
+)
+const GAS_REQUIRED_WITH_CALL = new BN(200).mul(new BN(10).pow(new BN(12)))
+const MAX_GAS = new BN(300).mul(new BN(10).pow(new BN(12)))
 
 test('ft_on_transfer() expected panic', async (t) => {
   const { bridge, user, token, wrongToken } = t.context.accounts
@@ -501,13 +686,14 @@ test('ft_on_transfer() success', async (t) => {
       .sub(bridgeNativeBalanceBefore.sub(payForFtOnTransfer))
       .lt(DELTA),
   )
-  t.true(
-    new BN(
-      ((await bridge.view('get_storage_paid_info', {
-        user: user.accountId,
-      })) as any)[5],
-    ).eq(totalStoragePaidBefore.sub(payForFtOnTransfer)),
-  )
+  // only the storage really taken is charged, it's not more than the estimate
+  const storageInfo = (await bridge.view('get_storage_paid_info', {
+    user: user.accountId,
+  })) as any
+  const charged = totalStoragePaidBefore.sub(new BN(storageInfo[5]))
+  t.true(charged.gt(new BN(0)))
+  t.true(charged.lte(payForFtOnTransfer))
+  t.is(storageInfo[1], payForFtOnTransfer.sub(charged).toString())
   t.is(
     tx.logs[1],
     `Sent ${TRANSFER_AMOUNT.toString()} tokens from ${
@@ -535,14 +721,10 @@ test('ft_on_transfer() success', async (t) => {
   t.is(txData.from_chain, 'NEAR')
   t.is(txData.to_chain, CHAIN)
 
-  t.is(
-    ((await bridge.view('get_storage_paid_info', {
-      user: user.accountId,
-    })) as any)[1],
-    '0',
-  )
   t.is(await bridge.view('get_nonce'), '1')
 
+  await user.call(bridge, 'storage_withdraw', {}, { attachedDeposit: '1' })
+
   const tx2 = await user.callRaw(
     token,
     'ft_transfer_call',
@@ -589,19 +771,14 @@ test('ft_on_transfer() success', async (t) => {
   t.is(txData3.nonce, '1')
   t.is(txData3.from_chain, 'NEAR')
   t.is(txData3.to_chain, CHAIN)
-  t.is(
-    ((await bridge.view('get_storage_paid_info', {
-      user: user.accountId,
-    })) as any)[1],
-    '0',
-  )
-  t.true(
-    new BN(
-      ((await bridge.view('get_storage_paid_info', {
-        user: user.accountId,
-      })) as any)[5],
-    ).eq(totalStoragePaidBefore3.sub(payForFtOnTransfer)),
-  )
+  // list of the user already exists, so the second send is cheaper
+  const storageInfo3 = (await bridge.view('get_storage_paid_info', {
+    user: user.accountId,
+  })) as any
+  const charged3 = totalStoragePaidBefore3.sub(new BN(storageInfo3[5]))
+  t.true(charged3.gt(new BN(0)))
+  t.true(charged3.lt(charged))
+  t.is(storageInfo3[1], payForFtOnTransfer.sub(charged3).toString())
   t.is(await bridge.view('get_nonce'), '2')
   t.is(
     (await token.view('ft_balance_of', {
@@ -609,122 +786,518 @@ test('ft_on_transfer() success', async (t) => {
     })) as string,
     new BN(bbBridge3).add(LIMIT_PER_SEND).toString(),
   )
-})
 
-/*
- ----------------
- Fullfill() tests
- ----------------
-*/
+  const sentVolume = TRANSFER_AMOUNT.add(LIMIT_PER_SEND).toString()
+  t.deepEqual(await bridge.view('get_chain_stats', { chain: CHAIN }), {
+    sent_volume: sentVolume,
+    sent_count: '2',
+    received_volume: '0',
+    received_count: '0',
+  })
+  t.deepEqual(await bridge.view('get_user_volume', { user: user.accountId }), {
+    sent: sentVolume,
+    received: '0',
+  })
+  t.deepEqual(await bridge.view('get_bridge_stats'), {
+    outbound_count: '2',
+    inbound_count: '0',
+    total_fees_collected: '0',
+  })
+})
 
-const NEAR_CHAIN = 'NEAR'
+test('ft_on_transfer() rejects repeated send with the same idempotency key', async (t) => {
+  const { bridge, user, token } = t.context.accounts
 
-test('fullfill() expected panic', async (t) => {
-  const { owner, token, bridge, user, relayer } = t.context.accounts
-  await owner.callRaw(
-    token,
-    'ft_transfer',
-    {
-      receiver_id: bridge.accountId,
-      amount: INITIAL_BRIDGE_BALANCE.toString(),
-    },
-    { attachedDeposit: '1' },
+  await storageDeposit(
+    user,
+    bridge,
+    payForRegister.add(payForFtOnTransfer.mul(new BN(2))),
   )
-
-  let error = await t.throwsAsync(
-    user.call(
-      bridge,
-      'fulfill',
+  const send = (idempotency_key: string) =>
+    user.callRaw(
+      token,
+      'ft_transfer_call',
       {
-        transaction: {
-          from_user: ETH_ADDR,
-          to_user: ETH_ADDR,
-          amount: new BN(10).toString(),
-          timestamp: new BN(666).toString(),
-          from_chain: CHAIN,
-          to_chain: NEAR_CHAIN,
-          nonce: '0',
-        },
-        signature: Array.from(new Uint8Array(Buffer.from('asd'))),
+        receiver_id: bridge.accountId,
+        amount: TRANSFER_AMOUNT.toString(),
+        msg: JSON.stringify({
+          recipient: ETH_ADDR,
+          chain: CHAIN,
+          idempotency_key,
+        }),
       },
-      { attachedDeposit: '1' },
-    ),
-  )
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    )
+  const bbUser = (await token.view('ft_balance_of', {
+    account_id: user.accountId,
+  })) as string
+
+  let tx = await send('retry-key')
   t.is(
-    panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Not enough gas prepaid, at least 80 Tgas is needed',
+    tx.logs[1],
+    `Sent ${TRANSFER_AMOUNT.toString()} tokens from ${
+      user.accountId
+    } to ${ETH_ADDR} in direction NEAR->BSC`,
   )
 
-  error = await t.throwsAsync(
-    user.call(
-      bridge,
-      'fulfill',
-      {
-        transaction: {
-          from_user: ETH_ADDR,
-          to_user: ETH_ADDR,
-          amount: new BN(10).toString(),
-          timestamp: new BN(666).toString(),
-          from_chain: CHAIN,
-          to_chain: NEAR_CHAIN,
-          nonce: '0',
-        },
-        signature: Array.from(new Uint8Array(Buffer.from('asd'))),
-      },
-      { attachedDeposit: '1', gas: GAS_REQUIRED },
+  // retry with the same key returns tokens and references the first send
+  tx = await send('retry-key')
+  t.true(
+    tx.logs.includes(
+      `Send with idempotency key retry-key of ${
+        user.accountId
+      } is already done with nonce 0, ${TRANSFER_AMOUNT.toString()} tokens are returned`,
     ),
   )
+  t.is(await bridge.view('get_nonce'), '1')
   t.is(
-    panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Not convertible transaction.to field to AccountId type',
+    await token.view('ft_balance_of', { account_id: user.accountId }),
+    new BN(bbUser).sub(TRANSFER_AMOUNT).toString(),
   )
 
-  error = await t.throwsAsync(
-    user.call(
-      bridge,
-      'fulfill',
+  await send('another-key')
+  t.is(await bridge.view('get_nonce'), '2')
+  t.is(
+    await token.view('ft_balance_of', { account_id: user.accountId }),
+    new BN(bbUser).sub(TRANSFER_AMOUNT.mul(new BN(2))).toString(),
+  )
+})
+
+test('ft_on_transfer() records payload', async (t) => {
+  const { bridge, user, token } = t.context.accounts
+
+  await storageDeposit(
+    user,
+    bridge,
+    payForRegister.add(payForFtOnTransfer.mul(new BN(2))),
+  )
+  const send = (payload?: string) =>
+    user.callRaw(
+      token,
+      'ft_transfer_call',
       {
-        transaction: {
-          from_user: ETH_ADDR,
-          to_user: user.accountId,
-          amount: new BN(10).toString(),
-          timestamp: new BN(666).toString(),
-          from_chain: CHAIN,
-          to_chain: 'AVAX',
-          nonce: '0',
-        },
-        signature: Array.from(new Uint8Array(Buffer.from('asd'))),
+        receiver_id: bridge.accountId,
+        amount: TRANSFER_AMOUNT.toString(),
+        msg: JSON.stringify({ recipient: ETH_ADDR, chain: CHAIN, payload }),
       },
       { attachedDeposit: '1', gas: GAS_REQUIRED },
-    ),
+    )
+
+  let tx = await send('deadbeef')
+  t.is(
+    tx.logs[1],
+    'PANIC: Payload should be 0x-prefixed hex of at most 1024 bytes',
+  )
+  tx = await send('0x' + 'ab'.repeat(1025))
+  t.is(
+    tx.logs[1],
+    'PANIC: Payload should be 0x-prefixed hex of at most 1024 bytes',
+  )
+
+  tx = await send('0xdeadbeef')
+  t.true(tx.logs.includes('Sent payload 0xdeadbeef with nonce 0'))
+  await send()
+
+  const records = (await bridge.view('get_transactions_in_nonce_range', {
+    from: '0',
+    to: '2',
+  })) as any[]
+  t.is(records[0].payload, '0xdeadbeef')
+  t.false('payload' in records[1])
+})
+
+test('outbound transactions are indexed by nonce', async (t) => {
+  const { bridge, user, token } = t.context.accounts
+
+  t.is(await bridge.view('get_latest_nonce'), null)
+  t.is(await bridge.view('get_transaction_by_nonce', { nonce: '0' }), null)
+
+  await storageDeposit(
+    user,
+    bridge,
+    payForRegister.add(payForFtOnTransfer.mul(new BN(2))),
+  )
+  for (const amount of ['100', '200']) {
+    await user.callRaw(
+      token,
+      'ft_transfer_call',
+      {
+        receiver_id: bridge.accountId,
+        amount,
+        msg: ETH_ADDR + CHAIN,
+      },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    )
+  }
+
+  t.is(await bridge.view('get_latest_nonce'), '1')
+  const txData = (await bridge.view('get_transaction_by_nonce', {
+    nonce: '1',
+  })) as any
+  t.is(txData.from_user, user.accountId)
+  t.is(txData.amount, '200')
+  t.is(txData.nonce, '1')
+
+  const txs = (await bridge.view('get_transactions_in_nonce_range', {
+    from: '0',
+    to: '10',
+  })) as any
+  t.deepEqual(
+    txs.map((tx: any) => tx.nonce),
+    ['0', '1'],
+  )
+
+  const error = await t.throwsAsync(
+    user.call(bridge, 'get_transactions_in_nonce_range', {
+      from: '0',
+      to: '1000',
+    }),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    "Smart contract panicked: Wrong 'toChain' in tx struct",
+    'Smart contract panicked: Nonce range is too wide',
+  )
+})
+
+test('paginated and filtered transaction views are correct', async (t) => {
+  const { bridge, user, token } = t.context.accounts
+
+  await storageDeposit(
+    user,
+    bridge,
+    payForRegister.add(payForFtOnTransfer.mul(new BN(3))),
+  )
+  for (const amount of ['100', '200', '300']) {
+    await user.callRaw(
+      token,
+      'ft_transfer_call',
+      {
+        receiver_id: bridge.accountId,
+        amount,
+        msg: ETH_ADDR + CHAIN,
+      },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    )
+  }
+
+  // unpaginated view reads the first page
+  t.deepEqual(
+    await bridge.view('get_transactions_by_user', { user: user.accountId }),
+    await bridge.view('get_transactions_by_user_paginated', {
+      user: user.accountId,
+    }),
+  )
+
+  let txs = (await bridge.view('get_transactions_by_user_paginated', {
+    user: user.accountId,
+    from_index: '1',
+    limit: '5',
+  })) as any
+  t.deepEqual(
+    txs.map((tx: any) => tx.amount),
+    ['200', '300'],
+  )
+
+  txs = (await bridge.view('get_transactions_by_user_reversed', {
+    user: user.accountId,
+    from_index: '0',
+    limit: '2',
+  })) as any
+  t.deepEqual(
+    txs.map((tx: any) => tx.amount),
+    ['300', '200'],
+  )
+
+  let page = (await bridge.view('get_filtered_transactions_by_user', {
+    user: user.accountId,
+    filter: { to_chain: CHAIN, min_amount: '150' },
+    reverse: true,
+  })) as any
+  t.deepEqual(
+    page.records.map((tx: any) => tx.amount),
+    ['300', '200'],
+  )
+  t.is(page.next_index, null)
+
+  // from_index counts all transactions, next_index continues after the last scanned one
+  page = (await bridge.view('get_filtered_transactions_by_user', {
+    user: user.accountId,
+    filter: { min_amount: '150' },
+    limit: '1',
+  })) as any
+  t.deepEqual(
+    page.records.map((tx: any) => tx.amount),
+    ['200'],
+  )
+  t.is(page.next_index, '2')
+  page = (await bridge.view('get_filtered_transactions_by_user', {
+    user: user.accountId,
+    filter: { min_amount: '150' },
+    from_index: page.next_index,
+    limit: '1',
+  })) as any
+  t.deepEqual(
+    page.records.map((tx: any) => tx.amount),
+    ['300'],
+  )
+  t.is(page.next_index, null)
+
+  page = (await bridge.view('get_filtered_transactions_by_user', {
+    user: user.accountId,
+    filter: { max_amount: '250', from_chain: NEAR_CHAIN },
+    from_index: '1',
+  })) as any
+  t.deepEqual(
+    page.records.map((tx: any) => tx.amount),
+    ['200'],
+  )
+
+  page = (await bridge.view('get_filtered_transactions_by_user', {
+    user: user.accountId,
+    filter: { to_chain: 'AVAX' },
+  })) as any
+  t.deepEqual(page, { records: [], next_index: null })
+})
+
+test('prune_transactions() is correct', async (t) => {
+  const { owner, bridge, user, token } = t.context.accounts
+
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+  await user.callRaw(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: TRANSFER_AMOUNT.toString(),
+      msg: ETH_ADDR + CHAIN,
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+
+  // transaction is within default retention period
+  t.deepEqual(
+    await bridge.view('get_reclaimable_storage', {
+      user: user.accountId,
+      direction: 'outbound',
+    }),
+    ['0', '0'],
+  )
+  let error = await t.throwsAsync(
+    user.call(bridge, 'prune_transactions', {
+      user: user.accountId,
+      direction: 'outbound',
+      limit: '10',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: No transactions to prune',
+  )
+
+  error = await t.throwsAsync(
+    user.call(bridge, 'set_retention_period', { retention_period: '0' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+  await owner.call(bridge, 'set_retention_period', { retention_period: '0' })
+  t.is(await bridge.view('get_retention_period'), '0')
+
+  // pending transactions are kept until their status is set
+  t.deepEqual(
+    await bridge.view('get_reclaimable_storage', {
+      user: user.accountId,
+      direction: 'outbound',
+    }),
+    ['0', '0'],
+  )
+  await owner.call(bridge, 'set_outbound_status', {
+    nonce: '0',
+    status: 'completed',
+  })
+
+  const reclaimable = (await bridge.view('get_reclaimable_storage', {
+    user: user.accountId,
+    direction: 'outbound',
+  })) as any
+  t.is(reclaimable[0], '1')
+  t.true(new BN(reclaimable[1]).gt(new BN(0)))
+  t.true(new BN(reclaimable[1]).lte(payForFtOnTransfer))
+
+  // anyone can prune, storage is refunded to the sender
+  const tx = await owner.callRaw(bridge, 'prune_transactions', {
+    user: user.accountId,
+    direction: 'outbound',
+    limit: '10',
+  })
+  t.is(
+    tx.logs[0],
+    `Pruned 1 transactions of ${user.accountId}, ${reclaimable[1]} yoctoNEAR of storage refunded`,
+  )
+  t.is(
+    ((await bridge.view('get_storage_paid_info', {
+      user: user.accountId,
+    })) as any)[1],
+    reclaimable[1],
+  )
+  t.deepEqual(
+    await bridge.view('get_transactions_by_user', { user: user.accountId }),
+    [],
+  )
+  t.is(await bridge.view('get_transaction_by_nonce', { nonce: '0' }), null)
+})
+
+test('outbound transactions are looked up by hash and status', async (t) => {
+  const { owner, bridge, user, token } = t.context.accounts
+
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+  const balanceBefore = BigInt(
+    (await token.view('ft_balance_of', {
+      account_id: user.accountId,
+    })) as string,
+  )
+  await user.callRaw(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: TRANSFER_AMOUNT.toString(),
+      msg: ETH_ADDR + CHAIN,
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+
+  const txData = (await bridge.view('get_transaction_by_nonce', {
+    nonce: '0',
+  })) as any
+  t.is(txData.status, 'pending')
+  const { status, ...tx } = txData
+  const hexHash = Buffer.from(
+    (await bridge.view('get_tx_hash', { transaction: tx })) as Uint8Array,
+  ).toString('hex')
+  t.deepEqual(
+    await bridge.view('get_outbound_transaction_by_hash', { tx_hash: hexHash }),
+    txData,
+  )
+  t.is(
+    await bridge.view('get_inbound_transaction_by_hash', { tx_hash: hexHash }),
+    null,
+  )
+
+  let error = await t.throwsAsync(
+    user.call(bridge, 'set_outbound_status', {
+      nonce: '0',
+      status: 'refunded',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_outbound_status', {
+      nonce: '1',
+      status: 'refunded',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: No outbound transaction with this nonce',
+  )
+
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_outbound_status', {
+      nonce: '0',
+      status: 'refunded',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough gas prepaid, at least 50 Tgas is needed',
+  )
+
+  await owner.call(
+    bridge,
+    'set_outbound_status',
+    { nonce: '0', status: 'refunded' },
+    { gas: GAS_REQUIRED },
+  )
+  t.is(
+    BigInt(
+      (await token.view('ft_balance_of', {
+        account_id: user.accountId,
+      })) as string,
+    ),
+    balanceBefore,
+  )
+  t.is(((await bridge.view('get_liabilities')) as any).bridged_out, '0')
+  t.is(
+    ((await bridge.view('get_outbound_transaction_by_hash', {
+      tx_hash: hexHash,
+    })) as any).status,
+    'refunded',
+  )
+  t.deepEqual(
+    await bridge.view('get_filtered_transactions_by_user', {
+      user: user.accountId,
+      filter: { status: 'pending' },
+    }),
+    { records: [], next_index: null },
   )
 
   error = await t.throwsAsync(
+    owner.call(bridge, 'set_outbound_status', {
+      nonce: '0',
+      status: 'completed',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Outbound transaction is already finished',
+  )
+})
+
+/*
+ ----------------
+ Fullfill() tests
+ ----------------
+*/
+
+test('fullfill() expected panic', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+
+  let error = await t.throwsAsync(
     user.call(
       bridge,
       'fulfill',
       {
         transaction: {
           from_user: ETH_ADDR,
-          to_user: user.accountId,
+          to_user: ETH_ADDR,
           amount: new BN(10).toString(),
           timestamp: new BN(666).toString(),
-          from_chain: NEAR_CHAIN,
+          from_chain: CHAIN,
           to_chain: NEAR_CHAIN,
           nonce: '0',
         },
         signature: Array.from(new Uint8Array(Buffer.from('asd'))),
       },
-      { attachedDeposit: '1', gas: GAS_REQUIRED },
+      { attachedDeposit: '1' },
     ),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Not supported fromChain in tx struct',
+    'Smart contract panicked: Not enough gas prepaid, at least 80 Tgas is needed',
   )
 
   error = await t.throwsAsync(
@@ -734,7 +1307,7 @@ test('fullfill() expected panic', async (t) => {
       {
         transaction: {
           from_user: ETH_ADDR,
-          to_user: user.accountId,
+          to_user: ETH_ADDR,
           amount: new BN(10).toString(),
           timestamp: new BN(666).toString(),
           from_chain: CHAIN,
@@ -748,15 +1321,9 @@ test('fullfill() expected panic', async (t) => {
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Not storage paid',
+    'Smart contract panicked: Not convertible transaction.to field to AccountId type',
   )
 
-  await user.call(
-    bridge,
-    'storage_deposit',
-    {},
-    { attachedDeposit: payForRegister },
-  )
   error = await t.throwsAsync(
     user.call(
       bridge,
@@ -768,7 +1335,7 @@ test('fullfill() expected panic', async (t) => {
           amount: new BN(10).toString(),
           timestamp: new BN(666).toString(),
           from_chain: CHAIN,
-          to_chain: NEAR_CHAIN,
+          to_chain: 'AVAX',
           nonce: '0',
         },
         signature: Array.from(new Uint8Array(Buffer.from('asd'))),
@@ -778,21 +1345,94 @@ test('fullfill() expected panic', async (t) => {
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Not enough storage paid',
+    "Smart contract panicked: Wrong 'toChain' in tx struct",
   )
-})
 
-test('fullfill() promise ft_transfer to user is failed', async (t) => {
-  const { owner, token, bridge, user, relayer } = t.context.accounts
-  await owner.callRaw(
-    token,
-    'ft_transfer',
-    {
-      receiver_id: bridge.accountId,
-      amount: INITIAL_BRIDGE_BALANCE.toString(),
-    },
-    { attachedDeposit: '1' },
-  )
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'fulfill',
+      {
+        transaction: {
+          from_user: ETH_ADDR,
+          to_user: user.accountId,
+          amount: new BN(10).toString(),
+          timestamp: new BN(666).toString(),
+          from_chain: NEAR_CHAIN,
+          to_chain: NEAR_CHAIN,
+          nonce: '0',
+        },
+        signature: Array.from(new Uint8Array(Buffer.from('asd'))),
+      },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not supported fromChain in tx struct',
+  )
+
+  // storage is charged after the signature is verified
+  const signedTx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: new BN(10).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const signedTxHash = (await bridge.view('get_tx_hash', {
+    transaction: signedTx,
+  })) as Uint8Array
+  const validSignature = Array.from(
+    (await relayer.getKey())?.sign(Uint8Array.from(signedTxHash))
+      ?.signature as Uint8Array,
+  )
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'fulfill',
+      { transaction: signedTx, signature: validSignature },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not storage paid',
+  )
+
+  await user.call(
+    bridge,
+    'storage_deposit',
+    {},
+    { attachedDeposit: payForRegister },
+  )
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'fulfill',
+      { transaction: signedTx, signature: validSignature },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough storage paid',
+  )
+})
+
+test('fullfill() promise ft_transfer to user is failed', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
   await relayer.call(
     bridge,
     'storage_deposit',
@@ -848,24 +1488,45 @@ test('fullfill() promise ft_transfer to user is failed', async (t) => {
     .join('')
 
   t.false(await bridge.view('is_tx_fulfilled', { tx_hash: hexHash })) // hash is not fulfilled
-  const txContractData = (await bridge.view('get_transactions_by_user', {
-    user: tx.from_user,
+  const txContractData = (await bridge.view('get_inbound_transaction_by_hash', {
+    tx_hash: hexHash,
   })) as any
-  t.is(txContractData.length, 0) // transaction is not pushed in the list
-
+  t.is(txContractData.status, 'rolled_back') // transaction is kept as rolled back
+  const fulfillInfo = (await bridge.view('get_fulfill_info', {
+    tx_hash: hexHash,
+  })) as any
+  t.is(fulfillInfo.status, 'rolled_back')
+  t.is(fulfillInfo.recipient, tx.to_user)
+  t.is(fulfillInfo.dispensed_amount, '0')
+  t.is(fulfillInfo.failure_reason, 'transfer_failed')
   t.is(
+    await bridge.view('get_inbound_transactions_amount_by_user', {
+      user: tx.to_user,
+    }),
+    '1',
+  )
+
+  // only storage of the kept record isn't returned
+  const userStoragePaidAfter = new BN(
     ((await bridge.view('get_storage_paid_info', {
       user: tx.to_user,
     })) as any)[1],
-    userStoragePaidBefore[1],
-  ) // storage paid is equal previous state
+  )
+  t.true(userStoragePaidAfter.lt(new BN(userStoragePaidBefore[1])))
+  t.true(
+    userStoragePaidAfter.gt(new BN(userStoragePaidBefore[1]).sub(payForFulfill)),
+  )
   t.true(
     new BN(
       ((await bridge.view('get_storage_paid_info', {
         user: user.accountId,
       })) as any)[5],
-    ).eq(totalStoragePaidBefore),
-  ) // total storage paid is equal previous state
+    ).eq(
+      totalStoragePaidBefore.sub(
+        new BN(userStoragePaidBefore[1]).sub(userStoragePaidAfter),
+      ),
+    ),
+  ) // total storage paid is decreased by the same amount
   t.is(
     await token.view('ft_balance_of', { account_id: owner.accountId }),
     feeWalletBalanceBefore,
@@ -945,24 +1606,43 @@ test("fullfill() fails as fee wallet didn't call storage_deposit() on token", as
     .join('')
 
   t.false(await bridge.view('is_tx_fulfilled', { tx_hash: hexHash })) // hash is not fulfilled
-  const txContractData = (await bridge.view('get_transactions_by_user', {
-    user: tx.from_user,
+  const txContractData = (await bridge.view('get_inbound_transaction_by_hash', {
+    tx_hash: hexHash,
   })) as any
-  t.is(txContractData.length, 0) // transaction is not pushed in the list
-
+  t.is(txContractData.status, 'rolled_back') // transaction is kept as rolled back
+  const fulfillInfo = (await bridge.view('get_fulfill_info', {
+    tx_hash: hexHash,
+  })) as any
+  t.is(fulfillInfo.status, 'rolled_back')
+  t.is(fulfillInfo.failure_reason, 'fee_wallet_not_registered')
   t.is(
+    await bridge.view('get_inbound_transactions_amount_by_user', {
+      user: tx.to_user,
+    }),
+    '1',
+  )
+
+  // only storage of the kept record isn't returned
+  const userStoragePaidAfter = new BN(
     ((await bridge.view('get_storage_paid_info', {
       user: tx.to_user,
     })) as any)[1],
-    userStoragePaidBefore[1],
-  ) // storage paid is equal previous state
+  )
+  t.true(userStoragePaidAfter.lt(new BN(userStoragePaidBefore[1])))
+  t.true(
+    userStoragePaidAfter.gt(new BN(userStoragePaidBefore[1]).sub(payForFulfill)),
+  )
   t.true(
     new BN(
       ((await bridge.view('get_storage_paid_info', {
         user: user.accountId,
       })) as any)[5],
-    ).eq(totalStoragePaidBefore),
-  ) // total storage paid is equal previous state
+    ).eq(
+      totalStoragePaidBefore.sub(
+        new BN(userStoragePaidBefore[1]).sub(userStoragePaidAfter),
+      ),
+    ),
+  ) // total storage paid is decreased by the same amount
   t.is(
     await token.view('ft_balance_of', { account_id: owner.accountId }),
     feeWalletBalanceBefore,
@@ -1036,9 +1716,11 @@ test('fulfill() success', async (t) => {
     .join('')
 
   t.true(await bridge.view('is_tx_fulfilled', { tx_hash: hexHash }))
-  const txContractData1 = ((await bridge.view('get_transactions_by_user', {
-    user: tx.from_user,
-  })) as any)[0]
+  const txContractData1 = ((await bridge.view(
+    'get_inbound_transactions_by_user',
+    { user: tx.to_user },
+  )) as any)[0]
+  t.is(txContractData1.status, 'completed')
   t.is(txContractData1.from_user, tx.from_user)
   t.is(txContractData1.to_user, tx.to_user)
   t.is(txContractData1.amount, tx.amount)
@@ -1055,13 +1737,15 @@ test('fulfill() success', async (t) => {
     await token.view('ft_balance_of', { account_id: owner.accountId }),
     feeWalletBBDispense,
   )
-  t.true(
+  const chargedForFulfill = totalStoragePaidBefore.sub(
     new BN(
       ((await bridge.view('get_storage_paid_info', {
         user: user.accountId,
       })) as any)[5],
-    ).eq(totalStoragePaidBefore.sub(payForFulfill)),
-  ) // total storage paid decreased
+    ),
+  )
+  t.true(chargedForFulfill.gt(new BN(0)))
+  t.true(chargedForFulfill.lte(payForFulfill)) // total storage paid decreased
 
   // FULFILL WITH FEE DISPENSE
   const totalAmount = new BN(100)
@@ -1117,9 +1801,10 @@ test('fulfill() success', async (t) => {
     .join('')
 
   t.true(await bridge.view('is_tx_fulfilled', { tx_hash: hexHash2 }))
-  const txContractData2 = ((await bridge.view('get_transactions_by_user', {
-    user: tx.from_user,
-  })) as any)[1]
+  const txContractData2 = ((await bridge.view(
+    'get_inbound_transactions_by_user',
+    { user: tx.to_user },
+  )) as any)[1]
   t.is(txContractData2.from_user, tx2.from_user)
   t.is(txContractData2.to_user, tx2.to_user)
   t.is(txContractData2.amount, tx2.amount)
@@ -1128,18 +1813,19 @@ test('fulfill() success', async (t) => {
   t.is(txContractData2.to_chain, tx2.to_chain)
   t.is(txContractData2.nonce, tx2.nonce)
 
+  // volume entry of the user already exists, so the second fulfill is cheaper
   t.true(
     new BN(
       ((await bridge.view('get_storage_paid_info', {
         user: user.accountId,
       })) as any)[5],
-    ).eq(totalStoragePaidBefore2.sub(payForFulfill)),
+    ).gt(totalStoragePaidBefore2.sub(chargedForFulfill)),
   ) // total storage paid decreased
 
-  const exactTxContractData = (await bridge.view('get_transaction_by_user', {
-    user: tx.from_user,
-    index: '1',
-  })) as any
+  const exactTxContractData = (await bridge.view(
+    'get_inbound_transaction_by_user',
+    { user: tx.to_user, index: '1' },
+  )) as any
   t.is(exactTxContractData.from_user, tx2.from_user)
   t.is(exactTxContractData.to_user, tx2.to_user)
   t.is(exactTxContractData.amount, tx2.amount)
@@ -1149,8 +1835,8 @@ test('fulfill() success', async (t) => {
   t.is(exactTxContractData.nonce, tx2.nonce)
 
   const error = await t.throwsAsync(
-    user.call(bridge, 'get_transaction_by_user', {
-      user: tx.from_user,
+    user.call(bridge, 'get_inbound_transaction_by_user', {
+      user: tx.to_user,
       index: '2',
     }),
   )
@@ -1160,6 +1846,19 @@ test('fulfill() success', async (t) => {
   )
 
   const fee = new BN(tx2.amount).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  const fulfillInfo = (await bridge.view('get_fulfill_info', {
+    tx_hash: hexHash2,
+  })) as any
+  t.is(fulfillInfo.status, 'completed')
+  t.is(fulfillInfo.recipient, tx2.to_user)
+  t.is(fulfillInfo.fee, fee.toString())
+  t.is(fulfillInfo.dispensed_amount, new BN(tx2.amount).sub(fee).toString())
+  t.is(fulfillInfo.failure_reason, null)
+  t.true(Number(fulfillInfo.fulfilled_at) > 0)
+  t.is(
+    await bridge.view('get_fulfill_info', { tx_hash: '00'.repeat(32) }),
+    null,
+  )
   t.is(
     await token.view('ft_balance_of', { account_id: tx2.to_user }),
     new BN(userBalanceBeforeDispense as any)
@@ -1174,171 +1873,3015 @@ test('fulfill() success', async (t) => {
     await token.view('ft_balance_of', { account_id: owner.accountId }),
     new BN(feeWalletBalanceBeforeDispense as any).add(fee).toString(),
   )
-
+
+  t.is(
+    await bridge.view('get_inbound_transactions_amount_by_user', {
+      user: tx.to_user,
+    }),
+    '2',
+  )
+  t.is(
+    await bridge.view('get_transactions_amount_by_user', {
+      user: tx.to_user,
+    }),
+    '0',
+  ) // inbound transactions aren't mixed with outbound ones
+
+  const receivedVolume = new BN(tx.amount).add(new BN(tx2.amount)).toString()
+  t.deepEqual(await bridge.view('get_chain_stats', { chain: CHAIN }), {
+    sent_volume: '0',
+    sent_count: '0',
+    received_volume: receivedVolume,
+    received_count: '2',
+  })
+  t.deepEqual(await bridge.view('get_user_volume', { user: tx.to_user }), {
+    sent: '0',
+    received: receivedVolume,
+  })
+  t.deepEqual(await bridge.view('get_bridge_stats'), {
+    outbound_count: '0',
+    inbound_count: '2',
+    total_fees_collected: fee.toString(),
+  })
+})
+
+test('fulfill() with storage paid by caller', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+
+  // user has never called storage_deposit() on bridge
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: new BN(100).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const hash_of_tx = (await bridge.view('get_tx_hash', {
+    transaction: tx,
+  })) as Uint8Array
+  const signature = (await relayer.getKey())?.sign(Uint8Array.from(hash_of_tx))
+
+  const error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill',
+      {
+        transaction: tx,
+        signature: Array.from(signature?.signature as Uint8Array),
+      },
+      { attachedDeposit: '2', gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough NEAR attached',
+  )
+
+  const userBalanceBefore = await token.view('ft_balance_of', {
+    account_id: user.accountId,
+  })
+  const totalStoragePaidBefore = new BN(
+    ((await bridge.view('get_storage_paid_info', {
+      user: user.accountId,
+    })) as any)[5],
+  )
+  const relayerBalanceBefore = await (await relayer.balance()).available
+
+  await relayer.call(
+    bridge,
+    'fulfill',
+    {
+      transaction: tx,
+      signature: Array.from(signature?.signature as Uint8Array),
+    },
+    { attachedDeposit: payForFulfill.add(ONE_NEAR), gas: GAS_REQUIRED },
+  )
+
+  const fee = new BN(tx.amount).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  t.is(
+    await token.view('ft_balance_of', { account_id: user.accountId }),
+    new BN(userBalanceBefore as any).add(new BN(tx.amount).sub(fee)).toString(),
+  )
+  const userStoragePaid = (await bridge.view('get_storage_paid_info', {
+    user: user.accountId,
+  })) as any
+  t.false(userStoragePaid[0]) // user is still not registered on bridge
+  t.true(new BN(userStoragePaid[5]).eq(totalStoragePaidBefore))
+
+  // excess of attached deposit and unused storage reserve are returned to relayer
+  t.true(
+    relayerBalanceBefore
+      .sub(await (await relayer.balance()).available)
+      .lt(payForFulfill.add(DELTA)),
+  )
+
+  // record storage is refunded to relayer when the record is pruned
+  const hexHash = Buffer.from(hash_of_tx).toString('hex')
+  const record = (await bridge.view('get_inbound_transaction_by_hash', {
+    tx_hash: hexHash,
+  })) as any
+  t.is(record.storage_payer, relayer.accountId)
+  await storageDeposit(relayer, bridge, payForRegister)
+  await owner.call(bridge, 'set_retention_period', { retention_period: '0' })
+  await owner.call(bridge, 'prune_transactions', {
+    user: user.accountId,
+    direction: 'inbound',
+    limit: '10',
+  })
+  const relayerStoragePaid = (await bridge.view('get_storage_paid_info', {
+    user: relayer.accountId,
+  })) as any
+  t.true(new BN(relayerStoragePaid[1]).gt(new BN(0)))
+  t.is(
+    await bridge.view('get_inbound_transaction_by_hash', { tx_hash: hexHash }),
+    null,
+  )
+})
+
+test('fulfill() registers recipient on token', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  const newcomer = await owner.createSubAccount('newcomer-account', {
+    initialBalance: NEAR.parse('1 N').toJSON(),
+  })
+  await newcomer.call(
+    bridge,
+    'storage_deposit',
+    {},
+    { attachedDeposit: payForRegister.add(payForFulfill) },
+  )
+
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: newcomer.accountId, // newcomer didn't call storage_deposit() on token
+    amount: new BN(100).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const hash_of_tx = (await bridge.view('get_tx_hash', {
+    transaction: tx,
+  })) as Uint8Array
+  const signature = (await relayer.getKey())?.sign(Uint8Array.from(hash_of_tx))
+  const fulfillArgs = {
+    transaction: tx,
+    signature: Array.from(signature?.signature as Uint8Array),
+    register_recipient: true,
+  }
+
+  let error = await t.throwsAsync(
+    user.call(bridge, 'fulfill', fulfillArgs, {
+      attachedDeposit: '1',
+      gas: GAS_REQUIRED,
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough gas prepaid, at least 120 Tgas is needed for recipient registration',
+  )
+
+  error = await t.throwsAsync(
+    user.call(bridge, 'fulfill', fulfillArgs, {
+      attachedDeposit: '1',
+      gas: GAS_REQUIRED_WITH_REGISTRATION,
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough NEAR in registration pool',
+  )
+
+  const payForRegistration = new BN(
+    await bridge.view('get_pay_for_registration'),
+  )
+  await owner.call(
+    bridge,
+    'fund_registration_pool',
+    {},
+    { attachedDeposit: payForRegistration },
+  )
+  t.is(
+    await bridge.view('get_registration_pool'),
+    payForRegistration.toString(),
+  )
+
+  const fulfillTx = await user.callRaw(bridge, 'fulfill', fulfillArgs, {
+    attachedDeposit: '1',
+    gas: GAS_REQUIRED_WITH_REGISTRATION,
+  })
+  t.true(
+    fulfillTx.logs.includes(
+      `Registered ${
+        newcomer.accountId
+      } on token for ${payForRegistration.toString()} yoctoNEAR`,
+    ),
+  )
+  t.is(await bridge.view('get_registration_pool'), '0')
+
+  const fee = new BN(tx.amount).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  t.is(
+    await token.view('ft_balance_of', { account_id: newcomer.accountId }),
+    new BN(tx.amount).sub(fee).toString(),
+  )
+})
+
+test('fulfill() registers and funds implicit recipient', async (t) => {
+  const { owner, token, bridge, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  // fresh wallet key that has no account yet
+  const implicitId = createHash('sha256').update('fresh-wallet').digest('hex')
+  const implicitAccount = owner.getAccount(implicitId)
+  t.false(await implicitAccount.exists())
+
+  let error = await t.throwsAsync(
+    relayer.call(bridge, 'set_implicit_account_funding', {
+      amount: NEAR.parse('0.01 N').toJSON(),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_implicit_account_funding', { amount: '1' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Implicit account funding should cover the account storage',
+  )
+  const funding = new BN(NEAR.parse('0.01 N').toJSON())
+  await owner.call(bridge, 'set_implicit_account_funding', {
+    amount: funding.toString(),
+  })
+  t.is(
+    await bridge.view('get_implicit_account_funding'),
+    funding.toString(),
+  )
+
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: implicitId,
+    amount: new BN(100).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const hash_of_tx = (await bridge.view('get_tx_hash', {
+    transaction: tx,
+  })) as Uint8Array
+  const signature = (await relayer.getKey())?.sign(Uint8Array.from(hash_of_tx))
+  const payForRegistration = new BN(
+    await bridge.view('get_pay_for_registration'),
+  )
+
+  const fulfillTx = await relayer.callRaw(
+    bridge,
+    'fulfill',
+    {
+      transaction: tx,
+      signature: Array.from(signature?.signature as Uint8Array),
+      register_recipient: true,
+    },
+    {
+      attachedDeposit: payForFulfill.add(payForRegistration).add(funding),
+      gas: GAS_REQUIRED_WITH_REGISTRATION,
+    },
+  )
+  t.true(
+    fulfillTx.logs.includes(
+      `Registered ${implicitId} on token for ${
+        payForRegistration.toString()
+      } yoctoNEAR`,
+    ),
+  )
+  t.true(
+    fulfillTx.logs.includes(
+      `Funded ${implicitId} with ${funding.toString()} yoctoNEAR`,
+    ),
+  )
+  t.true(await implicitAccount.exists())
+  t.true((await implicitAccount.balance()).total.gte(funding))
+
+  const fee = new BN(tx.amount).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  t.is(
+    await token.view('ft_balance_of', { account_id: implicitId }),
+    new BN(tx.amount).sub(fee).toString(),
+  )
+
+  // existing recipient isn't funded again
+  const nextTx = { ...tx, nonce: '1' }
+  const nextHash = (await bridge.view('get_tx_hash', {
+    transaction: nextTx,
+  })) as Uint8Array
+  const nextSignature = (await relayer.getKey())?.sign(
+    Uint8Array.from(nextHash),
+  )
+  const nextFulfillTx = await relayer.callRaw(
+    bridge,
+    'fulfill',
+    {
+      transaction: nextTx,
+      signature: Array.from(nextSignature?.signature as Uint8Array),
+      register_recipient: true,
+    },
+    {
+      attachedDeposit: payForFulfill.add(payForRegistration).add(funding),
+      gas: GAS_REQUIRED_WITH_REGISTRATION,
+    },
+  )
+  t.false(nextFulfillTx.logs.some((log) => log.startsWith('Funded')))
+  t.is(
+    await token.view('ft_balance_of', { account_id: implicitId }),
+    new BN(tx.amount).sub(fee).muln(2).toString(),
+  )
+})
+
+test('fulfill() delivers tokens with ft_transfer_call', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  // token contract has no ft_on_transfer, so it returns all tokens as unused
+  await owner.call(
+    token,
+    'storage_deposit',
+    { account_id: token.accountId },
+    { attachedDeposit: await bridge.view('get_pay_for_registration') },
+  )
+
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: token.accountId,
+    amount: new BN(100).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const msg = 'swap'
+  const hash_of_tx = (await bridge.view('get_tx_hash_with_msg', {
+    transaction: tx,
+    msg,
+  })) as Uint8Array
+  // records are keyed by the hash of the transaction alone
+  const hexHash = Buffer.from(
+    (await bridge.view('get_tx_hash', { transaction: tx })) as Uint8Array,
+  ).toString('hex')
+  const signature = Array.from(
+    (await relayer.getKey())?.sign(Uint8Array.from(hash_of_tx))
+      ?.signature as Uint8Array,
+  )
+  const attachedDeposit = payForFulfill.add(ONE_NEAR)
+
+  // msg is signed together with the transaction
+  let error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill',
+      { transaction: tx, signature },
+      { attachedDeposit, gas: GAS_REQUIRED_WITH_CALL },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong signature',
+  )
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill',
+      { transaction: tx, signature, msg },
+      { attachedDeposit, gas: GAS_REQUIRED_WITH_REGISTRATION },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough gas prepaid, at least 200 Tgas is needed for transfer call',
+  )
+
+  const fulfillTx = await relayer.callRaw(
+    bridge,
+    'fulfill',
+    { transaction: tx, signature, msg },
+    { attachedDeposit, gas: GAS_REQUIRED_WITH_CALL },
+  )
+  t.true(await bridge.view('is_tx_fulfilled', { tx_hash: hexHash }))
+
+  // transaction can't be fulfilled again without msg
+  const plainSignature = Array.from(
+    (await relayer.getKey())?.sign(
+      Uint8Array.from(Buffer.from(hexHash, 'hex')),
+    )?.signature as Uint8Array,
+  )
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill',
+      { transaction: tx, signature: plainSignature },
+      { attachedDeposit, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Tx has already been fulfilled',
+  )
+
+  const fee = new BN(tx.amount).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  const refunded = new BN(tx.amount).sub(fee).toString()
+  t.true(
+    fulfillTx.logs.includes(
+      `${refunded} tokens are returned by ${
+        token.accountId
+      }, they can be claimed with claim_refunded`,
+    ),
+  )
+  const fulfillInfo = (await bridge.view('get_fulfill_info', {
+    tx_hash: hexHash,
+  })) as any
+  t.is(fulfillInfo.status, 'completed')
+  t.is(fulfillInfo.dispensed_amount, '0')
+  t.deepEqual(fulfillInfo.transfer_call, { msg, refunded_amount: refunded })
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).unclaimed_refunds,
+    refunded,
+  )
+
+  // refund is sent to the recipient with plain ft_transfer
+  const claimTx = await user.callRaw(
+    bridge,
+    'claim_refunded',
+    { tx_hash: hexHash },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.true(
+    claimTx.logs.includes(
+      `Claimed ${refunded} refunded tokens to ${token.accountId}`,
+    ),
+  )
+  t.is(
+    await token.view('ft_balance_of', { account_id: token.accountId }),
+    refunded,
+  )
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).unclaimed_refunds,
+    '0',
+  )
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'claim_refunded',
+      { tx_hash: hexHash },
+      { attachedDeposit: '1' },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: No refunded tokens to claim',
+  )
+})
+
+test('fulfill() makes signed message call', async (t) => {
+  const { owner, token, bridge, user, relayer, wrongToken } =
+    t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  const callGas = new BN(10).mul(new BN(10).pow(new BN(12))).toString()
+  const signCall = async (
+    nonce: string,
+    method_name: string,
+    receiver_id = wrongToken.accountId,
+  ) => {
+    const tx = {
+      from_user: ETH_ADDR,
+      to_user: user.accountId,
+      amount: new BN(100).toString(),
+      timestamp: new BN(666).toString(),
+      from_chain: CHAIN,
+      to_chain: NEAR_CHAIN,
+      nonce,
+    }
+    const call = {
+      receiver_id,
+      method_name,
+      args: Buffer.from('{}').toString('base64'),
+      gas: callGas,
+    }
+    const hash = (await bridge.view('get_tx_hash_with_call', {
+      transaction: tx,
+      call,
+    })) as Uint8Array
+    const signature = Array.from(
+      (await relayer.getKey())?.sign(Uint8Array.from(hash))
+        ?.signature as Uint8Array,
+    )
+    return { transaction: tx, signature, call }
+  }
+  const fulfillWithCall = async (nonce: string, method_name: string) => {
+    const args = await signCall(nonce, method_name)
+    const result = await relayer.callRaw(bridge, 'fulfill', args, {
+      attachedDeposit: payForFulfill.add(ONE_NEAR),
+      gas: MAX_GAS,
+    })
+    const txHash = (await bridge.view('get_tx_hash', {
+      transaction: args.transaction,
+    })) as Uint8Array
+    return { result, hexHash: Buffer.from(txHash).toString('hex') }
+  }
+
+  // call can't be made to the bridge itself or its token
+  for (const receiver of [bridge, token]) {
+    const error = await t.throwsAsync(
+      relayer.call(
+        bridge,
+        'fulfill',
+        await signCall('0', 'ft_metadata', receiver.accountId),
+        { attachedDeposit: payForFulfill.add(ONE_NEAR), gas: MAX_GAS },
+      ),
+    )
+    t.is(
+      panicMessageFromThrowsAsync(error),
+      "Smart contract panicked: Message call can't be made to the bridge, its token or NFT contracts",
+    )
+  }
+
+  const delivered = await fulfillWithCall('0', 'ft_metadata')
+  t.true(
+    delivered.result.logs.includes(
+      `Message of ${delivered.hexHash} is delivered to ${wrongToken.accountId}`,
+    ),
+  )
+  let fulfillInfo = (await bridge.view('get_fulfill_info', {
+    tx_hash: delivered.hexHash,
+  })) as any
+  t.is(fulfillInfo.status, 'completed')
+  t.deepEqual(fulfillInfo.message, {
+    receiver_id: wrongToken.accountId,
+    method_name: 'ft_metadata',
+    args: Buffer.from('{}').toString('base64'),
+    gas: callGas,
+    status: 'delivered',
+  })
+
+  // tokens stay dispensed when the call fails, and the call can be retried
+  const failed = await fulfillWithCall('1', 'no_such_method')
+  const failedLog = `Message of ${failed.hexHash} to ${
+    wrongToken.accountId
+  } failed, it can be retried with retry_message`
+  t.true(failed.result.logs.includes(failedLog))
+  fulfillInfo = (await bridge.view('get_fulfill_info', {
+    tx_hash: failed.hexHash,
+  })) as any
+  t.is(fulfillInfo.status, 'completed')
+  t.is(fulfillInfo.message.status, 'failed')
+
+  const retry = await user.callRaw(
+    bridge,
+    'retry_message',
+    { tx_hash: failed.hexHash },
+    { gas: GAS_REQUIRED },
+  )
+  t.true(retry.logs.includes(failedLog))
+  const error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'retry_message',
+      { tx_hash: delivered.hexHash },
+      { gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only failed message can be retried',
+  )
+})
+
+test('send_native()/fulfill_native() is correct', async (t) => {
+  const { owner, bridge, user, relayer } = t.context.accounts
+  const limit = NEAR.parse('10 N').toJSON()
+  const sent = new BN(NEAR.parse('1 N').toJSON())
+  await owner.call(bridge, 'set_native_limit_per_send', {
+    limit_per_send: limit,
+  })
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+
+  let error = await t.throwsAsync(
+    user.call(bridge, 'send_native', { recipient: ETH_ADDR, chain: CHAIN }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Attached deposit should be greater than 0',
+  )
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'send_native',
+      { recipient: ETH_ADDR, chain: CHAIN },
+      { attachedDeposit: NEAR.parse('20 N').toJSON() },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Amount is over the limit per 1 send',
+  )
+
+  const sendTx = await user.callRaw(
+    bridge,
+    'send_native',
+    { recipient: ETH_ADDR, chain: CHAIN },
+    { attachedDeposit: sent.toString() },
+  )
+  t.is(
+    sendTx.logs[0],
+    `Sent ${sent.toString()} yoctoNEAR from ${
+      user.accountId
+    } to ${ETH_ADDR} in direction NEAR->BSC`,
+  )
+  const record = (await bridge.view('get_transaction_by_nonce', {
+    nonce: '0',
+  })) as any
+  t.true(record.native)
+  t.is(record.amount, sent.toString())
+  t.deepEqual(await bridge.view('get_native_info'), [
+    sent.toString(),
+    sent.toString(),
+    limit,
+    '0',
+  ])
+
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: sent.div(new BN(2)).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const sign = async (method: string) => {
+    const hash = (await bridge.view(method, {
+      transaction: tx,
+    })) as Uint8Array
+    return Array.from(
+      (await relayer.getKey())?.sign(Uint8Array.from(hash))
+        ?.signature as Uint8Array,
+    )
+  }
+
+  // signature of token transaction can't release NEAR
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill_native',
+      { transaction: tx, signature: await sign('get_tx_hash') },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong signature',
+  )
+  const signature = await sign('get_native_tx_hash')
+  // NEAR of the pending send can't be released
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill_native',
+      { transaction: tx, signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough native liquidity',
+  )
+
+  await owner.call(bridge, 'set_outbound_status', {
+    nonce: '0',
+    status: 'completed',
+  })
+  // NEAR of the completed send backs the bridged NEAR instead of the liquidity
+  t.deepEqual(await bridge.view('get_native_info'), [
+    '0',
+    '0',
+    limit,
+    sent.toString(),
+  ])
+  const userBalanceBefore = (await user.balance()).total
+  await relayer.call(
+    bridge,
+    'fulfill_native',
+    { transaction: tx, signature },
+    { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+  )
+  const fee = new BN(tx.amount).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  t.true(
+    (await user.balance()).total
+      .sub(userBalanceBefore)
+      .eq(new BN(tx.amount).sub(fee)),
+  )
+  t.deepEqual(await bridge.view('get_native_info'), [
+    '0',
+    '0',
+    limit,
+    sent.sub(new BN(tx.amount)).toString(),
+  ])
+  // released NEAR is recorded like token fulfills and only counted in stats
+  const nativeHash = Buffer.from(
+    (await bridge.view('get_native_tx_hash', {
+      transaction: tx,
+    })) as Uint8Array,
+  ).toString('hex')
+  const inbound = (await bridge.view('get_inbound_transaction_by_hash', {
+    tx_hash: nativeHash,
+  })) as any
+  t.is(inbound.status, 'completed')
+  t.true(inbound.native)
+  t.is(inbound.dispensed_amount, new BN(tx.amount).sub(fee).toString())
+  t.deepEqual(await bridge.view('get_chain_stats', { chain: CHAIN }), {
+    sent_volume: '0',
+    sent_count: '1',
+    received_volume: '0',
+    received_count: '1',
+  })
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill_native',
+      { transaction: tx, signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Tx has already been fulfilled',
+  )
+
+  // bridged out NEAR isn't withdrawable liquidity
+  await owner.call(
+    bridge,
+    'fund_native_liquidity',
+    {},
+    { attachedDeposit: sent.toString() },
+  )
+  error = await t.throwsAsync(
+    owner.call(
+      bridge,
+      'withdraw_native_liquidity',
+      { receiver: owner.accountId, amount: sent.add(new BN(1)).toString() },
+      { attachedDeposit: '1' },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Amount is more than native liquidity',
+  )
+  await owner.call(
+    bridge,
+    'withdraw_native_liquidity',
+    { receiver: owner.accountId, amount: sent.toString() },
+    { attachedDeposit: '1' },
+  )
+  t.deepEqual(await bridge.view('get_native_info'), [
+    '0',
+    '0',
+    limit,
+    sent.sub(new BN(tx.amount)).toString(),
+  ])
+
+  // NEAR of a refunded send is paid back to the sender
+  await storageDeposit(user, bridge, payForFtOnTransfer)
+  await user.call(
+    bridge,
+    'send_native',
+    { recipient: ETH_ADDR, chain: CHAIN },
+    { attachedDeposit: sent.toString() },
+  )
+  const balanceBeforeRefund = (await user.balance()).total
+  await owner.call(
+    bridge,
+    'set_outbound_status',
+    { nonce: '1', status: 'refunded' },
+    { gas: GAS_REQUIRED },
+  )
+  t.true((await user.balance()).total.sub(balanceBeforeRefund).eq(sent))
+  t.deepEqual(await bridge.view('get_native_info'), [
+    '0',
+    '0',
+    limit,
+    sent.sub(new BN(tx.amount)).toString(),
+  ])
+})
+
+test('nft_on_transfer()/fulfill_nft() is correct', async (t) => {
+  const { owner, bridge, user, relayer } = t.context.accounts
+  // user plays the NFT contract, it has no code so NFT calls fail
+  const transferMsg = JSON.stringify({ recipient: ETH_ADDR, chain: CHAIN })
+  let error = await t.throwsAsync(
+    user.call(bridge, 'nft_on_transfer', {
+      sender_id: user.accountId,
+      previous_owner_id: user.accountId,
+      token_id: '1',
+      msg: transferMsg,
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not supported NFT contract',
+  )
+
+  error = await t.throwsAsync(
+    owner.call(bridge, 'add_nft_contract', { nft_contract: user.accountId }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough NEAR attached',
+  )
+  await owner.call(
+    bridge,
+    'add_nft_contract',
+    { nft_contract: user.accountId },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  t.deepEqual(await bridge.view('get_nft_contracts'), [user.accountId])
+
+  error = await t.throwsAsync(
+    user.call(bridge, 'nft_on_transfer', {
+      sender_id: user.accountId,
+      previous_owner_id: user.accountId,
+      token_id: '1',
+      msg: JSON.stringify({
+        recipient: ETH_ADDR,
+        chain: CHAIN,
+        idempotency_key: 'nft-1',
+      }),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    "Smart contract panicked: Idempotency key and payload aren't supported for NFTs",
+  )
+  error = await t.throwsAsync(
+    user.call(bridge, 'nft_on_transfer', {
+      sender_id: user.accountId,
+      previous_owner_id: user.accountId,
+      token_id: '1',
+      msg: transferMsg,
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not storage paid',
+  )
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+  // the NFT is returned when its token can't be read
+  const sendTx = await user.callRaw(bridge, 'nft_on_transfer', {
+    sender_id: user.accountId,
+    previous_owner_id: user.accountId,
+    token_id: '1',
+    msg: transferMsg,
+  })
+  t.true(sendTx.logs.includes('PANIC: NFT token is unavailable'))
+  t.is(await bridge.view('get_nft_nonce'), '0')
+  t.is(await bridge.view('get_nft_transaction_by_nonce', { nonce: '0' }), null)
+
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    nft_contract: user.accountId,
+    token_id: '1',
+    metadata_hash: '00'.repeat(32),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const hash = (await bridge.view('get_nft_tx_hash', {
+    transaction: tx,
+  })) as Uint8Array
+  const signature = Array.from(
+    (await relayer.getKey())?.sign(Uint8Array.from(hash))
+      ?.signature as Uint8Array,
+  )
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill_nft',
+      { transaction: { ...tx, token_id: '2' }, signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong signature',
+  )
+
+  // failed nft_transfer rolls the fulfillment back so it can be retried
+  for (let i = 0; i < 2; i++) {
+    const fulfillTx = await relayer.callRaw(
+      bridge,
+      'fulfill_nft',
+      { transaction: tx, signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    )
+    t.true(
+      fulfillTx.logs.includes(
+        `Unlock NFT 1 of ${user.accountId} from ${ETH_ADDR} to ${
+          user.accountId
+        } in direction BSC->NEAR`,
+      ),
+    )
+    t.true(
+      fulfillTx.logs.includes(
+        'nft_transfer promise failed for to_user in tx struct',
+      ),
+    )
+  }
+
+  await owner.call(bridge, 'remove_nft_contract', {
+    nft_contract: user.accountId,
+  })
+  t.deepEqual(await bridge.view('get_nft_contracts'), [])
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill_nft',
+      { transaction: tx, signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not supported NFT contract',
+  )
+})
+
+test('NFT is locked by nft_transfer_call() and unlocked by fulfill_nft()', async (t) => {
+  const { owner, bridge, user, relayer } = t.context.accounts
+  const nft = await owner.devDeploy('../res/nft_mock.wasm', {
+    initialBalance: NEAR.parse('10 N').toJSON(),
+    method: 'new',
+    args: { owner_id: owner.accountId },
+  })
+  await owner.call(nft, 'nft_mint', {
+    token_id: '1',
+    receiver_id: user.accountId,
+    token_metadata: { title: 'Bridged NFT' },
+  })
+  await owner.call(
+    bridge,
+    'add_nft_contract',
+    { nft_contract: nft.accountId },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+  const storagePaid = (
+    (await bridge.view('storage_balance_of', {
+      account_id: user.accountId,
+    })) as any
+  ).available
+
+  await user.callRaw(
+    nft,
+    'nft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      token_id: '1',
+      msg: JSON.stringify({ recipient: ETH_ADDR, chain: CHAIN }),
+    },
+    { attachedDeposit: '1', gas: '300 Tgas' },
+  )
+  const token = (await nft.view('nft_token', { token_id: '1' })) as any
+  t.is(token.owner_id, bridge.accountId)
+  const sent = (await bridge.view('get_nft_transaction_by_nonce', {
+    nonce: '0',
+  })) as any
+  t.is(sent.from_user, user.accountId)
+  t.is(sent.nft_contract, nft.accountId)
+  t.is(
+    sent.metadata_hash,
+    createHash('sha256').update(JSON.stringify(token.metadata)).digest('hex'),
+  )
+  t.is(await bridge.view('get_nft_nonce'), '1')
+  // the owner the NFT is sent from pays for the send
+  t.true(
+    new BN(
+      (
+        (await bridge.view('storage_balance_of', {
+          account_id: user.accountId,
+        })) as any
+      ).available,
+    ).lt(new BN(storagePaid)),
+  )
+
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    nft_contract: nft.accountId,
+    token_id: '1',
+    metadata_hash: sent.metadata_hash,
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const hash = (await bridge.view('get_nft_tx_hash', {
+    transaction: tx,
+  })) as Uint8Array
+  const signature = Array.from(
+    (await relayer.getKey())?.sign(Uint8Array.from(hash))
+      ?.signature as Uint8Array,
+  )
+  await relayer.call(
+    bridge,
+    'fulfill_nft',
+    { transaction: tx, signature },
+    { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+  )
+  t.is(
+    ((await nft.view('nft_token', { token_id: '1' })) as any).owner_id,
+    user.accountId,
+  )
+  t.true(
+    await bridge.view('is_tx_fulfilled', {
+      tx_hash: Buffer.from(hash).toString('hex'),
+    }),
+  )
+})
+
+test('send_with_intent() sends escrowed tokens', async (t) => {
+  const { bridge, token, user, relayer } = t.context.accounts
+  const userKey = (await user.getKey())!
+  const escrowed = TRANSFER_AMOUNT.mul(new BN(2))
+  const relayerFee = new BN(1000)
+  await storageDeposit(
+    user,
+    bridge,
+    payForRegister.add(payForFtOnTransfer.mul(new BN(3))),
+  )
+  await user.call(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: escrowed.toString(),
+      msg: JSON.stringify({
+        intent_key: userKey.getPublicKey().toString(),
+      }),
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  // new escrow starts its intent nonce at the block timestamp
+  const escrow = (await bridge.view('get_escrow', {
+    user: user.accountId,
+  })) as any
+  t.is(escrow.balance, escrowed.toString())
+  t.is(escrow.intent_key, userKey.getPublicKey().toString())
+  t.not(escrow.intent_nonce, '0')
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).escrowed,
+    escrowed.toString(),
+  )
+
+  const intent = {
+    sender: user.accountId,
+    recipient: ETH_ADDR,
+    chain: CHAIN,
+    amount: TRANSFER_AMOUNT.toString(),
+    relayer_fee: relayerFee.toString(),
+    nonce: escrow.intent_nonce,
+    deadline: (Math.floor(Date.now() / 1000) + 3600).toString(),
+  }
+  const sign = async (key: KeyPair, signed: any) => {
+    const hash = (await bridge.view('get_intent_hash', {
+      intent: signed,
+    })) as Uint8Array
+    return Array.from(key.sign(Uint8Array.from(hash)).signature)
+  }
+
+  // intent should be signed by the sender's intent key
+  let error = await t.throwsAsync(
+    relayer.call(bridge, 'send_with_intent', {
+      intent,
+      signature: await sign((await relayer.getKey())!, intent),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong signature',
+  )
+  const expired = { ...intent, deadline: '1' }
+  error = await t.throwsAsync(
+    relayer.call(bridge, 'send_with_intent', {
+      intent: expired,
+      signature: await sign(userKey, expired),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Intent is expired',
+  )
+  const overspent = { ...intent, amount: escrowed.toString() }
+  error = await t.throwsAsync(
+    relayer.call(bridge, 'send_with_intent', {
+      intent: overspent,
+      signature: await sign(userKey, overspent),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough escrowed tokens',
+  )
+
+  const signature = await sign(userKey, intent)
+  // escrow of the relayer fee is paid by the relayer
+  error = await t.throwsAsync(
+    relayer.call(bridge, 'send_with_intent', { intent, signature }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough storage paid by relayer',
+  )
+  await storageDeposit(relayer, bridge, payForRegister.add(payForFtOnTransfer))
+  const relayerStorage = async () =>
+    (
+      (await bridge.view('storage_balance_of', {
+        account_id: relayer.accountId,
+      })) as any
+    ).available
+  const relayerStoragePaid = await relayerStorage()
+  const sendTx = await relayer.callRaw(bridge, 'send_with_intent', {
+    intent,
+    signature,
+  })
+  t.true(
+    sendTx.logs.includes(
+      `Sent ${TRANSFER_AMOUNT.toString()} tokens from ${
+        user.accountId
+      } to ${ETH_ADDR} in direction NEAR->BSC`,
+    ),
+  )
+  t.true(
+    sendTx.logs.includes(
+      `Intent ${intent.nonce} of ${user.accountId} is sent by ${
+        relayer.accountId
+      } with fee ${relayerFee.toString()}`,
+    ),
+  )
+  const record = (await bridge.view('get_transaction_by_nonce', {
+    nonce: '0',
+  })) as any
+  t.is(record.from_user, user.accountId)
+  t.is(record.amount, TRANSFER_AMOUNT.toString())
+  t.deepEqual(await bridge.view('get_escrow', { user: user.accountId }), {
+    balance: escrowed.sub(TRANSFER_AMOUNT).sub(relayerFee).toString(),
+    intent_key: userKey.getPublicKey().toString(),
+    intent_nonce: (BigInt(intent.nonce) + BigInt(1)).toString(),
+  })
+  const relayerEscrow = (await bridge.view('get_escrow', {
+    user: relayer.accountId,
+  })) as any
+  t.is(relayerEscrow.balance, relayerFee.toString())
+  t.is(relayerEscrow.intent_key, null)
+  t.true(new BN(await relayerStorage()).lt(new BN(relayerStoragePaid)))
+
+  // signed intent can't be replayed
+  error = await t.throwsAsync(
+    relayer.call(bridge, 'send_with_intent', { intent, signature }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong intent nonce',
+  )
+
+  // relayer fee is escrowed back while the relayer isn't registered on token
+  await relayer.call(
+    bridge,
+    'withdraw_escrow',
+    { amount: relayerFee.toString() },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.is(
+    ((await bridge.view('get_escrow', { user: relayer.accountId })) as any)
+      .balance,
+    relayerFee.toString(),
+  )
+  await registerUser(token, relayer)
+  await relayer.call(
+    bridge,
+    'withdraw_escrow',
+    { amount: relayerFee.toString() },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.is(
+    await token.view('ft_balance_of', { account_id: relayer.accountId }),
+    relayerFee.toString(),
+  )
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).escrowed,
+    escrowed.sub(TRANSFER_AMOUNT).sub(relayerFee).toString(),
+  )
+  // emptied escrow is removed and its storage is returned
+  t.deepEqual(await bridge.view('get_escrow', { user: relayer.accountId }), {
+    balance: '0',
+    intent_key: null,
+    intent_nonce: '0',
+  })
+  t.is(await relayerStorage(), relayerStoragePaid)
+
+  // intents signed for a removed escrow can't be replayed on a new one
+  await user.call(
+    bridge,
+    'withdraw_escrow',
+    { amount: escrowed.sub(TRANSFER_AMOUNT).sub(relayerFee).toString() },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.is(
+    ((await bridge.view('get_escrow', { user: user.accountId })) as any)
+      .intent_nonce,
+    '0',
+  )
+  await user.call(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: escrowed.toString(),
+      msg: JSON.stringify({
+        intent_key: userKey.getPublicKey().toString(),
+      }),
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  error = await t.throwsAsync(
+    relayer.call(bridge, 'send_with_intent', { intent, signature }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong intent nonce',
+  )
+})
+
+test('fast_fulfill() is settled or slashed by canonical fulfill', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  const liquidity = TRANSFER_AMOUNT
+  const fastFee = 100
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  await registerUser(token, relayer)
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    { receiver_id: relayer.accountId, amount: liquidity.toString() },
+    { attachedDeposit: '1' },
+  )
+  await owner.call(
+    bridge,
+    'add_fast_relayer',
+    { relayer: relayer.accountId },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  await owner.call(bridge, 'set_fast_fee_numerator', {
+    fee_numerator: fastFee,
+  })
+  await owner.call(bridge, 'set_fast_min_bond', {
+    min_bond: ONE_NEAR.toString(),
+  })
+  await relayer.call(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: liquidity.toString(),
+      msg: 'fast_liquidity',
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).fast_liquidity,
+    liquidity.toString(),
+  )
+
+  const makeTx = (nonce: string, amount: BN) => ({
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: amount.toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce,
+  })
+  const fastFulfill = (tx: any) =>
+    relayer.call(
+      bridge,
+      'fast_fulfill',
+      { transaction: tx },
+      { attachedDeposit: NEAR.parse('0.01 N').toJSON(), gas: GAS_REQUIRED },
+    )
+  const fulfill = async (tx: any) => {
+    const hash = (await bridge.view('get_tx_hash', {
+      transaction: tx,
+    })) as Uint8Array
+    const signature = Array.from(
+      (await relayer.getKey())?.sign(Uint8Array.from(hash))
+        ?.signature as Uint8Array,
+    )
+    return relayer.callRaw(
+      bridge,
+      'fulfill',
+      { transaction: tx, signature },
+      { attachedDeposit: payForFulfill.add(ONE_NEAR), gas: GAS_REQUIRED },
+    )
+  }
+  const userBalance = async () =>
+    new BN(
+      (await token.view('ft_balance_of', {
+        account_id: user.accountId,
+      })) as string,
+    )
+  const fees = (amount: BN) => ({
+    fee: amount.mul(new BN(FEE_NUMERATOR)).div(new BN(10000)),
+    fastFee: amount.mul(new BN(fastFee)).div(new BN(10000)),
+  })
+
+  const amount = new BN(10000)
+  const tx = makeTx('0', amount)
+  let error = await t.throwsAsync(fastFulfill(tx))
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Fast relayer bond is less than the minimum',
+  )
+  await relayer.call(
+    bridge,
+    'fund_fast_bond',
+    {},
+    { attachedDeposit: ONE_NEAR.toString() },
+  )
+
+  // recipient is paid from relayer liquidity without the fee and the fast fee
+  let balanceBefore = await userBalance()
+  await fastFulfill(tx)
+  const { fee, fastFee: fastFeeAmount } = fees(amount)
+  const paid = amount.sub(fee).sub(fastFeeAmount)
+  t.is((await userBalance()).sub(balanceBefore).toString(), paid.toString())
+  t.is(
+    ((await bridge.view('get_fast_fulfill', {
+      from_chain: CHAIN,
+      nonce: '0',
+    })) as any).paid,
+    paid.toString(),
+  )
+  error = await t.throwsAsync(fastFulfill(tx))
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Tx with this nonce is already fronted',
+  )
+
+  // canonical fulfill reimburses the relayer instead of paying the recipient
+  balanceBefore = await userBalance()
+  const settleTx = await fulfill(tx)
+  const hexHash = Buffer.from(
+    (await bridge.view('get_tx_hash', { transaction: tx })) as Uint8Array,
+  ).toString('hex')
+  t.true(
+    settleTx.logs.includes(
+      `Settled fast fulfill of ${hexHash} to ${
+        relayer.accountId
+      } with ${amount.sub(fee).toString()} tokens`,
+    ),
+  )
+  t.true((await userBalance()).eq(balanceBefore))
+  t.true(await bridge.view('is_tx_fulfilled', { tx_hash: hexHash }))
+  // settled transaction is recorded and attested like a dispensed one
+  const info = (await bridge.view('get_fulfill_info', {
+    tx_hash: hexHash,
+  })) as any
+  t.is(info.status, 'completed')
+  t.not(info.fulfilled_at, '0')
+  t.is(info.dispensed_amount, amount.sub(fee).toString())
+  t.is(info.fee, fee.toString())
+  t.is(
+    ((await bridge.view('get_attestation', {
+      from_chain: CHAIN,
+      nonce: '0',
+    })) as any).tx_hash,
+    hexHash,
+  )
+  t.deepEqual(await bridge.view('get_chain_stats', { chain: CHAIN }), {
+    sent_volume: '0',
+    sent_count: '0',
+    received_volume: amount.toString(),
+    received_count: '1',
+  })
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).pending_inbound,
+    '0',
+  )
+  // the fee is sent to the fee wallet
+  t.is(
+    ((await bridge.view('get_bridge_stats')) as any).total_fees_collected,
+    fee.toString(),
+  )
+  t.deepEqual(
+    await bridge.view('get_fast_relayer', { relayer: relayer.accountId }),
+    {
+      liquidity: liquidity.add(fastFeeAmount).toString(),
+      bond: ONE_NEAR.toString(),
+      unsettled: '0',
+    },
+  )
+
+  // bond is slashed when the canonical transaction doesn't match the fronted
+  await fastFulfill(makeTx('1', amount))
+  balanceBefore = await userBalance()
+  const canonical = makeTx('1', amount.mul(new BN(2)))
+  const slashTx = await fulfill(canonical)
+  const slashLog = `Fast relayer ${relayer.accountId} is slashed by ${ONE_NEAR}`
+  t.true(slashTx.logs.some((log) => log.startsWith(slashLog)))
+  t.is(
+    (await userBalance()).sub(balanceBefore).toString(),
+    amount.mul(new BN(2)).sub(fees(amount.mul(new BN(2))).fee).toString(),
+  )
+  t.deepEqual(
+    await bridge.view('get_fast_relayer', { relayer: relayer.accountId }),
+    {
+      liquidity: liquidity.add(fastFeeAmount).sub(paid).toString(),
+      bond: '0',
+      unsettled: '0',
+    },
+  )
+})
+
+test('submit_fraud_proof() slashes relayer bond', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  await registerUser(token, relayer)
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    { receiver_id: relayer.accountId, amount: TRANSFER_AMOUNT.toString() },
+    { attachedDeposit: '1' },
+  )
+  await storageDeposit(relayer, bridge, payForRegister.add(payForFtOnTransfer))
+  const relayerKey = (await relayer.getKey())!.getPublicKey().toString()
+  await relayer.call(
+    bridge,
+    'bond_relayer',
+    {
+      relayer_key: relayerKey,
+      signature: await signBondHash(bridge, relayer, relayer),
+    },
+    { attachedDeposit: ONE_NEAR.toString() },
+  )
+  // another account can't bond tokens to the relayer key
+  const bondTx = await user.callRaw(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: TRANSFER_AMOUNT.toString(),
+      msg: JSON.stringify({ relayer_bond: relayerKey }),
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.true(bondTx.logs.includes("PANIC: Relayer key isn't bonded by sender"))
+  await relayer.call(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: TRANSFER_AMOUNT.toString(),
+      msg: JSON.stringify({ relayer_bond: relayerKey }),
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).relayer_bonds,
+    TRANSFER_AMOUNT.toString(),
+  )
+
+  const makeTx = (amount: BN) => ({
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: amount.toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  })
+  const sign = async (tx: any) => {
+    const hash = (await bridge.view('get_tx_hash', {
+      transaction: tx,
+    })) as Uint8Array
+    return Array.from(
+      (await relayer.getKey())?.sign(Uint8Array.from(hash))
+        ?.signature as Uint8Array,
+    )
+  }
+  const tx = makeTx(new BN(100))
+  const fulfill = async () =>
+    relayer.call(
+      bridge,
+      'fulfill',
+      { transaction: tx, signature: await sign(tx) },
+      { attachedDeposit: payForFulfill.add(ONE_NEAR), gas: GAS_REQUIRED },
+    )
+
+  await owner.call(bridge, 'set_min_relayer_bond', {
+    near: ONE_NEAR.mul(new BN(2)).toString(),
+    tokens: TRANSFER_AMOUNT.mul(new BN(2)).toString(),
+  })
+  let error = await t.throwsAsync(fulfill())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Relayer bond is less than the minimum',
+  )
+  await owner.call(bridge, 'set_min_relayer_bond', {
+    near: ONE_NEAR.mul(new BN(2)).toString(),
+    tokens: TRANSFER_AMOUNT.toString(),
+  })
+  await fulfill()
+  t.is(
+    ((await bridge.view('get_attestation', {
+      from_chain: CHAIN,
+      nonce: '0',
+    })) as any).signer,
+    relayerKey,
+  )
+
+  error = await t.throwsAsync(
+    user.call(bridge, 'submit_fraud_proof', {
+      transaction: tx,
+      signature: await sign(tx),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    "Smart contract panicked: Transaction doesn't conflict with the attestation",
+  )
+
+  // relayer role signed another transaction with the same nonce
+  const conflicting = makeTx(new BN(200))
+  const watcherBalanceBefore = (await user.balance()).total
+  const proofTx = await user.callRaw(bridge, 'submit_fraud_proof', {
+    transaction: conflicting,
+    signature: await sign(conflicting),
+  })
+  const slashedLog =
+    `Relayer key ${relayerKey} is slashed by ${ONE_NEAR} yoctoNEAR ` +
+    `and ${TRANSFER_AMOUNT} tokens`
+  t.true(proofTx.logs[0].startsWith(slashedLog))
+  t.true(
+    (await user.balance()).total
+      .sub(watcherBalanceBefore)
+      .gt(ONE_NEAR.sub(DELTA)),
+  )
+  const bond = (await bridge.view('get_relayer_bond', {
+    relayer_key: relayerKey,
+  })) as any
+  t.is(bond.near, '0')
+  t.is(bond.tokens, '0')
+  t.is(
+    ((await bridge.view('get_liabilities')) as any).relayer_bonds,
+    '0',
+  )
+  error = await t.throwsAsync(
+    user.call(bridge, 'submit_fraud_proof', {
+      transaction: conflicting,
+      signature: await sign(conflicting),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: No attestation with this nonce',
+  )
+})
+
+test('native and NFT fulfills are attested in their own domains', async (t) => {
+  const { owner, bridge, user, relayer } = t.context.accounts
+  const nft = await owner.devDeploy('../res/nft_mock.wasm', {
+    initialBalance: NEAR.parse('10 N').toJSON(),
+    method: 'new',
+    args: { owner_id: owner.accountId },
+  })
+  await owner.call(nft, 'nft_mint', {
+    token_id: '1',
+    receiver_id: user.accountId,
+    token_metadata: { title: 'Bridged NFT' },
+  })
+  await owner.call(
+    bridge,
+    'add_nft_contract',
+    { nft_contract: nft.accountId },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+  await user.call(
+    nft,
+    'nft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      token_id: '1',
+      msg: JSON.stringify({ recipient: ETH_ADDR, chain: CHAIN }),
+    },
+    { attachedDeposit: '1', gas: '300 Tgas' },
+  )
+  await owner.call(
+    bridge,
+    'fund_native_liquidity',
+    {},
+    { attachedDeposit: ONE_NEAR.toString() },
+  )
+  await storageDeposit(relayer, bridge, payForRegister.add(payForFtOnTransfer))
+  const relayerKey = (await relayer.getKey())!.getPublicKey().toString()
+  await relayer.call(
+    bridge,
+    'bond_relayer',
+    {
+      relayer_key: relayerKey,
+      signature: await signBondHash(bridge, relayer, relayer),
+    },
+    { attachedDeposit: ONE_NEAR.toString() },
+  )
+
+  const makeNativeTx = (amount: BN) => ({
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: amount.toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  })
+  const makeNftTx = (tokenId: string) => ({
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    nft_contract: nft.accountId,
+    token_id: tokenId,
+    metadata_hash: '',
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  })
+  const hashOf = async (method: string, tx: any) =>
+    (await bridge.view(method, { transaction: tx })) as Uint8Array
+  const sign = async (method: string, tx: any) =>
+    Array.from(
+      (await relayer.getKey())?.sign(Uint8Array.from(await hashOf(method, tx)))
+        ?.signature as Uint8Array,
+    )
+  const nativeTx = makeNativeTx(ONE_NEAR.div(new BN(2)))
+  const nftTx = makeNftTx('1')
+  const fulfillNative = async () =>
+    relayer.call(
+      bridge,
+      'fulfill_native',
+      {
+        transaction: nativeTx,
+        signature: await sign('get_native_tx_hash', nativeTx),
+      },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    )
+  const fulfillNft = async () =>
+    relayer.call(
+      bridge,
+      'fulfill_nft',
+      { transaction: nftTx, signature: await sign('get_nft_tx_hash', nftTx) },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    )
+
+  await owner.call(bridge, 'set_min_relayer_bond', {
+    near: ONE_NEAR.mul(new BN(2)).toString(),
+    tokens: '0',
+  })
+  let error = await t.throwsAsync(fulfillNative())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Relayer bond is less than the minimum',
+  )
+  error = await t.throwsAsync(fulfillNft())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Relayer bond is less than the minimum',
+  )
+  await owner.call(bridge, 'set_min_relayer_bond', {
+    near: ONE_NEAR.toString(),
+    tokens: '0',
+  })
+  await fulfillNative()
+  await fulfillNft()
+  t.is(
+    ((await nft.view('nft_token', { token_id: '1' })) as any).owner_id,
+    user.accountId,
+  )
+
+  // transactions with the same nonce are attested apart by their kind
+  const attestation = async (kind?: string) =>
+    (await bridge.view('get_attestation', {
+      from_chain: CHAIN,
+      nonce: '0',
+      kind,
+    })) as any
+  t.is(await attestation(), null)
+  const nativeAttestation = await attestation('native')
+  t.is(nativeAttestation.signer, relayerKey)
+  t.is(
+    nativeAttestation.tx_hash,
+    Buffer.from(await hashOf('get_native_tx_hash', nativeTx)).toString('hex'),
+  )
+  const nftAttestation = await attestation('nft')
+  t.is(nftAttestation.signer, relayerKey)
+  t.is(
+    nftAttestation.tx_hash,
+    Buffer.from(await hashOf('get_nft_tx_hash', nftTx)).toString('hex'),
+  )
+
+  error = await t.throwsAsync(
+    user.call(bridge, 'submit_nft_fraud_proof', {
+      transaction: nftTx,
+      signature: await sign('get_nft_tx_hash', nftTx),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    "Smart contract panicked: Transaction doesn't conflict with the attestation",
+  )
+  // signature over the token hash isn't a proof against the NFT attestation
+  const conflictingNft = makeNftTx('2')
+  error = await t.throwsAsync(
+    user.call(bridge, 'submit_nft_fraud_proof', {
+      transaction: conflictingNft,
+      signature: await sign('get_tx_hash', nativeTx),
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong signature',
+  )
+
+  // relayer role signed another NFT transaction with the same nonce
+  const watcherBalanceBefore = (await user.balance()).total
+  const nftProofTx = await user.callRaw(bridge, 'submit_nft_fraud_proof', {
+    transaction: conflictingNft,
+    signature: await sign('get_nft_tx_hash', conflictingNft),
+  })
+  t.true(
+    nftProofTx.logs[0].startsWith(
+      `Relayer key ${relayerKey} is slashed by ${ONE_NEAR} yoctoNEAR ` +
+        'and 0 tokens',
+    ),
+  )
+  t.true(
+    (await user.balance()).total
+      .sub(watcherBalanceBefore)
+      .gt(ONE_NEAR.sub(DELTA)),
+  )
+  t.is(await attestation('nft'), null)
+
+  // and another native transaction, the bond is already slashed
+  const conflictingNative = makeNativeTx(ONE_NEAR)
+  const nativeProofTx = await user.callRaw(
+    bridge,
+    'submit_native_fraud_proof',
+    {
+      transaction: conflictingNative,
+      signature: await sign('get_native_tx_hash', conflictingNative),
+    },
+  )
+  t.true(
+    nativeProofTx.logs[0].startsWith(
+      `Relayer key ${relayerKey} is slashed by 0 yoctoNEAR and 0 tokens`,
+    ),
+  )
+  t.is(await attestation('native'), null)
+  const bond = (await bridge.view('get_relayer_bond', {
+    relayer_key: relayerKey,
+  })) as any
+  t.is(bond.near, '0')
+  error = await t.throwsAsync(fulfillNative())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Relayer bond is less than the minimum',
+  )
+})
+
+test('unbond_relayer()/withdraw_unbonded() is correct', async (t) => {
+  const { owner, bridge, relayer } = t.context.accounts
+  await storageDeposit(relayer, bridge, payForRegister.add(payForFtOnTransfer))
+  const relayerKey = (await relayer.getKey())!.getPublicKey().toString()
+  // the bond hash is signed for another account
+  let error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'bond_relayer',
+      {
+        relayer_key: relayerKey,
+        signature: await signBondHash(bridge, relayer, owner),
+      },
+      { attachedDeposit: ONE_NEAR.toString() },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong signature',
+  )
+  await relayer.call(
+    bridge,
+    'bond_relayer',
+    {
+      relayer_key: relayerKey,
+      signature: await signBondHash(bridge, relayer, relayer),
+    },
+    { attachedDeposit: ONE_NEAR.toString() },
+  )
+  error = await t.throwsAsync(
+    owner.call(
+      bridge,
+      'unbond_relayer',
+      { relayer_key: relayerKey, near: ONE_NEAR.toString(), tokens: '0' },
+      { attachedDeposit: '1' },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only bond owner can call this method',
+  )
+
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_unbonding_period', { unbonding_period: '60' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Unbonding period should be longer than dispute window',
+  )
+  await owner.call(bridge, 'set_dispute_window', { dispute_window: '0' })
+  await owner.call(bridge, 'set_unbonding_period', { unbonding_period: '2' })
+
+  await relayer.call(
+    bridge,
+    'unbond_relayer',
+    { relayer_key: relayerKey, near: ONE_NEAR.toString(), tokens: '0' },
+    { attachedDeposit: '1' },
+  )
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'withdraw_unbonded',
+      { relayer_key: relayerKey },
+      { attachedDeposit: '1' },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    "Smart contract panicked: Unbonding period hasn't passed yet",
+  )
+
+  await new Promise((resolve) => setTimeout(resolve, 3000))
+  const relayerBalanceBefore = (await relayer.balance()).total
+  await relayer.call(
+    bridge,
+    'withdraw_unbonded',
+    { relayer_key: relayerKey },
+    { attachedDeposit: '1' },
+  )
+  t.true(
+    (await relayer.balance()).total
+      .sub(relayerBalanceBefore)
+      .gt(ONE_NEAR.sub(DELTA)),
+  )
+  t.is(
+    ((await bridge.view('get_relayer_bond', {
+      relayer_key: relayerKey,
+    })) as any).unbonding_near,
+    '0',
+  )
+})
+
+test('native and NFT fulfills are rejected for optimistic chains', async (t) => {
+  const { owner, bridge, user, relayer } = t.context.accounts
+  await owner.call(bridge, 'set_challenge_bond', {
+    challenge_bond: NEAR.parse('0.1 N').toJSON(),
+  })
+  await owner.call(
+    bridge,
+    'add_guardian',
+    { guardian: owner.accountId },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  const setWindow = (window: string | null) =>
+    owner.call(
+      bridge,
+      'set_optimistic_window',
+      { chain: CHAIN, window },
+      { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+    )
+  await setWindow('3')
+  // user plays the NFT contract, the NFT is rejected before it's transferred
+  await owner.call(
+    bridge,
+    'add_nft_contract',
+    { nft_contract: user.accountId },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  await owner.call(
+    bridge,
+    'fund_native_liquidity',
+    {},
+    { attachedDeposit: ONE_NEAR.toString() },
+  )
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+
+  const nativeTx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: ONE_NEAR.div(new BN(2)).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const nftTx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    nft_contract: user.accountId,
+    token_id: '1',
+    metadata_hash: '',
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const sign = async (method: string, tx: any) => {
+    const hash = (await bridge.view(method, {
+      transaction: tx,
+    })) as Uint8Array
+    return Array.from(
+      (await relayer.getKey())?.sign(Uint8Array.from(hash))
+        ?.signature as Uint8Array,
+    )
+  }
+  const fulfillNative = async () =>
+    relayer.call(
+      bridge,
+      'fulfill_native',
+      {
+        transaction: nativeTx,
+        signature: await sign('get_native_tx_hash', nativeTx),
+      },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    )
+
+  let error = await t.throwsAsync(fulfillNative())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    "Smart contract panicked: Native tx of optimistic chain isn't supported",
+  )
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill_nft',
+      { transaction: nftTx, signature: await sign('get_nft_tx_hash', nftTx) },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    "Smart contract panicked: NFT tx of optimistic chain isn't supported",
+  )
+  t.deepEqual(await bridge.view('get_native_info'), [
+    ONE_NEAR.toString(),
+    '0',
+    '0',
+    '0',
+  ])
+
+  // NEAR is released once the window is disabled
+  await setWindow(null)
+  const userBalanceBefore = (await user.balance()).total
+  await fulfillNative()
+  t.true((await user.balance()).total.gt(userBalanceBefore))
+  error = await t.throwsAsync(fulfillNative())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Tx has already been fulfilled',
+  )
+})
+
+test('optimistic fulfill() is finalized after challenge window', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  const challengeBond = NEAR.parse('0.1 N').toJSON()
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  const setWindow = () =>
+    owner.call(
+      bridge,
+      'set_optimistic_window',
+      { chain: CHAIN, window: '3' },
+      { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+    )
+  let error = await t.throwsAsync(setWindow())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Challenge bond and guardians should be set first',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_challenge_bond', { challenge_bond: '0' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Challenge bond should be greater than 0',
+  )
+  await owner.call(bridge, 'set_challenge_bond', {
+    challenge_bond: challengeBond,
+  })
+  error = await t.throwsAsync(setWindow())
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Challenge bond and guardians should be set first',
+  )
+  await owner.call(
+    bridge,
+    'add_guardian',
+    { guardian: owner.accountId },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  await setWindow()
+
+  const makeTx = (nonce: string) => ({
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: new BN(100).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce,
+  })
+  const claim = async (tx: any) => {
+    const hash = (await bridge.view('get_tx_hash', {
+      transaction: tx,
+    })) as Uint8Array
+    const signature = Array.from(
+      (await relayer.getKey())?.sign(Uint8Array.from(hash))
+        ?.signature as Uint8Array,
+    )
+    const result = await relayer.callRaw(
+      bridge,
+      'fulfill',
+      { transaction: tx, signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    )
+    return { result, signature, hexHash: Buffer.from(hash).toString('hex') }
+  }
+  const finalize = (tx_hash: string) =>
+    relayer.call(
+      bridge,
+      'finalize',
+      { tx_hash },
+      { attachedDeposit: payForFulfill.add(ONE_NEAR), gas: GAS_REQUIRED },
+    )
+  const claimStatus = async (tx_hash: string) =>
+    ((await bridge.view('get_optimistic_claim', { tx_hash })) as any).status
+  const userBalance = async () =>
+    new BN(
+      (await token.view('ft_balance_of', {
+        account_id: user.accountId,
+      })) as string,
+    )
+
+  // fulfill of optimistic chain only claims the transaction
+  const balanceBefore = await userBalance()
+  const { result, hexHash } = await claim(makeTx('0'))
+  t.true(
+    result.logs.some((log) =>
+      log.startsWith(`Claimed ${hexHash} by ${relayer.accountId}`),
+    ),
+  )
+  t.true((await userBalance()).eq(balanceBefore))
+  t.is(await claimStatus(hexHash), 'pending')
+  error = await t.throwsAsync(finalize(hexHash))
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    "Smart contract panicked: Challenge window hasn't passed yet",
+  )
+
+  error = await t.throwsAsync(
+    user.call(bridge, 'challenge', { tx_hash: hexHash }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Attached deposit is less than challenge bond',
+  )
+  await user.call(
+    bridge,
+    'challenge',
+    { tx_hash: hexHash },
+    { attachedDeposit: challengeBond },
+  )
+  t.is(await claimStatus(hexHash), 'challenged')
+  error = await t.throwsAsync(
+    user.call(bridge, 'dismiss_challenge', { tx_hash: hexHash }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only guardian can call this method',
+  )
+  await owner.call(bridge, 'dismiss_challenge', { tx_hash: hexHash })
+  t.is(await claimStatus(hexHash), 'pending')
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'challenge',
+      { tx_hash: hexHash },
+      { attachedDeposit: challengeBond },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Claim has already been challenged',
+  )
+
+  await new Promise((resolve) => setTimeout(resolve, 4000))
+  await finalize(hexHash)
+  const fee = new BN(100).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  t.is(
+    (await userBalance()).sub(balanceBefore).toString(),
+    new BN(100).sub(fee).toString(),
+  )
+  t.true(await bridge.view('is_tx_fulfilled', { tx_hash: hexHash }))
+  t.is(await bridge.view('get_optimistic_claim', { tx_hash: hexHash }), null)
+
+  // pending claim can't be fulfilled again
+  const vetoed = await claim(makeTx('1'))
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill',
+      { transaction: makeTx('1'), signature: vetoed.signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Tx has already been claimed',
+  )
+
+  // vetoed claim is removed with its storage returned to the relayer
+  const relayerBalanceBefore = (await relayer.balance()).total
+  await owner.call(bridge, 'veto', { tx_hash: vetoed.hexHash })
+  t.true((await relayer.balance()).total.gt(relayerBalanceBefore))
+  t.is(
+    await bridge.view('get_optimistic_claim', { tx_hash: vetoed.hexHash }),
+    null,
+  )
+  t.true(await bridge.view('is_tx_vetoed', { tx_hash: vetoed.hexHash }))
+  error = await t.throwsAsync(finalize(vetoed.hexHash))
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: No claim with this hash',
+  )
+  error = await t.throwsAsync(
+    relayer.call(
+      bridge,
+      'fulfill',
+      { transaction: makeTx('1'), signature: vetoed.signature },
+      { attachedDeposit: payForFulfill, gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Tx is vetoed',
+  )
+})
+
+/*
+ ----------------------------------------------
+ Submit_checkpoint() and claim_with_proof() tests
+ ----------------------------------------------
+*/
+
+const FROM_BLOCK = '100'
+const TO_BLOCK = '200'
+
+test('submit_checkpoint() is correct', async (t) => {
+  const { bridge, user, relayer } = t.context.accounts
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: new BN(100).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const leaf = (await bridge.view('get_checkpoint_leaf', {
+    transaction: tx,
+  })) as Uint8Array
+  const root = Array.prototype.map
+    .call(leaf, function (byte) {
+      return ('0' + (byte & 0xff).toString(16)).slice(-2)
+    })
+    .join('')
+  const checkpoint = {
+    chain: CHAIN,
+    from_block: FROM_BLOCK,
+    to_block: TO_BLOCK,
+    root,
+  }
+  const hash_of_checkpoint = (await bridge.view('get_checkpoint_hash', {
+    checkpoint,
+  })) as Uint8Array
+  const signature = (await relayer.getKey())?.sign(
+    Uint8Array.from(hash_of_checkpoint),
+  )
+  const payForCheckpoint = new BN(await bridge.view('get_pay_for_checkpoint'))
+
+  let error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'submit_checkpoint',
+      {
+        checkpoint: { ...checkpoint, chain: 'AVAX' },
+        signature: Array.from(signature?.signature as Uint8Array),
+      },
+      { attachedDeposit: payForCheckpoint },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Chain is not supported',
+  )
+
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'submit_checkpoint',
+      {
+        checkpoint: { ...checkpoint, from_block: '300' },
+        signature: Array.from(signature?.signature as Uint8Array),
+      },
+      { attachedDeposit: payForCheckpoint },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong block range in checkpoint',
+  )
+
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'submit_checkpoint',
+      {
+        checkpoint: { ...checkpoint, to_block: '201' },
+        signature: Array.from(signature?.signature as Uint8Array),
+      },
+      { attachedDeposit: payForCheckpoint },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Wrong signature',
+  )
+
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'submit_checkpoint',
+      {
+        checkpoint,
+        signature: Array.from(signature?.signature as Uint8Array),
+      },
+      { attachedDeposit: payForCheckpoint.sub(new BN(1)) },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough NEAR attached',
+  )
+
+  const submitTx = await user.callRaw(
+    bridge,
+    'submit_checkpoint',
+    {
+      checkpoint,
+      signature: Array.from(signature?.signature as Uint8Array),
+    },
+    { attachedDeposit: payForCheckpoint },
+  )
+  t.is(
+    submitTx.logs[0],
+    `Checkpoint ${root} submitted for blocks ${FROM_BLOCK}..${TO_BLOCK} of ${CHAIN}`,
+  )
+  t.deepEqual(await bridge.view('get_checkpoint', { root }), checkpoint)
+
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'submit_checkpoint',
+      {
+        checkpoint,
+        signature: Array.from(signature?.signature as Uint8Array),
+      },
+      { attachedDeposit: payForCheckpoint },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Checkpoint is already submitted',
+  )
+
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'submit_checkpoint',
+      {
+        checkpoint: {
+          ...checkpoint,
+          from_block: TO_BLOCK,
+          root: '00'.repeat(32),
+        },
+        signature: Array.from(signature?.signature as Uint8Array),
+      },
+      { attachedDeposit: payForCheckpoint },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Checkpoint overlaps blocks of a previous checkpoint',
+  )
+})
+
+test('claim_with_proof() is correct', async (t) => {
+  const { owner, token, bridge, user, relayer } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  await user.call(
+    bridge,
+    'storage_deposit',
+    {},
+    { attachedDeposit: payForRegister.add(payForFulfill) },
+  )
+
+  // Checkpoint of the only transfer, so its root is the transfer leaf itself
+  const tx = {
+    from_user: ETH_ADDR,
+    to_user: user.accountId,
+    amount: new BN(100).toString(),
+    timestamp: new BN(666).toString(),
+    from_chain: CHAIN,
+    to_chain: NEAR_CHAIN,
+    nonce: '0',
+  }
+  const toHex = (bytes: Uint8Array) =>
+    Array.prototype.map
+      .call(bytes, function (byte) {
+        return ('0' + (byte & 0xff).toString(16)).slice(-2)
+      })
+      .join('')
+  const hash_of_tx = toHex(
+    (await bridge.view('get_tx_hash', { transaction: tx })) as Uint8Array,
+  )
+  const root = toHex(
+    (await bridge.view('get_checkpoint_leaf', {
+      transaction: tx,
+    })) as Uint8Array,
+  )
+  t.not(root, hash_of_tx)
+
+  let error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'claim_with_proof',
+      { transaction: tx, merkle_proof: [] },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: No checkpoint is posted for this merkle proof',
+  )
+
+  const checkpoint = {
+    chain: CHAIN,
+    from_block: FROM_BLOCK,
+    to_block: TO_BLOCK,
+    root,
+  }
+  const hash_of_checkpoint = (await bridge.view('get_checkpoint_hash', {
+    checkpoint,
+  })) as Uint8Array
+  const signature = (await relayer.getKey())?.sign(
+    Uint8Array.from(hash_of_checkpoint),
+  )
+  await relayer.call(
+    bridge,
+    'submit_checkpoint',
+    {
+      checkpoint,
+      signature: Array.from(signature?.signature as Uint8Array),
+    },
+    { attachedDeposit: await bridge.view('get_pay_for_checkpoint') },
+  )
+
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'claim_with_proof',
+      { transaction: tx, merkle_proof: ['abc'] },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Merkle proof element should be a hex string',
+  )
+
+  const userBalanceBefore = await token.view('ft_balance_of', {
+    account_id: user.accountId,
+  })
+  const claimTx = await user.callRaw(
+    bridge,
+    'claim_with_proof',
+    { transaction: tx, merkle_proof: [] },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.is(claimTx.logs[0], 'Merkle proof has been verified')
+  t.true(await bridge.view('is_tx_fulfilled', { tx_hash: hash_of_tx }))
+
+  const fee = new BN(tx.amount).mul(new BN(FEE_NUMERATOR)).div(new BN(10000))
+  t.is(
+    await token.view('ft_balance_of', { account_id: user.accountId }),
+    new BN(userBalanceBefore as any).add(new BN(tx.amount).sub(fee)).toString(),
+  )
+
+  await user.call(
+    bridge,
+    'storage_deposit',
+    {},
+    { attachedDeposit: payForFulfill },
+  )
+  error = await t.throwsAsync(
+    user.call(
+      bridge,
+      'claim_with_proof',
+      { transaction: tx, merkle_proof: [] },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Tx has already been fulfilled',
+  )
+})
+
+/*
+ ------------------------------
+ Administrative functions tests
+ ------------------------------
+*/
+
+test('set_fee_numerator() is correct', async (t) => {
+  const { owner, token, bridge, user } = t.context.accounts
+  await owner.call(bridge, 'set_fee_numerator', { fee_numerator: 222 })
+  t.deepEqual(await bridge.view('get_fee_info', {}), [
+    owner.accountId,
+    222,
+    10000,
+  ])
+  const error = await t.throwsAsync(
+    user.call(bridge, 'set_fee_numerator', { fee_numerator: 1000 }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+})
+
+test('transfer_ownership() is correct', async (t) => {
+  const { owner, token, bridge, user } = t.context.accounts
+  await owner.call(bridge, 'transfer_ownership', { owner: user.accountId })
+  t.is(await bridge.view('get_owner', {}), user.accountId)
+  let error = await t.throwsAsync(
+    owner.call(bridge, 'transfer_ownership', { owner: user.accountId }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+  error = await t.throwsAsync(
+    user.call(bridge, 'transfer_ownership', { owner: user.accountId }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Current owner is equal to new owner',
+  )
+})
+
+test('set_relayer_role() is correct', async (t) => {
+  const { owner, token, bridge, user } = t.context.accounts
+  await owner.call(bridge, 'set_relayer_role', {
+    relayer: 'ed25519:ifRNRsDd85kNtGj4WRUm17vpqmymwswn8QzCwdJVnBT',
+  })
+  t.is(
+    await bridge.view('get_relayer_role', {}),
+    'ed25519:ifRNRsDd85kNtGj4WRUm17vpqmymwswn8QzCwdJVnBT',
+  )
+  let error = await t.throwsAsync(
+    user.call(bridge, 'set_relayer_role', {
+      relayer: 'ed25519:DTRVwm7mmqCxfTZTFwi2kgp5vuYB3aiaB67vqcLXCpmh',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_relayer_role', { relayer: 'abc' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not convertible to PublicKey type',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_relayer_role', {
+      relayer:
+        'secp256k1:qMoRgcoXai4mBPsdbHi1wfyxF9TdbPCF4qSDQTRP3TfescSRoUdSx6nmeQoN3aiwGzwMyGXAb1gUjBTv5AY8DXj',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: The only supported curve type for relayer role is ED25519',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_relayer_role', {
+      relayer: 'ed25519:ifRNRsDd85kNtGj4WRUm17vpqmymwswn8QzCwdJVnBT',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Current relayer is equal to new relayer',
+  )
+})
+
+test('set_limit_per_send is correct()', async (t) => {
+  const { owner, token, bridge, user } = t.context.accounts
+  await owner.call(bridge, 'set_limit_per_send', { limit_per_send: '666' })
+  t.is(await bridge.view('get_limit_per_send', {}), '666')
+  let error = await t.throwsAsync(
+    user.call(bridge, 'set_limit_per_send', { limit_per_send: '500' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_limit_per_send', { limit_per_send: '666' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Current limit is equal to new limit',
+  )
+})
+
+const INITIAL_BRIDGE_BALANCE = new BN(10000).mul(
+  new BN(10).pow(new BN(DECIMALS)),
+)
+
+test('withdraw() is correct', async (t) => {
+  const { owner, token, bridge, user } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+
+  const bridgeBalanceBefore = (await token.view('ft_balance_of', {
+    account_id: bridge.accountId,
+  })) as string
+  const ownerBalanceBefore = (await token.view('ft_balance_of', {
+    account_id: owner.accountId,
+  })) as string
+  await owner.call(
+    bridge,
+    'withdraw',
+    { amount: '800' },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.is(
+    (await token.view('ft_balance_of', {
+      account_id: bridge.accountId,
+    })) as string,
+    new BN(bridgeBalanceBefore).sub(new BN(800)).toString(),
+  )
+  t.is(
+    (await token.view('ft_balance_of', {
+      account_id: owner.accountId,
+    })) as string,
+    new BN(ownerBalanceBefore).add(new BN(800)).toString(),
+  )
+
+  const error = await t.throwsAsync(
+    user.call(bridge, 'withdraw', { amount: '800' }, { attachedDeposit: '1' }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+})
+
+test('withdraw_to() is limited to surplus over liabilities', async (t) => {
+  const { owner, token, bridge, user } = t.context.accounts
+  await owner.callRaw(
+    token,
+    'ft_transfer',
+    {
+      receiver_id: bridge.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1' },
+  )
+  await storageDeposit(user, bridge, payForRegister.add(payForFtOnTransfer))
+  await user.callRaw(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: TRANSFER_AMOUNT.toString(),
+      msg: ETH_ADDR + CHAIN,
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.deepEqual(await bridge.view('get_liabilities'), {
+    pending_outbound: TRANSFER_AMOUNT.toString(),
+    bridged_out: '0',
+    pending_inbound: '0',
+    withdrawals_in_flight: '0',
+    unclaimed_refunds: '0',
+    escrowed: '0',
+    fast_liquidity: '0',
+    relayer_bonds: '0',
+  })
+
+  // tokens of the pending send can't be withdrawn
+  const bridgeBalance = (await token.view('ft_balance_of', {
+    account_id: bridge.accountId,
+  })) as string
+  let tx = await owner.callRaw(
+    bridge,
+    'withdraw_to',
+    { receiver: user.accountId, amount: bridgeBalance },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.true(
+    tx.logs.includes(
+      `Withdrawal of ${bridgeBalance} tokens to ${user.accountId} failed: amount is more than surplus`,
+    ),
+  )
+  t.is(
+    await token.view('ft_balance_of', { account_id: bridge.accountId }),
+    bridgeBalance,
+  )
+
+  const userBalanceBefore = (await token.view('ft_balance_of', {
+    account_id: user.accountId,
+  })) as string
+  tx = await owner.callRaw(
+    bridge,
+    'withdraw_to',
+    {
+      receiver: user.accountId,
+      amount: INITIAL_BRIDGE_BALANCE.toString(),
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.true(
+    tx.logs.includes(
+      `Withdrawn ${INITIAL_BRIDGE_BALANCE.toString()} tokens to ${
+        user.accountId
+      }`,
+    ),
+  )
+  t.is(
+    await token.view('ft_balance_of', { account_id: user.accountId }),
+    new BN(userBalanceBefore).add(INITIAL_BRIDGE_BALANCE).toString(),
+  )
+  t.is(
+    await token.view('ft_balance_of', { account_id: bridge.accountId }),
+    TRANSFER_AMOUNT.toString(),
+  )
+
+  // tokens of the finished send still back the bridged tokens
+  await owner.call(bridge, 'set_outbound_status', {
+    nonce: '0',
+    status: 'completed',
+  })
+  const liabilities = (await bridge.view('get_liabilities')) as any
+  t.is(liabilities.pending_outbound, '0')
+  t.is(liabilities.bridged_out, TRANSFER_AMOUNT.toString())
+  tx = await owner.callRaw(
+    bridge,
+    'withdraw_to',
+    { receiver: owner.accountId, amount: TRANSFER_AMOUNT.toString() },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
+  )
+  t.true(
+    tx.logs.includes(
+      `Withdrawal of ${TRANSFER_AMOUNT.toString()} tokens to ${owner.accountId} failed: amount is more than surplus`,
+    ),
+  )
   t.is(
-    await bridge.view('get_transactions_amount_by_user', {
-      user: tx.from_user,
-    }),
-    '2',
+    await token.view('ft_balance_of', { account_id: bridge.accountId }),
+    TRANSFER_AMOUNT.toString(),
   )
 })
 
-/*
- ------------------------------
- Administrative functions tests
- ------------------------------
-*/
-
-test('set_fee_numerator() is correct', async (t) => {
-  const { owner, token, bridge, user } = t.context.accounts
-  await owner.call(bridge, 'set_fee_numerator', { fee_numerator: 222 })
-  t.deepEqual(await bridge.view('get_fee_info', {}), [
-    owner.accountId,
-    222,
-    10000,
-  ])
-  const error = await t.throwsAsync(
-    user.call(bridge, 'set_fee_numerator', { fee_numerator: 1000 }),
-  )
-  t.is(
-    panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Only owner function',
+test('rescue_ft() is correct', async (t) => {
+  const { owner, token, wrongToken, bridge, user } = t.context.accounts
+  await user.call(
+    wrongToken,
+    'ft_transfer',
+    { receiver_id: bridge.accountId, amount: '500' },
+    { attachedDeposit: '1' },
   )
-})
+  const userBalanceBefore = (await wrongToken.view('ft_balance_of', {
+    account_id: user.accountId,
+  })) as string
 
-test('transfer_ownership() is correct', async (t) => {
-  const { owner, token, bridge, user } = t.context.accounts
-  await owner.call(bridge, 'transfer_ownership', { owner: user.accountId })
-  t.is(await bridge.view('get_owner', {}), user.accountId)
   let error = await t.throwsAsync(
-    owner.call(bridge, 'transfer_ownership', { owner: user.accountId }),
+    user.call(
+      bridge,
+      'rescue_ft',
+      { token: wrongToken.accountId, receiver: user.accountId, amount: '500' },
+      { attachedDeposit: '1' },
+    ),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
     'Smart contract panicked: Only owner function',
   )
   error = await t.throwsAsync(
-    user.call(bridge, 'transfer_ownership', { owner: user.accountId }),
+    owner.call(
+      bridge,
+      'rescue_ft',
+      { token: token.accountId, receiver: owner.accountId, amount: '500' },
+      { attachedDeposit: '1' },
+    ),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Current owner is equal to new owner',
+    "Smart contract panicked: Bridged token can't be rescued",
   )
-})
 
-test('set_relayer_role() is correct', async (t) => {
-  const { owner, token, bridge, user } = t.context.accounts
-  await owner.call(bridge, 'set_relayer_role', {
-    relayer: 'ed25519:ifRNRsDd85kNtGj4WRUm17vpqmymwswn8QzCwdJVnBT',
-  })
-  t.is(
-    await bridge.view('get_relayer_role', {}),
-    'ed25519:ifRNRsDd85kNtGj4WRUm17vpqmymwswn8QzCwdJVnBT',
+  const tx = await owner.callRaw(
+    bridge,
+    'rescue_ft',
+    { token: wrongToken.accountId, receiver: user.accountId, amount: '500' },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
   )
-  let error = await t.throwsAsync(
-    user.call(bridge, 'set_relayer_role', {
-      relayer: 'ed25519:DTRVwm7mmqCxfTZTFwi2kgp5vuYB3aiaB67vqcLXCpmh',
-    }),
+  t.true(
+    tx.logs.includes(
+      `Rescued 500 tokens of ${wrongToken.accountId} to ${user.accountId}`,
+    ),
   )
   t.is(
-    panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Only owner function',
-  )
-  error = await t.throwsAsync(
-    owner.call(bridge, 'set_relayer_role', { relayer: 'abc' }),
+    await wrongToken.view('ft_balance_of', { account_id: user.accountId }),
+    new BN(userBalanceBefore).add(new BN(500)).toString(),
   )
   t.is(
-    panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Not convertible to PublicKey type',
+    await wrongToken.view('ft_balance_of', { account_id: bridge.accountId }),
+    '0',
   )
-  error = await t.throwsAsync(
-    owner.call(bridge, 'set_relayer_role', {
-      relayer:
-        'secp256k1:qMoRgcoXai4mBPsdbHi1wfyxF9TdbPCF4qSDQTRP3TfescSRoUdSx6nmeQoN3aiwGzwMyGXAb1gUjBTv5AY8DXj',
+})
+
+test('upgrade is staged with a delay and can be cancelled', async (t) => {
+  const { owner, bridge, user } = t.context.accounts
+  t.is(await bridge.view('get_state_version'), 2)
+  t.is(await bridge.view('get_staged_upgrade'), null)
+  t.deepEqual(
+    await bridge.view('get_legacy_transactions_by_user', {
+      user: user.accountId,
     }),
+    [],
+  )
+
+  const code = readFileSync('../res/near_bridge_assist.wasm')
+  let error = await t.throwsAsync(
+    user.call(bridge, 'stage_upgrade', code, { attachedDeposit: '5 N' }),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: The only supported curve type for relayer role is ED25519',
+    'Smart contract panicked: Only owner function',
   )
   error = await t.throwsAsync(
-    owner.call(bridge, 'set_relayer_role', {
-      relayer: 'ed25519:ifRNRsDd85kNtGj4WRUm17vpqmymwswn8QzCwdJVnBT',
-    }),
+    owner.call(bridge, 'deploy_upgrade', {}, { gas: '300 Tgas' }),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Current relayer is equal to new relayer',
+    'Smart contract panicked: No upgrade is staged',
   )
-})
 
-test('set_limit_per_send is correct()', async (t) => {
-  const { owner, token, bridge, user } = t.context.accounts
-  await owner.call(bridge, 'set_limit_per_send', { limit_per_send: '666' })
-  t.is(await bridge.view('get_limit_per_send', {}), '666')
-  let error = await t.throwsAsync(
-    user.call(bridge, 'set_limit_per_send', { limit_per_send: '500' }),
+  await owner.call(bridge, 'stage_upgrade', code, { attachedDeposit: '5 N' })
+  const stagedUpgrade = (await bridge.view('get_staged_upgrade')) as any
+  t.is(stagedUpgrade.code_hash, createHash('sha256').update(code).digest('hex'))
+
+  error = await t.throwsAsync(
+    owner.call(bridge, 'deploy_upgrade', {}, { gas: '300 Tgas' }),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Only owner function',
+    'Smart contract panicked: Upgrade delay has not passed yet',
   )
   error = await t.throwsAsync(
-    owner.call(bridge, 'set_limit_per_send', { limit_per_send: '666' }),
+    owner.call(bridge, 'stage_upgrade', code, { attachedDeposit: '5 N' }),
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Current limit is equal to new limit',
+    'Smart contract panicked: Upgrade is already staged',
   )
-})
-
-const INITIAL_BRIDGE_BALANCE = new BN(10000).mul(
-  new BN(10).pow(new BN(DECIMALS)),
-)
 
-test('withdraw() is correct', async (t) => {
-  const { owner, token, bridge, user } = t.context.accounts
-  await owner.callRaw(
-    token,
-    'ft_transfer',
-    {
-      receiver_id: bridge.accountId,
-      amount: INITIAL_BRIDGE_BALANCE.toString(),
-    },
-    { attachedDeposit: '1' },
+  const tx = await owner.callRaw(bridge, 'cancel_upgrade', {})
+  t.is(
+    tx.logs[0],
+    `Upgrade with code hash ${stagedUpgrade.code_hash} is cancelled`,
   )
+  t.is(await bridge.view('get_staged_upgrade'), null)
+})
 
-  const bridgeBalanceBefore = (await token.view('ft_balance_of', {
-    account_id: bridge.accountId,
-  })) as string
-  const ownerBalanceBefore = (await token.view('ft_balance_of', {
-    account_id: owner.accountId,
-  })) as string
+test('state of the baseline contract is kept by upgrades', async (t) => {
+  const { owner, token, user, relayer } = t.context.accounts
+  const { worker } = t.context
+  const bridge = await owner.devDeploy('../res/near_bridge_assist_v1.wasm', {
+    initialBalance: NEAR.parse('100 N').toJSON(),
+    method: 'init',
+    args: {
+      owner: owner.accountId,
+      relayer_role: (await relayer.getKey())?.getPublicKey().toString(),
+      token: token.accountId,
+      fee_wallet: owner.accountId,
+      limit_per_send: LIMIT_PER_SEND.toString(),
+      fee_numerator: FEE_NUMERATOR,
+    },
+  })
+  await registerUser(token, bridge)
+  const payInfo = (await bridge.view('get_storage_paid_info', {
+    user: user.accountId,
+  })) as string[]
+  const payForLegacyChain = (await bridge.view(
+    'get_pay_for_add_chain',
+  )) as string
   await owner.call(
     bridge,
-    'withdraw',
-    { amount: '800' },
-    { attachedDeposit: '1' },
+    'add_chain',
+    { chain: CHAIN },
+    { attachedDeposit: payForLegacyChain },
   )
-  t.is(
-    (await token.view('ft_balance_of', {
-      account_id: bridge.accountId,
-    })) as string,
-    new BN(bridgeBalanceBefore).sub(new BN(800)).toString(),
+  await user.call(
+    bridge,
+    'storage_deposit',
+    {},
+    { attachedDeposit: new BN(payInfo[2]).add(new BN(payInfo[3])) },
   )
-  t.is(
-    (await token.view('ft_balance_of', {
-      account_id: owner.accountId,
-    })) as string,
-    new BN(ownerBalanceBefore).add(new BN(800)).toString(),
+  await user.callRaw(
+    token,
+    'ft_transfer_call',
+    {
+      receiver_id: bridge.accountId,
+      amount: TRANSFER_AMOUNT.toString(),
+      msg: ETH_ADDR + CHAIN,
+    },
+    { attachedDeposit: '1', gas: GAS_REQUIRED },
   )
+  const transactions = await bridge.view('get_transactions_by_user', {
+    user: user.accountId,
+  })
+  t.is((transactions as any[]).length, 1)
+  const userInfo = (await bridge.view('get_storage_paid_info', {
+    user: user.accountId,
+  })) as any[]
+  const feeInfo = await bridge.view('get_fee_info')
+  const nonce = await bridge.view('get_nonce')
+  const chains = await bridge.view('supported_chain_list')
 
-  const error = await t.throwsAsync(
-    user.call(bridge, 'withdraw', { amount: '800' }, { attachedDeposit: '1' }),
+  // baseline contract can't stage upgrades, its code is deployed with the key
+  const code = readFileSync('../res/near_bridge_assist.wasm')
+  await bridge.deploy(code)
+  await bridge.call(bridge, 'migrate', {}, { gas: '300 Tgas' })
+  t.is(await bridge.view('get_state_version'), 2)
+
+  // later upgrades are staged and deployed after the delay
+  await owner.call(bridge, 'stage_upgrade', code, { attachedDeposit: '5 N' })
+  const deployAfter = BigInt(
+    ((await bridge.view('get_staged_upgrade')) as any).deploy_after,
+  )
+  const blockTime = async () =>
+    BigInt(
+      (await worker.provider.block({ finality: 'final' })).header
+        .timestamp_nanosec,
+    ) / BigInt(1_000_000_000)
+  while ((await blockTime()) <= deployAfter) {
+    await worker.provider.fastForward(10000)
+  }
+  await owner.call(bridge, 'deploy_upgrade', {}, { gas: '300 Tgas' })
+  t.is(await bridge.view('get_staged_upgrade'), null)
+  t.is(await bridge.view('get_state_version'), 2)
+
+  t.deepEqual(
+    await bridge.view('get_legacy_transactions_by_user', {
+      user: user.accountId,
+    }),
+    transactions,
   )
-  t.is(
-    panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Only owner function',
+  // storage estimates are measured again, paid storage is kept
+  const upgradedInfo = (await bridge.view('get_storage_paid_info', {
+    user: user.accountId,
+  })) as any[]
+  t.true(upgradedInfo[0])
+  t.is(upgradedInfo[1], userInfo[1])
+  t.is(upgradedInfo[5], userInfo[5])
+  t.deepEqual(await bridge.view('get_fee_info'), feeInfo)
+  t.deepEqual(await bridge.view('get_nonce'), nonce)
+  t.deepEqual(await bridge.view('supported_chain_list'), chains)
+  t.deepEqual(
+    await bridge.view('storage_balance_of', { account_id: user.accountId }),
+    {
+      total: new BN(upgradedInfo[2]).add(new BN(userInfo[1])).toString(),
+      available: userInfo[1],
+    },
   )
 })
 
@@ -1395,6 +4938,136 @@ test('add_chain()/remove_chain() is correct', async (t) => {
   )
 })
 
+test('set_chain_address_format() validates recipients', async (t) => {
+  const { owner, token, bridge, user } = t.context.accounts
+  const SOLANA_ADDR = 'So11111111111111111111111111111111111111112'
+  const COSMOS_ADDR = 'cosmos1qqqsyqcyq5rqwzqfpg9scrgwpugpzysnrk363e'
+
+  t.is(await bridge.view('get_chain_address_format', { chain: CHAIN }), 'evm')
+  let error = await t.throwsAsync(
+    user.call(bridge, 'set_chain_address_format', {
+      chain: CHAIN,
+      format: 'solana',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Only owner function',
+  )
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_chain_address_format', {
+      chain: 'SOL',
+      format: 'solana',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Chain is not in the list yet',
+  )
+
+  for (const chain of ['SOL', 'ATOM']) {
+    await owner.call(
+      bridge,
+      'add_chain',
+      { chain },
+      { attachedDeposit: payForAddChain },
+    )
+  }
+  error = await t.throwsAsync(
+    owner.call(bridge, 'set_chain_address_format', {
+      chain: 'SOL',
+      format: 'solana',
+    }),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Not enough NEAR attached',
+  )
+  error = await t.throwsAsync(
+    owner.call(
+      bridge,
+      'set_chain_address_format',
+      { chain: 'ATOM', format: { cosmos: { hrp: 'Cosmos' } } },
+      { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+    ),
+  )
+  t.is(
+    panicMessageFromThrowsAsync(error),
+    'Smart contract panicked: Human readable part should be lowercase letters',
+  )
+  await owner.call(
+    bridge,
+    'set_chain_address_format',
+    { chain: 'SOL', format: 'solana' },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  await owner.call(
+    bridge,
+    'set_chain_address_format',
+    { chain: 'ATOM', format: { cosmos: { hrp: 'cosmos' } } },
+    { attachedDeposit: NEAR.parse('0.01 N').toJSON() },
+  )
+  t.is(
+    await bridge.view('get_chain_address_format', { chain: 'SOL' }),
+    'solana',
+  )
+  t.deepEqual(
+    await bridge.view('get_chain_address_format', { chain: 'ATOM' }),
+    { cosmos: { hrp: 'cosmos' } },
+  )
+
+  await storageDeposit(
+    user,
+    bridge,
+    payForRegister.add(payForFtOnTransfer.mul(new BN(2))),
+  )
+  const send = (msg: string) =>
+    user.callRaw(
+      token,
+      'ft_transfer_call',
+      {
+        receiver_id: bridge.accountId,
+        amount: TRANSFER_AMOUNT.toString(),
+        msg,
+      },
+      { attachedDeposit: '1', gas: GAS_REQUIRED },
+    )
+
+  let tx = await send(JSON.stringify({ recipient: ETH_ADDR, chain: 'SOL' }))
+  t.is(tx.logs[1], 'PANIC: Recipient is not a valid SOL address')
+  tx = await send(JSON.stringify({ recipient: SOLANA_ADDR, chain: CHAIN }))
+  t.is(tx.logs[1], `PANIC: Recipient is not a valid ${CHAIN} address`)
+  tx = await send(
+    JSON.stringify({
+      recipient: COSMOS_ADDR.slice(0, -1) + 'f',
+      chain: 'ATOM',
+    }),
+  )
+  t.is(tx.logs[1], 'PANIC: Recipient is not a valid ATOM address')
+
+  tx = await send(JSON.stringify({ recipient: SOLANA_ADDR, chain: 'SOL' }))
+  t.is(
+    tx.logs[1],
+    `Sent ${TRANSFER_AMOUNT.toString()} tokens from ${
+      user.accountId
+    } to ${SOLANA_ADDR} in direction NEAR->SOL`,
+  )
+  tx = await send(JSON.stringify({ recipient: COSMOS_ADDR, chain: 'ATOM' }))
+  t.is(
+    tx.logs[1],
+    `Sent ${TRANSFER_AMOUNT.toString()} tokens from ${
+      user.accountId
+    } to ${COSMOS_ADDR} in direction NEAR->ATOM`,
+  )
+
+  // setting the default format back removes the entry
+  await owner.call(bridge, 'set_chain_address_format', {
+    chain: 'SOL',
+    format: 'evm',
+  })
+  t.is(await bridge.view('get_chain_address_format', { chain: 'SOL' }), 'evm')
+})
+
 test('withdraw_native_fee() is correct', async (t) => {
   const { owner, token, bridge, user } = t.context.accounts
   await user.call(
@@ -1413,7 +5086,7 @@ test('withdraw_native_fee() is correct', async (t) => {
   )
   t.is(
     panicMessageFromThrowsAsync(error),
-    'Smart contract panicked: Left contract balance is less than users total storage paid',
+    'Smart contract panicked: Left contract balance is less than reserved NEAR',
   )
   error = await t.throwsAsync(
     owner.call(
@@ -0,0 +1,206 @@
+use crate::*;
+use near_sdk::base64;
+
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const BECH32_MAX_LENGTH: usize = 90;
+// Longest recipient of all formats, it's bech32 one
+pub(crate) const MAX_ADDRESS_LENGTH: usize = BECH32_MAX_LENGTH;
+const BECH32_CHECKSUM_LENGTH: usize = 6;
+const TON_FRIENDLY_ADDRESS_LENGTH: usize = 48;
+const TON_BOUNCEABLE_TAG: u8 = 0x11;
+const TON_NON_BOUNCEABLE_TAG: u8 = 0x51;
+const TON_TESTNET_FLAG: u8 = 0x80;
+
+// Format of recipient addresses on a destination chain, chains without a format are EVM ones
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFormat {
+    // 0x followed by 40 hexadecimal characters
+    Evm,
+    // Base58 of 32 bytes public key
+    Solana,
+    // Bech32 of 20 or 32 bytes with the human readable part of the chain, e.g. "cosmos"
+    Cosmos { hrp: String },
+    // Segwit bech32/bech32m or legacy base58check P2PKH/P2SH address
+    Bitcoin { testnet: bool },
+    // Raw "workchain:hex" or 48 characters user-friendly base64 address
+    Ton,
+}
+
+impl AddressFormat {
+    pub(crate) fn is_valid(&self, address: &str) -> bool {
+        match self {
+            AddressFormat::Evm => is_valid_evm_address(address),
+            AddressFormat::Solana => is_valid_solana_address(address),
+            AddressFormat::Cosmos { hrp } => is_valid_cosmos_address(address, hrp),
+            AddressFormat::Bitcoin { testnet } => {
+                is_valid_segwit_address(address, *testnet)
+                    || is_valid_base58check_address(address, *testnet)
+            }
+            AddressFormat::Ton => {
+                is_valid_ton_raw_address(address) || is_valid_ton_friendly_address(address)
+            }
+        }
+    }
+}
+
+fn is_valid_evm_address(address: &str) -> bool {
+    address.len() == ETH_ADDRESS_LENGTH as usize
+        && address.starts_with("0x")
+        && address[2..].bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn is_valid_solana_address(address: &str) -> bool {
+    bs58::decode(address)
+        .into_vec()
+        .is_ok_and(|bytes| bytes.len() == 32)
+}
+
+fn is_valid_cosmos_address(address: &str, expected_hrp: &str) -> bool {
+    let Some((hrp, data, checksum_const)) = bech32_decode(address) else {
+        return false;
+    };
+    hrp == expected_hrp
+        && checksum_const == BECH32_CONST
+        && convert_bits(&data, 5, 8).is_some_and(|bytes| bytes.len() == 20 || bytes.len() == 32)
+}
+
+fn is_valid_segwit_address(address: &str, testnet: bool) -> bool {
+    let Some((hrp, data, checksum_const)) = bech32_decode(address) else {
+        return false;
+    };
+    if hrp != if testnet { "tb" } else { "bc" } {
+        return false;
+    }
+    let Some((&version, program)) = data.split_first() else {
+        return false;
+    };
+    let Some(program) = convert_bits(program, 5, 8) else {
+        return false;
+    };
+    match version {
+        0 => checksum_const == BECH32_CONST && (program.len() == 20 || program.len() == 32),
+        1..=16 => checksum_const == BECH32M_CONST && (2..=40).contains(&program.len()),
+        _ => false,
+    }
+}
+
+fn is_valid_base58check_address(address: &str, testnet: bool) -> bool {
+    let Ok(bytes) = bs58::decode(address).into_vec() else {
+        return false;
+    };
+    if bytes.len() != 25 {
+        return false;
+    }
+    let (payload, checksum) = bytes.split_at(21);
+    let versions: [u8; 2] = if testnet { [0x6f, 0xc4] } else { [0x00, 0x05] };
+    versions.contains(&payload[0]) && env::sha256(&env::sha256(payload))[..4] == *checksum
+}
+
+fn is_valid_ton_raw_address(address: &str) -> bool {
+    let Some((workchain, hash)) = address.split_once(':') else {
+        return false;
+    };
+    (workchain == "0" || workchain == "-1")
+        && hash.len() == 64
+        && hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn is_valid_ton_friendly_address(address: &str) -> bool {
+    if address.len() != TON_FRIENDLY_ADDRESS_LENGTH {
+        return false;
+    }
+    // Both standard and url-safe alphabets are used by wallets
+    let Ok(bytes) = base64::decode(address.replace('-', "+").replace('_', "/")) else {
+        return false;
+    };
+    if bytes.len() != 36 {
+        return false;
+    }
+    let tag = bytes[0] & !TON_TESTNET_FLAG;
+    (tag == TON_BOUNCEABLE_TAG || tag == TON_NON_BOUNCEABLE_TAG)
+        && (bytes[1] == 0x00 || bytes[1] == 0xff)
+        && crc16_xmodem(&bytes[..34]).to_be_bytes() == bytes[34..]
+}
+
+// Human readable part, 5-bit data without checksum and the checksum constant it matched
+fn bech32_decode(address: &str) -> Option<(String, Vec<u8>, u32)> {
+    if address.len() > BECH32_MAX_LENGTH
+        || !address.bytes().all(|byte| (33..=126).contains(&byte))
+        || (address.bytes().any(|byte| byte.is_ascii_lowercase())
+            && address.bytes().any(|byte| byte.is_ascii_uppercase()))
+    {
+        return None;
+    }
+    let address = address.to_ascii_lowercase();
+    let separator = address.rfind('1')?;
+    if separator == 0 || address.len() - separator - 1 < BECH32_CHECKSUM_LENGTH {
+        return None;
+    }
+    let hrp = &address[..separator];
+    let data = address[separator + 1..]
+        .bytes()
+        .map(|byte| BECH32_CHARSET.iter().position(|&c| c == byte).map(|value| value as u8))
+        .collect::<Option<Vec<u8>>>()?;
+
+    let mut values: Vec<u8> = hrp.bytes().map(|byte| byte >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|byte| byte & 31));
+    values.extend(&data);
+    let checksum_const = bech32_polymod(&values);
+    if checksum_const != BECH32_CONST && checksum_const != BECH32M_CONST {
+        return None;
+    }
+    Some((
+        hrp.to_string(),
+        data[..data.len() - BECH32_CHECKSUM_LENGTH].to_vec(),
+        checksum_const,
+    ))
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    values.iter().fold(1u32, |checksum, &value| {
+        let top = checksum >> 25;
+        let checksum = ((checksum & 0x01ff_ffff) << 5) ^ value as u32;
+        (0..5)
+            .filter(|i| (top >> i) & 1 == 1)
+            .fold(checksum, |checksum, i| checksum ^ GENERATOR[i])
+    })
+}
+
+// Regroups bits without padding, None if the leftover bits aren't zero padding
+fn convert_bits(data: &[u8], from: u32, to: u32) -> Option<Vec<u8>> {
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let mut result = Vec::new();
+    let max_value = (1u32 << to) - 1;
+    let max_accumulator = (1u32 << (from + to - 1)) - 1;
+    for &value in data {
+        accumulator = ((accumulator << from) | value as u32) & max_accumulator;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((accumulator >> bits) & max_value) as u8);
+        }
+    }
+    if bits >= from || (accumulator << (to - bits)) & max_value != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+fn crc16_xmodem(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
@@ -0,0 +1,74 @@
+use crate::*;
+use near_sdk::IntoStorageKey;
+use std::marker::PhantomData;
+
+// Append-only list of a user's transactions, the oldest ones can be removed from its head
+// without moving the rest. Elements are stored under the prefix followed by u64 position, as
+// in Vector, and indexes are counted from the head
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct HistoryList<T> {
+    prefix: Vec<u8>,
+    head: u64,
+    tail: u64,
+    #[borsh_skip]
+    element: PhantomData<T>,
+}
+
+impl<T: BorshSerialize + BorshDeserialize> HistoryList<T> {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self {
+            prefix: prefix.into_storage_key(),
+            head: 0,
+            tail: 0,
+            element: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    pub fn push(&mut self, element: &T) {
+        env::storage_write(&self.element_key(self.tail), &element.try_to_vec().unwrap());
+        self.tail += 1;
+    }
+
+    pub fn get(&self, index: u64) -> Option<T> {
+        if index >= self.len() {
+            return None;
+        }
+        Some(self.read(self.head + index))
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = T> + '_ {
+        (self.head..self.tail).map(|position| self.read(position))
+    }
+
+    // Removes up to `count` oldest elements
+    pub fn remove_first(&mut self, count: u64) {
+        let end = self.head + count.min(self.len());
+        for position in self.head..end {
+            env::storage_remove(&self.element_key(position));
+        }
+        self.head = end;
+    }
+
+    pub fn clear(&mut self) {
+        self.remove_first(self.len());
+    }
+
+    fn read(&self, position: u64) -> T {
+        let element = env::storage_read(&self.element_key(position))
+            .unwrap_or_else(|| env::panic_str("History list is inconsistent"));
+        T::try_from_slice(&element)
+            .unwrap_or_else(|_| env::panic_str("History list element is corrupted"))
+    }
+
+    fn element_key(&self, position: u64) -> Vec<u8> {
+        [self.prefix.as_slice(), &position.to_le_bytes()].concat()
+    }
+}
@@ -1,10 +1,17 @@
+use address::{AddressFormat, MAX_ADDRESS_LENGTH};
 use ed25519_dalek::{PublicKey as ed25519_dalek_PublicKey, Verifier};
+use history::HistoryList;
+use migration::{read_old_state, read_state_version, write_state_version, STATE_VERSION};
 use near_contract_standards::fungible_token::core::ext_ft_core;
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
-use near_contract_standards::storage_management::StorageBalance;
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+use near_contract_standards::non_fungible_token::{Token, TokenId};
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::{LookupMap, LookupSet, UnorderedSet, Vector};
-use near_sdk::json_types::{U128, U64};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     assert_one_yocto, bs58, env, ext_contract, near_bindgen, AccountId, Balance,
@@ -12,19 +19,73 @@ use near_sdk::{
     PromiseOrValue, PromiseResult, PublicKey, StorageUsage,
 };
 
+mod address;
+mod history;
+mod migration;
+
 #[ext_contract(token_storage)]
 pub trait ExtTokenStorage {
     fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance>;
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance;
+}
+
+#[ext_contract(nft_contract)]
+pub trait ExtNftContract {
+    fn nft_token(&self, token_id: TokenId) -> Option<Token>;
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    );
 }
 
 const GAS_FOR_FULFILL: Gas = Gas(80_000_000_000_000);
+const GAS_FOR_FULFILL_WITH_REGISTRATION: Gas = Gas(120_000_000_000_000);
+const GAS_FOR_FULFILL_WITH_CALL: Gas = Gas(200_000_000_000_000);
+const GAS_FOR_RESOLVE_MESSAGE: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_RESOLVE_FEE_TRANSFER: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_WITHDRAW: Gas = Gas(50_000_000_000_000);
+const GAS_FOR_DEPLOY_UPGRADE: Gas = Gas(150_000_000_000_000);
+const GAS_FOR_MIGRATE: Gas = Gas(50_000_000_000_000);
 const CURRENT_CHAIN: &str = "NEAR";
 const MAX_ACCOUNT_ID_LENGTH: u8 = 64;
 const ETH_ADDRESS_LENGTH: u8 = 42;
 const FEE_DENOMINATOR: u16 = 10000;
 const MIN_TOKEN_STORAGE_DEPOSIT: u128 = 1250000000000000000000;
+// Covers storage of a new implicit account with its full access key
+const MIN_IMPLICIT_ACCOUNT_FUNDING: u128 = 1820000000000000000000;
+const IMPLICIT_ACCOUNT_ID_LENGTH: usize = 64;
+const DEFAULT_RETENTION_PERIOD: u64 = 30 * 24 * 60 * 60;
+const DEFAULT_DISPUTE_WINDOW: u64 = 24 * 60 * 60;
+const DEFAULT_UNBONDING_PERIOD: u64 = 7 * 24 * 60 * 60;
+// Bytes NEAR charges for every storage record in addition to its key and value
+const STORAGE_RECORD_OVERHEAD: u64 = 40;
+const DEFAULT_PAGE_LIMIT: u64 = 50;
+// History views read at most this many records per call, filtered ones return a cursor to go on
+const MAX_SCANNED_RECORDS: u64 = 200;
+const UPGRADE_DELAY: u64 = 24 * 60 * 60;
+const IDEMPOTENCY_WINDOW: u64 = 24 * 60 * 60;
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 64;
+// Prepended to native transactions before hashing, so they can't be fulfilled as token ones
+const CHECKPOINT_LEAF_DOMAIN: &str = "CHECKPOINT_LEAF";
+const NATIVE_DOMAIN: &str = "NATIVE";
+const NFT_DOMAIN: &str = "NFT";
+const INTENT_DOMAIN: &str = "INTENT";
+// Msg of ft_transfer_call that deposits liquidity of a fast relayer
+const FAST_LIQUIDITY_MSG: &str = "fast_liquidity";
+const BOND_DOMAIN: &str = "BOND";
+// In bytes, payload is sent as 0x-prefixed hex
+const MAX_PAYLOAD_LENGTH: usize = 1024;
+// Staged wasm is kept under its own key, so it isn't read with the contract state
+const STAGED_CODE_KEY: &[u8] = b"STAGED_CODE";
 
-#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Transaction {
     from_user: String,
@@ -36,13 +97,428 @@ pub struct Transaction {
     nonce: U128,
 }
 
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Pending,
+    Completed,
+    Refunded,
+    RolledBack,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionDirection {
+    Outbound,
+    Inbound,
+}
+
+// Kind of a relayer signed transaction, each kind has its own hash domain and nonce sequence
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Token,
+    Native,
+    Nft,
+}
+
+// NFT locked on NEAR or unlocked from it, the token is identified by its contract and id
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftTransaction {
+    from_user: String,
+    to_user: String,
+    nft_contract: AccountId,
+    token_id: TokenId,
+    // Hex sha256 of JSON metadata of the token
+    metadata_hash: String,
+    timestamp: U64,
+    from_chain: String,
+    to_chain: String,
+    nonce: U128,
+}
+
+// Transaction sent from NEAR, it's pending until the owner marks how it ended on destination chain
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OutboundRecord {
+    #[serde(flatten)]
+    transaction: Transaction,
+    status: TransactionStatus,
+    // Idempotency key entry of the send, it's removed with the record
+    #[serde(skip)]
+    idempotency_hash: Option<CryptoHash>,
+    // Stored separately by nonce and set only in views
+    #[borsh_skip]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    // Set only in views, true if NEAR is sent instead of the token
+    #[borsh_skip]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    native: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    FeeWalletNotRegistered,
+    RecipientStorageCheckFailed,
+    RecipientRegistrationFailed,
+    TransferFailed,
+}
+
+// Transaction fulfilled on NEAR, it's pending while tokens are being dispensed
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InboundRecord {
+    #[serde(flatten)]
+    transaction: Transaction,
+    status: TransactionStatus,
+    // Block timestamp of the fulfill call in seconds
+    fulfilled_at: U64,
+    fee: U128,
+    // 0 until tokens are dispensed
+    dispensed_amount: U128,
+    failure_reason: Option<FailureReason>,
+    // Caller who paid the record storage with attached deposit, None if the recipient paid it
+    storage_payer: Option<AccountId>,
+    // Set only in views, true if NEAR is released instead of the token
+    #[borsh_skip]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    native: bool,
+}
+
+// Contract call the relayer signs together with inbound transaction, it's made after tokens
+// are dispensed
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MessageCall {
+    receiver_id: AccountId,
+    method_name: String,
+    args: Base64VecU8,
+    gas: U64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InboundMessage {
+    #[serde(flatten)]
+    call: MessageCall,
+    status: MessageStatus,
+}
+
+// Inbound transaction delivered to a contract recipient with ft_transfer_call
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferCall {
+    msg: String,
+    // Unused amount the recipient returned, it's sent to the recipient with claim_refunded
+    refunded_amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FulfillInfo {
+    status: TransactionStatus,
+    fulfilled_at: U64,
+    recipient: String,
+    dispensed_amount: U128,
+    fee: U128,
+    failure_reason: Option<FailureReason>,
+    transfer_call: Option<TransferCall>,
+    message: Option<InboundMessage>,
+}
+
+// Lifetime totals of transfers between NEAR and one chain, inbound ones count once dispensed
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChainStats {
+    sent_volume: U128,
+    sent_count: U64,
+    received_volume: U128,
+    received_count: U64,
+}
+
+impl Default for ChainStats {
+    fn default() -> Self {
+        Self {
+            sent_volume: U128(0),
+            sent_count: U64(0),
+            received_volume: U128(0),
+            received_count: U64(0),
+        }
+    }
+}
+
+// Lifetime volume sent from and received to one NEAR account
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UserVolume {
+    sent: U128,
+    received: U128,
+}
+
+impl Default for UserVolume {
+    fn default() -> Self {
+        Self {
+            sent: U128(0),
+            received: U128(0),
+        }
+    }
+}
+
+// Bridged tokens the owner can't withdraw as they back users' transfers
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Liabilities {
+    pending_outbound: U128,
+    bridged_out: U128,
+    pending_inbound: U128,
+    withdrawals_in_flight: U128,
+    unclaimed_refunds: U128,
+    escrowed: U128,
+    fast_liquidity: U128,
+    relayer_bonds: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BridgeStats {
+    outbound_count: U64,
+    inbound_count: U64,
+    total_fees_collected: U128,
+}
+
+// Records of a filtered history view, next_index is the from_index to continue the scan from,
+// None if the scan reached the end of the list
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransactionPage<T> {
+    records: Vec<T>,
+    next_index: Option<U64>,
+}
+
+// Conditions for transaction history views, every field that is set should match
+#[derive(Deserialize, Serialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransactionFilter {
+    from_chain: Option<String>,
+    to_chain: Option<String>,
+    min_amount: Option<U128>,
+    max_amount: Option<U128>,
+    from_timestamp: Option<U64>,
+    to_timestamp: Option<U64>,
+    status: Option<TransactionStatus>,
+}
+
+impl TransactionFilter {
+    fn matches(&self, tx: &Transaction, status: TransactionStatus) -> bool {
+        self.status.map_or(true, |expected| status == expected)
+            && self.from_chain.as_ref().map_or(true, |chain| &tx.from_chain == chain)
+            && self.to_chain.as_ref().map_or(true, |chain| &tx.to_chain == chain)
+            && self.min_amount.map_or(true, |amount| tx.amount.0 >= amount.0)
+            && self.max_amount.map_or(true, |amount| tx.amount.0 <= amount.0)
+            && self.from_timestamp.map_or(true, |timestamp| tx.timestamp.0 >= timestamp.0)
+            && self.to_timestamp.map_or(true, |timestamp| tx.timestamp.0 <= timestamp.0)
+    }
+}
+
+// Signed by the relayer role, attests all transfers from `chain` in the block range
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Checkpoint {
+    chain: String,
+    from_block: U64,
+    to_block: U64,
+    root: String,
+}
+
+// JSON form of ft_transfer_call msg, the legacy one is ETH address followed by chain name
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SendMessage {
+    recipient: String,
+    chain: String,
+    // Repeated send with the same key of the same sender is rejected within IDEMPOTENCY_WINDOW
+    idempotency_key: Option<String>,
+    // 0x-prefixed hex passed to the destination chain with the tokens
+    payload: Option<String>,
+}
+
+// JSON form of ft_transfer_call msg that escrows tokens for intents signed by intent_key
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(deny_unknown_fields)]
+pub struct EscrowMessage {
+    intent_key: PublicKey,
+}
+
+// JSON form of ft_transfer_call msg that bonds tokens to the relayer key bonded by the sender
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(deny_unknown_fields)]
+pub struct RelayerBondMessage {
+    relayer_bond: PublicKey,
+}
+
+// Tokens of a user that relayers can send on the user's signed intents
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Escrow {
+    balance: U128,
+    // Ed25519 key signing intents, None for escrows credited only with relayer fees
+    intent_key: Option<PublicKey>,
+    // Nonce the next intent should have
+    intent_nonce: U64,
+}
+
+// Send signed by the sender's intent key, so a relayer can submit it and pay the gas
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SendIntent {
+    sender: AccountId,
+    recipient: String,
+    chain: String,
+    amount: U128,
+    // Paid from escrow of the sender to escrow of the relayer in addition to amount
+    relayer_fee: U128,
+    nonce: U64,
+    // Unix timestamp in seconds the intent can't be submitted after
+    deadline: U64,
+}
+
+// Relayer paying fulfills from its own liquidity before the transaction is fully confirmed
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FastRelayer {
+    // Bridged tokens available for fronting
+    liquidity: U128,
+    // NEAR slashed if a fronted transaction doesn't match the canonical one
+    bond: U128,
+    // Fronted transactions not settled yet, the bond can't be withdrawn while there are any
+    unsettled: U64,
+}
+
+// Transaction fronted by a fast relayer, it's keyed by source chain and nonce
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FastFulfill {
+    tx_hash: String,
+    relayer: AccountId,
+    // Tokens paid to the recipient from relayer liquidity
+    paid: U128,
+    // NEAR covering storage of this entry and the fulfilled hash on settlement
+    storage_deposit: U128,
+    // False while ft_transfer to the recipient is in flight
+    confirmed: bool,
+}
+
+// Stake bonded to a relayer signing key by its owner, all of it including the unbonding part is
+// slashed on fraud proof of the key
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RelayerBond {
+    owner: AccountId,
+    near: U128,
+    tokens: U128,
+    unbonding_near: U128,
+    unbonding_tokens: U128,
+    // Unix timestamp in seconds the unbonding part can be withdrawn at
+    unbonding_at: U64,
+}
+
+// Relayer key that signed a fulfilled transaction, its bond is bound to the transaction for the
+// dispute window
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Attestation {
+    tx_hash: String,
+    signer: PublicKey,
+    fulfilled_at: U64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimStatus {
+    Pending,
+    Challenged,
+}
+
+// Relayer signed or merkle proven transaction of an optimistic chain, it's dispensed by finalize
+// after the challenge window
+#[derive(BorshDeserialize, BorshSerialize, Deserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OptimisticClaim {
+    transaction: Transaction,
+    register_recipient: Option<bool>,
+    msg: Option<String>,
+    call: Option<MessageCall>,
+    relayer: AccountId,
+    // Relayer key that signed the transaction, None if it's claimed with merkle proof
+    signer: Option<PublicKey>,
+    // NEAR of the relayer covering storage of this claim, it's returned when it's finalized
+    storage_deposit: U128,
+    // Unix timestamp in seconds the claim can be finalized at
+    finalize_after: U64,
+    status: ClaimStatus,
+    challenger: Option<AccountId>,
+    challenge_bond: U128,
+}
+
+// New contract code waiting for the upgrade delay to pass
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StagedUpgrade {
+    // Hex sha256 of the staged wasm
+    code_hash: String,
+    deploy_after: U64,
+}
+
+// State of one fulfill passed through its promise callbacks
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FulfillContext {
+    tx_hash: String,
+    to_user: AccountId,
+    fee: u128,
+    // Storage cost used by the fulfill call plus a reserve for the callbacks
+    storage_paid_for_call: Balance,
+    // Storage cost actually used by the fulfill call, the rest is returned when it's resolved
+    storage_used: Balance,
+    // Caller who paid storage with attached deposit, None if it's taken from to_user storage paid
+    storage_payer: Option<AccountId>,
+    // Reserved for registering to_user on token and not spent yet, 0 if registration isn't needed
+    registration_deposit: Balance,
+    // Reserved for funding implicit to_user with NEAR, it's spent only if the fulfill registers it
+    funding_deposit: Balance,
+    // Storage taken by a new inbound record, it's kept after rollback so it isn't refunded
+    record_storage_cost: Balance,
+    // Signed msg of ft_transfer_call to the recipient, None for plain ft_transfer
+    msg: Option<String>,
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
-struct BridgeAssist {
+pub struct BridgeAssist {
     bytes_for_register: StorageUsage,
     bytes_for_ft_on_transfer: StorageUsage,
     bytes_for_fulfill: StorageUsage,
     bytes_for_add_chain: StorageUsage,
+    bytes_for_checkpoint: StorageUsage,
     owner: AccountId,
     relayer_role: PublicKey,
     token: AccountId,
@@ -50,11 +526,93 @@ struct BridgeAssist {
     limit_per_send: Balance,
     nonce: U128,
     fee_numerator: u16,
-    transactions: LookupMap<String, Vector<Transaction>>,
+    retention_period: u64,
+    outbound_by_user: LookupMap<AccountId, HistoryList<u128>>,
+    outbound_transactions: LookupMap<u128, OutboundRecord>,
+    outbound_by_hash: LookupMap<String, u128>,
+    inbound_transactions: LookupMap<String, InboundRecord>,
+    inbound_by_user: LookupMap<AccountId, HistoryList<String>>,
     fulfilled: LookupSet<String>,
     storage_paid: LookupMap<AccountId, Balance>,
     total_storage_paid: Balance,
+    registration_pool: Balance,
     available_chains: UnorderedSet<String>,
+    checkpoints: LookupMap<String, Checkpoint>,
+    // Last block covered by checkpoints by chain, next checkpoints have to start after it
+    last_checkpoint_block: LookupMap<String, u64>,
+    chain_stats: LookupMap<String, ChainStats>,
+    user_volumes: LookupMap<AccountId, UserVolume>,
+    outbound_count: u64,
+    inbound_count: u64,
+    total_fees_collected: Balance,
+    pending_outbound_amount: Balance,
+    pending_inbound_amount: Balance,
+    withdrawals_in_flight: Balance,
+    // Tokens of finished outbound sends, they back the bridged tokens on other chains whatever
+    // status the send is given and are released only by inbound dispenses
+    bridged_out_amount: Balance,
+    staged_upgrade: Option<StagedUpgrade>,
+    // Sends made before records were split by direction, by user. They are only read
+    legacy_transactions: LookupMap<String, Vector<Transaction>>,
+    // Nonce of the last send by sha256 of sender and idempotency key
+    idempotency_keys: LookupMap<CryptoHash, u128>,
+    // Recipient address format by chain, chains without an entry use AddressFormat::Evm
+    chain_address_formats: LookupMap<String, AddressFormat>,
+    // NEAR sent to implicit recipients registered by fulfill, 0 if they aren't funded
+    implicit_account_funding: Balance,
+    // Msg and refunded amount of inbound transactions delivered with ft_transfer_call by hash
+    transfer_calls: LookupMap<String, TransferCall>,
+    unclaimed_refunds: Balance,
+    outbound_payloads: LookupMap<u128, String>,
+    // Contract calls of inbound transactions by hash
+    inbound_messages: LookupMap<String, InboundMessage>,
+    // Nonces of outbound transactions that sent NEAR
+    native_outbound: LookupSet<u128>,
+    // NEAR held for native bridging, it isn't part of storage paid or withdrawable fees
+    total_native_locked: Balance,
+    pending_native_outbound: Balance,
+    native_limit_per_send: Balance,
+    // Hashes of inbound transactions that released NEAR
+    native_inbound: LookupSet<String>,
+    nft_contracts: UnorderedSet<AccountId>,
+    // Locked NFTs by their own nonce sequence
+    nft_outbound: LookupMap<u128, NftTransaction>,
+    nft_nonce: U128,
+    escrows: LookupMap<AccountId, Escrow>,
+    total_escrowed: Balance,
+    fast_relayers: LookupMap<AccountId, FastRelayer>,
+    // Fronted transactions by "from_chain:nonce"
+    fast_fulfills: LookupMap<String, FastFulfill>,
+    // Paid by the recipient of a fronted transaction to the fast relayer on top of the fee
+    fast_fee_numerator: u16,
+    fast_min_bond: Balance,
+    total_fast_liquidity: Balance,
+    total_fast_bonds: Balance,
+    relayer_bonds: LookupMap<PublicKey, RelayerBond>,
+    // Fulfilled relayer signed transactions by "from_chain:nonce"
+    attestations: LookupMap<String, Attestation>,
+    // Fulfill callers need at least one of the minimums bonded, 0 for both disables the check
+    min_relayer_bond_near: Balance,
+    min_relayer_bond_tokens: Balance,
+    dispute_window: u64,
+    unbonding_period: u64,
+    total_bonded_near: Balance,
+    total_bonded_tokens: Balance,
+    // Challenge window in seconds by source chain, chains without an entry are fulfilled at once
+    optimistic_windows: LookupMap<String, u64>,
+    optimistic_claims: LookupMap<String, OptimisticClaim>,
+    // Accounts that can veto claims and resolve challenges
+    guardians: UnorderedSet<AccountId>,
+    // NEAR attached to challenge, it's returned on veto and paid to the relayer on dismissal
+    challenge_bond: Balance,
+    // Hashes of vetoed claims, these transactions can't be fulfilled in any way
+    vetoed: LookupSet<String>,
+    total_challenge_bonds: Balance,
+    // Relayer keys bonded by their owner
+    relayer_keys: LookupMap<AccountId, Vec<PublicKey>>,
+    // NEAR of completed native sends, it backs the bridged NEAR on other chains, so it's only
+    // released by fulfill_native
+    native_bridged_out: Balance,
 }
 
 /// Helper structure for keys of the persistent collections
@@ -65,6 +623,36 @@ pub enum StorageKey {
     Fulfilled,
     StoragePaid,
     AvailableChains,
+    Checkpoints,
+    OutboundTransactions,
+    OutboundByHash,
+    InboundTransactions,
+    InboundByUser,
+    InboundByUserInner { account_id_hash: CryptoHash },
+    ChainStats,
+    UserVolumes,
+    IdempotencyKeys,
+    ChainAddressFormats,
+    TransferCalls,
+    OutboundPayloads,
+    InboundMessages,
+    NativeOutbound,
+    NftContracts,
+    NftOutbound,
+    Escrows,
+    FastRelayers,
+    FastFulfills,
+    RelayerBonds,
+    Attestations,
+    OptimisticWindows,
+    OptimisticClaims,
+    Guardians,
+    LastCheckpointBlocks,
+    OutboundByUser,
+    OutboundByUserInner { account_id_hash: CryptoHash },
+    NativeInbound,
+    Vetoed,
+    RelayerKeys,
 }
 
 /*
@@ -100,80 +688,236 @@ impl FungibleTokenReceiver for BridgeAssist {
             env::panic_str("Sender_id is not the signer of tx");
         }
 
-        if msg.len() as u8 <= ETH_ADDRESS_LENGTH {
-            env::log_str(
-                "PANIC: 42 hexadecimal characters as ETH address should be specified in msg field + destination chain",
-            );
-            env::panic_str(
-                "42 hexadecimal characters as ETH address should be specified in msg field + destination chain",
-            );
+        if let Ok(message) = near_sdk::serde_json::from_str::<RelayerBondMessage>(&msg) {
+            let bond = self.relayer_bonds.get(&message.relayer_bond);
+            if bond.map(|bond| bond.owner) != Some(sender_id.clone()) {
+                env::log_str("PANIC: Relayer key isn't bonded by sender");
+                env::panic_str("Relayer key isn't bonded by sender");
+            }
+            self.add_relayer_bond(&sender_id, &message.relayer_bond, 0, u128::from(amount));
+            return PromiseOrValue::Value(U128::from(0));
+        }
+        if msg == FAST_LIQUIDITY_MSG {
+            self.deposit_fast_liquidity(&sender_id, u128::from(amount));
+            return PromiseOrValue::Value(U128::from(0));
+        }
+        // Tokens escrowed for intents aren't sent until a relayer submits a signed intent
+        if let Ok(message) = near_sdk::serde_json::from_str::<EscrowMessage>(&msg) {
+            self.deposit_to_escrow(&sender_id, u128::from(amount), message.intent_key);
+            return PromiseOrValue::Value(U128::from(0));
         }
 
-        let eth_address = &msg[0..ETH_ADDRESS_LENGTH as usize];
-        let chain = &msg[ETH_ADDRESS_LENGTH as usize..];
+        let message = self.parse_send_message(&msg);
+        let recipient = message.recipient.as_str();
+        let chain = message.chain.as_str();
 
         if !self.is_available_chain(String::from(chain)) {
             env::log_str("PANIC: Chain is not supported");
             env::panic_str("Chain is not supported")
         }
+        if !self.get_chain_address_format(String::from(chain)).is_valid(recipient) {
+            let error = format!("Recipient is not a valid {} address", chain);
+            env::log_str(&format!("PANIC: {}", error));
+            env::panic_str(&error);
+        }
 
-        let user_storage_paid = self.storage_paid.get(&sender_id).unwrap_or_else(|| {
-            env::log_str("PANIC: Not storage paid");
-            env::panic_str("Not storage paid")
-        });
-        let storage_paid_for_ft_on_transfer = self.bytes_for_ft_on_transfer as u128 * env::STORAGE_PRICE_PER_BYTE;
-        if user_storage_paid < storage_paid_for_ft_on_transfer {
-            env::log_str("PANIC: Not enough storage paid");
-            env::panic_str("Not enough storage paid");
+        // Repeated send is rejected with all tokens returned as unused
+        let idempotency_hash = message
+            .idempotency_key
+            .as_ref()
+            .map(|key| self.get_idempotency_hash(&sender_id, key));
+        let original_nonce = idempotency_hash.and_then(|hash| self.find_recent_send(&hash));
+        if let Some(original_nonce) = original_nonce {
+            let log = format!(
+                "Send with idempotency key {} of {} is already done with nonce {}, {} tokens are returned",
+                message.idempotency_key.as_ref().unwrap(),
+                sender_id,
+                original_nonce,
+                Balance::from(amount)
+            );
+            env::log_str(&log);
+            return PromiseOrValue::Value(amount);
         }
 
-        // Limits check
-        if Balance::from(amount) > self.limit_per_send {
-            env::log_str("PANIC: Amount is over the limit per 1 send");
-            env::panic_str("Amount is over the limit per 1 send");
+        self.record_outbound_send(
+            &sender_id,
+            amount,
+            &message,
+            idempotency_hash,
+            env::storage_usage(),
+        );
+        PromiseOrValue::Value(U128::from(0))
+    }
+}
+
+/*
+    Callback from whitelisted NFT contracts on nft_transfer_call. The NFT is returned to its owner
+    if the call or its callback panics.
+*/
+#[near_bindgen]
+impl NonFungibleTokenReceiver for BridgeAssist {
+    // Locks NFT to be sent on another chain by user
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        let nft_contract_id = env::predecessor_account_id();
+        if !self.nft_contracts.contains(&nft_contract_id) {
+            env::log_str("PANIC: Not supported NFT contract");
+            env::panic_str("Not supported NFT contract");
         }
 
-        let tx_data = Transaction {
-            from_user: sender_id.to_string(),
-            to_user: String::from(eth_address),
-            amount: U128::from(amount),
+        let message = self.parse_send_message(&msg);
+        // NFT sends have no records to dedupe by key or carry payload with
+        if message.idempotency_key.is_some() || message.payload.is_some() {
+            env::log_str("PANIC: Idempotency key and payload aren't supported for NFTs");
+            env::panic_str("Idempotency key and payload aren't supported for NFTs");
+        }
+        if !self.is_available_chain(message.chain.clone()) {
+            env::log_str("PANIC: Chain is not supported");
+            env::panic_str("Chain is not supported")
+        }
+        if !self.get_chain_address_format(message.chain.clone()).is_valid(&message.recipient) {
+            let error = format!("Recipient is not a valid {} address", message.chain);
+            env::log_str(&format!("PANIC: {}", error));
+            env::panic_str(&error);
+        }
+        // The owner the NFT is sent from and returned to pays for the send, not an approved
+        // sender
+        drop(sender_id);
+        if !self.storage_paid.contains_key(&previous_owner_id) {
+            env::log_str("PANIC: Not storage paid");
+            env::panic_str("Not storage paid");
+        }
+
+        // Metadata hash and nonce are set once the token is read
+        let transaction = NftTransaction {
+            from_user: previous_owner_id.to_string(),
+            to_user: message.recipient,
+            nft_contract: nft_contract_id.clone(),
+            token_id: token_id.clone(),
+            metadata_hash: String::new(),
             timestamp: U64::from(env::block_timestamp() / 1_000_000_000),
             from_chain: String::from(CURRENT_CHAIN),
-            to_chain: String::from(chain),
-            nonce: self.nonce,
+            to_chain: message.chain,
+            nonce: U128::from(0),
         };
+        nft_contract::ext(nft_contract_id)
+            .nft_token(token_id)
+            .then(
+                Self::ext(env::current_account_id())
+                    .resolve_nft_send(previous_owner_id, transaction),
+            )
+            .into()
+    }
+}
 
-        // Insert tx_data in LookupMap
-        let mut tx_vector = self
-            .transactions
-            .get(&tx_data.from_user)
-            .unwrap_or_else(|| {
-                Vector::new(StorageKey::TransactionsInner {
-                    account_id_hash: env::sha256_array(sender_id.as_bytes()),
-                })
-            });
-        tx_vector.push(&tx_data);
-        self.transactions.insert(&tx_data.from_user, &tx_vector);
+/*
+    NEP-145 storage management. Registration costs bytes_for_register, everything deposited above
+    it is kept in storage_paid and is spent on ft_on_transfer and fulfill calls.
+*/
+#[near_bindgen]
+impl StorageManagement for BridgeAssist {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let user = account_id.unwrap_or_else(env::predecessor_account_id);
+        let attached_near = env::attached_deposit();
+        let registration_only = registration_only.unwrap_or(false);
+        if let Some(user_storage_paid) = self.storage_paid.get(&user) {
+            if registration_only {
+                env::log_str("The account is already registered, refunding the deposit");
+                if attached_near > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(attached_near);
+                }
+            } else {
+                self.storage_paid.insert(&user, &(user_storage_paid + attached_near));
+                self.total_storage_paid += attached_near;
+            }
+        } else {
+            let min_balance = self.storage_balance_bounds().min.0;
+            if attached_near < min_balance {
+                env::panic_str("Not enough NEAR attached");
+            }
+            let excess = attached_near - min_balance;
+            if registration_only {
+                self.storage_paid.insert(&user, &0);
+                if excess > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(excess);
+                }
+            } else {
+                self.storage_paid.insert(&user, &excess);
+                self.total_storage_paid += excess;
+            }
+        }
+        self.storage_balance_of(user).unwrap()
+    }
 
-        // Update storage paid
-        let new_storage_paid =
-            user_storage_paid - storage_paid_for_ft_on_transfer;
-        self.storage_paid.insert(&sender_id, &new_storage_paid);
-        self.total_storage_paid = self.total_storage_paid - storage_paid_for_ft_on_transfer;
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let user = env::predecessor_account_id();
+        let user_storage_paid = self
+            .storage_paid
+            .get(&user)
+            .unwrap_or_else(|| env::panic_str("No storage paid"));
+        let amount = amount.map(u128::from).unwrap_or(user_storage_paid);
+        if amount > user_storage_paid {
+            env::panic_str("Amount is more than your storage paid");
+        }
+        if amount > 0 {
+            Promise::new(user.clone()).transfer(amount);
+        }
+        self.storage_paid
+            .insert(&user, &(user_storage_paid - amount));
+        self.total_storage_paid -= amount;
+        self.storage_balance_of(user).unwrap()
+    }
 
-        // Increment nonce
-        self.nonce = U128::from(u128::from(self.nonce) + 1 as u128);
+    // Account with data paid from its storage balance is unregistered only with `force`, which
+    // removes the data and burns escrowed tokens and relayer bonds of the account
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let user = env::predecessor_account_id();
+        if self.storage_paid.get(&user).is_some() && self.has_storage_data(&user) {
+            if !force.unwrap_or(false) {
+                env::panic_str("Account still has data, unregister it with force to remove it");
+            }
+            self.remove_storage_data(&user);
+        }
+        match self.storage_paid.remove(&user) {
+            Some(user_storage_paid) => {
+                self.total_storage_paid -= user_storage_paid;
+                let refund = self.storage_balance_bounds().min.0 + user_storage_paid;
+                Promise::new(user).transfer(refund);
+                true
+            }
+            None => {
+                env::log_str("The account is not registered");
+                false
+            }
+        }
+    }
 
-        let log = format!(
-            "Sent {} tokens from {} to {} in direction {}->{}",
-            Balance::from(amount),
-            sender_id,
-            String::from(eth_address),
-            CURRENT_CHAIN,
-            String::from(chain)
-        );
-        env::log_str(&log);
-        PromiseOrValue::Value(U128::from(0))
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128::from(self.bytes_for_register as u128 * env::STORAGE_PRICE_PER_BYTE),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_paid.get(&account_id).map(|user_storage_paid| StorageBalance {
+            total: U128::from(self.storage_balance_bounds().min.0 + user_storage_paid),
+            available: U128::from(user_storage_paid),
+        })
     }
 }
 
@@ -202,6 +946,7 @@ impl BridgeAssist {
             bytes_for_ft_on_transfer: 0,
             bytes_for_fulfill: 0,
             bytes_for_add_chain: 0,
+            bytes_for_checkpoint: 0,
             owner,
             relayer_role: relayer,
             token,
@@ -209,208 +954,2767 @@ impl BridgeAssist {
             limit_per_send: Balance::from(limit_per_send),
             nonce: U128::from(0),
             fee_numerator,
-            transactions: LookupMap::new(StorageKey::Transactions),
+            retention_period: DEFAULT_RETENTION_PERIOD,
+            outbound_by_user: LookupMap::new(StorageKey::OutboundByUser),
+            outbound_transactions: LookupMap::new(StorageKey::OutboundTransactions),
+            outbound_by_hash: LookupMap::new(StorageKey::OutboundByHash),
+            inbound_transactions: LookupMap::new(StorageKey::InboundTransactions),
+            inbound_by_user: LookupMap::new(StorageKey::InboundByUser),
             fulfilled: LookupSet::new(StorageKey::Fulfilled),
             storage_paid: LookupMap::new(StorageKey::StoragePaid),
-            total_storage_paid: Balance::from(0 as u128),
+            total_storage_paid: 0,
+            registration_pool: 0,
             available_chains: UnorderedSet::new(StorageKey::AvailableChains),
+            checkpoints: LookupMap::new(StorageKey::Checkpoints),
+            last_checkpoint_block: LookupMap::new(StorageKey::LastCheckpointBlocks),
+            chain_stats: LookupMap::new(StorageKey::ChainStats),
+            user_volumes: LookupMap::new(StorageKey::UserVolumes),
+            outbound_count: 0,
+            inbound_count: 0,
+            total_fees_collected: 0,
+            pending_outbound_amount: 0,
+            pending_inbound_amount: 0,
+            withdrawals_in_flight: 0,
+            bridged_out_amount: 0,
+            staged_upgrade: None,
+            legacy_transactions: LookupMap::new(StorageKey::Transactions),
+            idempotency_keys: LookupMap::new(StorageKey::IdempotencyKeys),
+            chain_address_formats: LookupMap::new(StorageKey::ChainAddressFormats),
+            implicit_account_funding: 0,
+            transfer_calls: LookupMap::new(StorageKey::TransferCalls),
+            unclaimed_refunds: 0,
+            outbound_payloads: LookupMap::new(StorageKey::OutboundPayloads),
+            inbound_messages: LookupMap::new(StorageKey::InboundMessages),
+            native_outbound: LookupSet::new(StorageKey::NativeOutbound),
+            total_native_locked: 0,
+            pending_native_outbound: 0,
+            native_limit_per_send: 0,
+            native_inbound: LookupSet::new(StorageKey::NativeInbound),
+            nft_contracts: UnorderedSet::new(StorageKey::NftContracts),
+            nft_outbound: LookupMap::new(StorageKey::NftOutbound),
+            nft_nonce: U128::from(0),
+            escrows: LookupMap::new(StorageKey::Escrows),
+            total_escrowed: 0,
+            fast_relayers: LookupMap::new(StorageKey::FastRelayers),
+            fast_fulfills: LookupMap::new(StorageKey::FastFulfills),
+            fast_fee_numerator: 0,
+            fast_min_bond: 0,
+            total_fast_liquidity: 0,
+            total_fast_bonds: 0,
+            relayer_bonds: LookupMap::new(StorageKey::RelayerBonds),
+            attestations: LookupMap::new(StorageKey::Attestations),
+            min_relayer_bond_near: 0,
+            min_relayer_bond_tokens: 0,
+            dispute_window: DEFAULT_DISPUTE_WINDOW,
+            unbonding_period: DEFAULT_UNBONDING_PERIOD,
+            total_bonded_near: 0,
+            total_bonded_tokens: 0,
+            optimistic_windows: LookupMap::new(StorageKey::OptimisticWindows),
+            optimistic_claims: LookupMap::new(StorageKey::OptimisticClaims),
+            guardians: UnorderedSet::new(StorageKey::Guardians),
+            challenge_bond: 0,
+            vetoed: LookupSet::new(StorageKey::Vetoed),
+            total_challenge_bonds: 0,
+            relayer_keys: LookupMap::new(StorageKey::RelayerKeys),
+            native_bridged_out: 0,
         };
         this.measure_bytes_for_functions();
+        write_state_version();
+        this
+    }
+
+    // Called by deploy_upgrade right after the new code is deployed, converts older state layout
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let version = read_state_version();
+        let this: Self = match version {
+            STATE_VERSION => env::state_read()
+                .unwrap_or_else(|| env::panic_str("Failed to read state")),
+            version if version < STATE_VERSION => {
+                let mut this = read_old_state(version);
+                // Estimates depend on the layout, so they are measured again
+                this.measure_bytes_for_functions();
+                this
+            }
+            _ => env::panic_str("Unknown state version"),
+        };
+        write_state_version();
         this
     }
 
-    // Fulfills transaction from another chain
+    // Fulfills transaction from another chain. With msg signed together with the transaction
+    // tokens are delivered to the recipient contract with ft_transfer_call, with signed call
+    // the contract call is made after tokens are dispensed
     #[payable]
-    pub fn fulfill(&mut self, transaction: Transaction, signature: Vec<u8>) {
-        assert_one_yocto();
+    pub fn fulfill(
+        &mut self,
+        transaction: Transaction,
+        signature: Vec<u8>,
+        register_recipient: Option<bool>,
+        msg: Option<String>,
+        call: Option<MessageCall>,
+    ) {
+        if env::attached_deposit() == 0 {
+            env::panic_str("Requires attached deposit of at least 1 yoctoNEAR");
+        }
+        self.check_fulfill_gas(msg.as_ref(), call.as_ref());
+        if let Some(call) = &call {
+            self.check_message_receiver(call);
+        }
+        let to_user = self.check_inbound_transaction(&transaction);
+        let registration_deposit = self.check_registration_gas(register_recipient);
+
+        // Tx reply check is by the transaction alone, so it can't be fulfilled again with
+        // another msg or call
+        let tx_hash = hex::encode(self.get_tx_hash(&transaction));
+        self.check_tx_replay(&tx_hash);
+
+        // Signature check, the signed hash covers msg or call
+        let signed_hash = self.get_signed_hash(&transaction, msg.as_ref(), call.as_ref());
+        self.verify_relayer_signature(&signed_hash, &signature);
+        env::log_str("Signature has been verified");
+        self.check_relayer_bond(&self.relayer_role);
+        let relayer_id = env::predecessor_account_id();
+        if let Some(window) = self.optimistic_windows.get(&transaction.from_chain) {
+            let claim = OptimisticClaim {
+                transaction,
+                register_recipient,
+                msg,
+                call,
+                relayer: relayer_id,
+                signer: Some(self.relayer_role.clone()),
+                storage_deposit: U128::from(0),
+                finalize_after: U64::from(env::block_timestamp() / 1_000_000_000 + window),
+                status: ClaimStatus::Pending,
+                challenger: None,
+                challenge_bond: U128::from(0),
+            };
+            self.record_optimistic_claim(tx_hash, claim);
+            return;
+        }
+        self.start_fulfill(
+            &transaction,
+            tx_hash,
+            to_user,
+            registration_deposit,
+            msg,
+            call,
+            Some(self.relayer_role.clone()),
+        );
+    }
+
+    // Panics if prepaid gas isn't enough for the fulfill with its transfer call or message call
+    fn check_fulfill_gas(&self, msg: Option<&String>, call: Option<&MessageCall>) {
         if env::prepaid_gas() < GAS_FOR_FULFILL {
             env::panic_str("Not enough gas prepaid, at least 80 Tgas is needed");
         }
-        let to_user = AccountId::try_from(transaction.to_user.clone()).unwrap_or_else(|_| {
-            env::panic_str("Not convertible transaction.to field to AccountId type")
-        });
-        if transaction.to_chain != CURRENT_CHAIN {
-            env::panic_str("Wrong 'toChain' in tx struct");
+        if msg.is_some() && env::prepaid_gas() < GAS_FOR_FULFILL_WITH_CALL {
+            env::panic_str("Not enough gas prepaid, at least 200 Tgas is needed for transfer call");
         }
-        if !self.is_available_chain(transaction.from_chain.clone()) {
-            env::panic_str("Not supported fromChain in tx struct");
+        if let Some(call) = call {
+            if msg.is_some() {
+                env::panic_str("Msg and call can't be delivered together");
+            }
+            let required_gas = GAS_FOR_FULFILL_WITH_CALL + Gas::from(call.gas.0);
+            if env::prepaid_gas() < required_gas {
+                let error = format!(
+                    "Not enough gas prepaid, at least {} Tgas is needed for message call",
+                    required_gas.0 / 1_000_000_000_000
+                );
+                env::panic_str(&error);
+            }
         }
+    }
 
-        let user_storage_paid = self
-            .storage_paid
-            .get(&to_user)
-            .unwrap_or_else(|| env::panic_str("Not storage paid"));
-        let storage_paid_for_fulfill = self.bytes_for_fulfill as u128 * env::STORAGE_PRICE_PER_BYTE;
-        if user_storage_paid < storage_paid_for_fulfill {
-            env::panic_str("Not enough storage paid");
+    // Message call is made by the bridge account, so it can't target the bridge itself or the
+    // contracts holding bridged tokens and NFTs
+    fn check_message_receiver(&self, call: &MessageCall) {
+        if call.receiver_id == env::current_account_id()
+            || call.receiver_id == self.token
+            || self.nft_contracts.contains(&call.receiver_id)
+        {
+            env::panic_str("Message call can't be made to the bridge, its token or NFT contracts");
         }
+    }
 
-        // Update storage paid
-        let new_storage_paid =
-            user_storage_paid - storage_paid_for_fulfill;
-        self.storage_paid.insert(&to_user, &new_storage_paid);
-        self.total_storage_paid = self.total_storage_paid - storage_paid_for_fulfill;
+    // Fulfills transaction from another chain by proving its inclusion into a posted checkpoint
+    #[payable]
+    pub fn claim_with_proof(
+        &mut self,
+        transaction: Transaction,
+        merkle_proof: Vec<String>,
+        register_recipient: Option<bool>,
+    ) {
+        if env::attached_deposit() == 0 {
+            env::panic_str("Requires attached deposit of at least 1 yoctoNEAR");
+        }
+        if env::prepaid_gas() < GAS_FOR_FULFILL {
+            env::panic_str("Not enough gas prepaid, at least 80 Tgas is needed");
+        }
+        let to_user = self.check_inbound_transaction(&transaction);
+        let registration_deposit = self.check_registration_gas(register_recipient);
 
         // Tx reply check
-        let tx_hash_bytes = self.get_tx_hash(&transaction);
-        let tx_hash = hex::encode(tx_hash_bytes.clone());
-        if self.fulfilled.contains(&tx_hash) {
+        let tx_hash = hex::encode(self.get_tx_hash(&transaction));
+        self.check_tx_replay(&tx_hash);
+
+        // Merkle proof check
+        let leaf = self.get_checkpoint_leaf(&transaction);
+        let root = hex::encode(self.compute_merkle_root(&leaf, &merkle_proof));
+        let checkpoint = self
+            .checkpoints
+            .get(&root)
+            .unwrap_or_else(|| env::panic_str("No checkpoint is posted for this merkle proof"));
+        if checkpoint.chain != transaction.from_chain {
+            env::panic_str("Checkpoint is posted for another chain");
+        }
+        env::log_str("Merkle proof has been verified");
+        if let Some(window) = self.optimistic_windows.get(&transaction.from_chain) {
+            let claim = OptimisticClaim {
+                transaction,
+                register_recipient,
+                msg: None,
+                call: None,
+                relayer: env::predecessor_account_id(),
+                signer: None,
+                storage_deposit: U128::from(0),
+                finalize_after: U64::from(env::block_timestamp() / 1_000_000_000 + window),
+                status: ClaimStatus::Pending,
+                challenger: None,
+                challenge_bond: U128::from(0),
+            };
+            self.record_optimistic_claim(tx_hash, claim);
+            return;
+        }
+        self.start_fulfill(
+            &transaction,
+            tx_hash,
+            to_user,
+            registration_deposit,
+            None,
+            None,
+            None,
+        );
+    }
+
+    // Panics if the transaction is fulfilled, claimed or vetoed, every fulfill path checks it by
+    // the canonical tx hash
+    fn check_tx_replay(&self, tx_hash: &String) {
+        if self.fulfilled.contains(tx_hash) {
             env::panic_str("Tx has already been fulfilled");
         }
+        if self.optimistic_claims.contains_key(tx_hash) {
+            env::panic_str("Tx has already been claimed");
+        }
+        if self.vetoed.contains(tx_hash) {
+            env::panic_str("Tx is vetoed");
+        }
+    }
 
-        // Signature check
-        let signature = ed25519_dalek::Signature::try_from(signature.as_ref())
-            .unwrap_or_else(|_| env::panic_str("Signature should be a valid array of 64 bytes"));
-        let relayer_role_pub_key_without_prefix =
-            &String::try_from(&self.relayer_role).unwrap()[8..];
-        let relayer_role_pub_key = ed25519_dalek_PublicKey::from_bytes(
-            &bs58::decode(relayer_role_pub_key_without_prefix)
-                .into_vec()
-                .unwrap(),
-        )
-        .unwrap();
+    // Records verified transaction and starts dispensing. Transaction fronted by a fast relayer is
+    // settled instead and its record is completed at once
+    #[allow(clippy::too_many_arguments)]
+    fn start_fulfill(
+        &mut self,
+        transaction: &Transaction,
+        tx_hash: String,
+        to_user: AccountId,
+        registration_deposit: Balance,
+        msg: Option<String>,
+        call: Option<MessageCall>,
+        attester: Option<PublicKey>,
+    ) {
+        if self.settle_fast_fulfill(transaction) {
+            if msg.is_some() || call.is_some() {
+                env::log_str("Msg and call aren't delivered for a fronted transaction");
+            }
+            // The recipient is already paid, so it isn't registered either
+            let context =
+                self.record_fulfill(transaction, tx_hash, to_user, 0, None, None, attester, false);
+            self.complete_fronted_fulfill(context);
+            return;
+        }
+        let context = self.record_fulfill(
+            transaction,
+            tx_hash,
+            to_user,
+            registration_deposit,
+            msg,
+            call,
+            attester,
+            false,
+        );
+        self.process_fulfill(transaction, context);
+    }
+
+    // Stores fulfilled hash and pending record of verified transaction with the attestation of
+    // the relayer account (if it's relayer signed), charges their real storage plus a reserve
+    // for the callbacks. Returns context of the dispense
+    // Completes the record of a settled fronted transaction the same way a successful dispense
+    // does, the fee is sent out of the amount the fast relayer isn't reimbursed with
+    fn complete_fronted_fulfill(&mut self, context: FulfillContext) {
+        let initial_storage_usage = env::storage_usage();
+        self.complete_inbound_record(&context.tx_hash, None);
+        self.record_inbound_stats(&context);
+        let storage_used = context.storage_used
+            + (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        let refund = context.storage_paid_for_call.saturating_sub(storage_used);
+        self.return_storage(&context, refund);
+        if context.fee != 0 {
+            self.transfer_fee(context.fee);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn record_fulfill(
+        &mut self,
+        transaction: &Transaction,
+        tx_hash: String,
+        to_user: AccountId,
+        registration_deposit: Balance,
+        msg: Option<String>,
+        call: Option<MessageCall>,
+        attester: Option<PublicKey>,
+        native: bool,
+    ) -> FulfillContext {
+        let initial_storage_usage = env::storage_usage();
+        self.fulfilled.insert(&tx_hash);
+        let fee = self.calculate_fee(transaction);
+        // Storage is paid with attached deposit the same way charge_storage_for_fulfill takes it
+        let storage_payer = (env::attached_deposit() > 1).then(env::predecessor_account_id);
+        let record_storage_cost = self.record_inbound_transaction(
+            transaction,
+            &tx_hash,
+            &to_user,
+            fee,
+            msg.as_ref(),
+            call,
+            storage_payer,
+            native,
+        );
+        if let Some(signer) = attester {
+            let kind = if native { TransactionKind::Native } else { TransactionKind::Token };
+            let key = self.get_attestation_key(kind, &transaction.from_chain, transaction.nonce);
+            self.record_attestation(&key, &tx_hash, signer);
+        }
+        let storage_used =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        let storage_paid_for_fulfill = storage_used + self.get_callback_storage_reserve(&to_user);
+        let funding_deposit = self.get_recipient_funding(&to_user, registration_deposit);
+        let storage_payer = self.charge_storage_for_fulfill(
+            &to_user,
+            storage_paid_for_fulfill,
+            registration_deposit + funding_deposit,
+        );
+
+        FulfillContext {
+            fee,
+            tx_hash,
+            to_user,
+            storage_paid_for_call: storage_paid_for_fulfill,
+            storage_used,
+            storage_payer,
+            registration_deposit,
+            funding_deposit,
+            record_storage_cost,
+            msg,
+        }
+    }
+
+    // Upper bound of storage the fulfill callbacks can take: a volume entry of the recipient on
+    // success or the failure reason of the record on rollback
+    fn get_callback_storage_reserve(&self, to_user: &AccountId) -> Balance {
+        let volume_bytes = if self.user_volumes.contains_key(to_user) {
+            0
+        } else {
+            map_entry_bytes(StorageKey::UserVolumes, to_user, &UserVolume::default())
+        };
+        // Option tag of the failure reason is already stored, so only the variant byte is added
+        let failure_reason_bytes = 1;
+        (volume_bytes + failure_reason_bytes) as u128 * env::STORAGE_PRICE_PER_BYTE
+    }
 
-        match relayer_role_pub_key.verify(&tx_hash_bytes, &signature) {
-            Ok(()) => {
-                env::log_str("Signature has been verified");
-                self.fulfilled.insert(&tx_hash);
+    // Accepts JSON SendMessage or legacy 42 characters of ETH address followed by chain name,
+    // recipients of non-EVM chains can only be sent in JSON
+    fn parse_send_message(&self, msg: &str) -> SendMessage {
+        if msg.starts_with('{') {
+            let message: SendMessage = near_sdk::serde_json::from_str(msg).unwrap_or_else(|_| {
+                env::log_str("PANIC: Msg is not a valid send message");
+                env::panic_str("Msg is not a valid send message")
+            });
+            if message.idempotency_key.as_ref().is_some_and(|key| {
+                key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH
+            }) {
+                env::log_str("PANIC: Idempotency key should be 1 to 64 characters long");
+                env::panic_str("Idempotency key should be 1 to 64 characters long");
             }
-            Err(_) => env::panic_str("Wrong signature"),
+            if message.payload.as_ref().is_some_and(|payload| !is_valid_payload(payload)) {
+                env::log_str("PANIC: Payload should be 0x-prefixed hex of at most 1024 bytes");
+                env::panic_str("Payload should be 0x-prefixed hex of at most 1024 bytes");
+            }
+            return message;
+        }
+
+        if msg.len() as u8 <= ETH_ADDRESS_LENGTH {
+            env::log_str(
+                "PANIC: 42 hexadecimal characters as ETH address should be specified in msg field + destination chain",
+            );
+            env::panic_str(
+                "42 hexadecimal characters as ETH address should be specified in msg field + destination chain",
+            );
+        }
+        SendMessage {
+            recipient: msg[0..ETH_ADDRESS_LENGTH as usize].to_string(),
+            chain: msg[ETH_ADDRESS_LENGTH as usize..].to_string(),
+            idempotency_key: None,
+            payload: None,
+        }
+    }
+
+    // Records a send of sender_id with its indexes and charges the storage taken since
+    // initial_storage_usage from sender_id storage paid. Returns nonce of the send
+    fn record_outbound_send(
+        &mut self,
+        sender_id: &AccountId,
+        amount: U128,
+        message: &SendMessage,
+        idempotency_hash: Option<CryptoHash>,
+        initial_storage_usage: StorageUsage,
+    ) -> u128 {
+        let recipient = message.recipient.as_str();
+        let chain = message.chain.as_str();
+        let user_storage_paid = self.storage_paid.get(sender_id).unwrap_or_else(|| {
+            env::log_str("PANIC: Not storage paid");
+            env::panic_str("Not storage paid")
+        });
+
+        // Limits check
+        if Balance::from(amount) > self.limit_per_send {
+            env::log_str("PANIC: Amount is over the limit per 1 send");
+            env::panic_str("Amount is over the limit per 1 send");
+        }
+
+        let tx_data = Transaction {
+            from_user: sender_id.to_string(),
+            to_user: String::from(recipient),
+            amount,
+            timestamp: U64::from(env::block_timestamp() / 1_000_000_000),
+            from_chain: String::from(CURRENT_CHAIN),
+            to_chain: String::from(chain),
+            nonce: self.nonce,
+        };
+
+        // Insert tx_data in outbound store and its indexes
+        let nonce = u128::from(tx_data.nonce);
+        let mut nonces = self.get_outbound_nonces(sender_id);
+        nonces.push(&nonce);
+        self.outbound_by_user.insert(sender_id, &nonces);
+        self.outbound_by_hash
+            .insert(&hex::encode(self.get_tx_hash(&tx_data)), &nonce);
+        let record = OutboundRecord {
+            transaction: tx_data,
+            status: TransactionStatus::Pending,
+            idempotency_hash,
+            payload: None,
+            native: false,
+        };
+        self.outbound_transactions.insert(&nonce, &record);
+        if let Some(payload) = message.payload.as_ref() {
+            self.outbound_payloads.insert(&nonce, payload);
+        }
+        self.record_outbound_stats(sender_id, chain, u128::from(amount));
+        self.pending_outbound_amount += u128::from(amount);
+        if let Some(hash) = idempotency_hash {
+            self.idempotency_keys.insert(&hash, &nonce);
+        }
+
+        // Charge the storage actually taken by the send
+        let storage_paid_for_send =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        if user_storage_paid < storage_paid_for_send {
+            env::log_str("PANIC: Not enough storage paid");
+            env::panic_str("Not enough storage paid");
+        }
+        let new_storage_paid =
+            user_storage_paid - storage_paid_for_send;
+        self.storage_paid.insert(sender_id, &new_storage_paid);
+        self.total_storage_paid -= storage_paid_for_send;
+
+        // Increment nonce
+        self.nonce = U128::from(u128::from(self.nonce) + 1);
+
+        let log = format!(
+            "Sent {} tokens from {} to {} in direction {}->{}",
+            Balance::from(amount),
+            sender_id,
+            String::from(recipient),
+            CURRENT_CHAIN,
+            String::from(chain)
+        );
+        env::log_str(&log);
+        if let Some(payload) = message.payload.as_ref() {
+            env::log_str(&format!("Sent payload {} with nonce {}", payload, nonce));
+        }
+        nonce
+    }
+
+    fn get_idempotency_hash(&self, sender: &AccountId, idempotency_key: &str) -> CryptoHash {
+        // Account ids can't contain ':', so sender and key can't be confused
+        env::sha256_array(format!("{}:{}", sender, idempotency_key).as_bytes())
+    }
+
+    // Nonce of the send with this idempotency hash if it was made within IDEMPOTENCY_WINDOW
+    fn find_recent_send(&self, idempotency_hash: &CryptoHash) -> Option<u128> {
+        let nonce = self.idempotency_keys.get(idempotency_hash)?;
+        let record = self.outbound_transactions.get(&nonce)?;
+        let now = env::block_timestamp() / 1_000_000_000;
+        (u64::from(record.transaction.timestamp) + IDEMPOTENCY_WINDOW > now).then_some(nonce)
+    }
+
+    // Stores pending inbound record with its transfer call msg or message call, or marks the
+    // rolled back one as pending again on retry. Returns cost of the storage taken by the new
+    // record
+    #[allow(clippy::too_many_arguments)]
+    fn record_inbound_transaction(
+        &mut self,
+        transaction: &Transaction,
+        tx_hash: &String,
+        to_user: &AccountId,
+        fee: u128,
+        msg: Option<&String>,
+        call: Option<MessageCall>,
+        storage_payer: Option<AccountId>,
+        native: bool,
+    ) -> Balance {
+        let initial_storage_usage = env::storage_usage();
+        if !self.inbound_transactions.contains_key(tx_hash) {
+            let mut hashes = self.get_inbound_hashes(to_user);
+            hashes.push(tx_hash);
+            self.inbound_by_user.insert(to_user, &hashes);
+        }
+        let record = InboundRecord {
+            transaction: transaction.clone(),
+            status: TransactionStatus::Pending,
+            fulfilled_at: U64::from(env::block_timestamp() / 1_000_000_000),
+            fee: U128::from(fee),
+            dispensed_amount: U128::from(0),
+            failure_reason: None,
+            storage_payer,
+            native: false,
         };
+        self.inbound_transactions.insert(tx_hash, &record);
+        if let Some(msg) = msg {
+            let transfer_call = TransferCall {
+                msg: msg.clone(),
+                refunded_amount: U128::from(0),
+            };
+            self.transfer_calls.insert(tx_hash, &transfer_call);
+        }
+        if let Some(call) = call {
+            let message = InboundMessage {
+                call,
+                status: MessageStatus::Pending,
+            };
+            self.inbound_messages.insert(tx_hash, &message);
+        }
+        // Liabilities are in token units, so released NEAR isn't counted there
+        if native {
+            self.native_inbound.insert(tx_hash);
+        } else {
+            self.pending_inbound_amount += u128::from(transaction.amount);
+        }
+        (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE
+    }
+
+    // Completes the record with the amount the recipient used (all of it for plain ft_transfer),
+    // the unused rest is kept for the recipient to claim. Returns the unused amount
+    fn complete_inbound_record(
+        &mut self,
+        tx_hash: &String,
+        used_amount: Option<Balance>,
+    ) -> Balance {
+        let mut record = self.inbound_transactions.get(tx_hash).unwrap();
+        let dispense_amount = u128::from(record.transaction.amount) - record.fee.0;
+        let dispensed_amount =
+            used_amount.map_or(dispense_amount, |used| used.min(dispense_amount));
+        record.status = TransactionStatus::Completed;
+        record.dispensed_amount = U128::from(dispensed_amount);
+        self.inbound_transactions.insert(tx_hash, &record);
+        if !self.native_inbound.contains(tx_hash) {
+            self.pending_inbound_amount -= u128::from(record.transaction.amount);
+            self.release_bridged_out(u128::from(record.transaction.amount));
+        }
+
+        let refunded_amount = dispense_amount - dispensed_amount;
+        if refunded_amount != 0 {
+            let mut transfer_call = self.transfer_calls.get(tx_hash).unwrap();
+            transfer_call.refunded_amount = U128::from(refunded_amount);
+            self.transfer_calls.insert(tx_hash, &transfer_call);
+            self.unclaimed_refunds += refunded_amount;
+        }
+        refunded_amount
+    }
+
+    // Tokens that came back from other chains don't back the bridged tokens there anymore. More
+    // can come back than was sent out, as the bridge is funded with liquidity for inbound ones
+    fn release_bridged_out(&mut self, amount: Balance) {
+        self.bridged_out_amount = self.bridged_out_amount.saturating_sub(amount);
+    }
+
+    // Returns cost of the storage the failure reason took in the record
+    fn roll_back_inbound_record(&mut self, tx_hash: &String, reason: FailureReason) -> Balance {
+        let initial_storage_usage = env::storage_usage();
+        let mut record = self.inbound_transactions.get(tx_hash).unwrap();
+        record.status = TransactionStatus::RolledBack;
+        record.failure_reason = Some(reason);
+        self.inbound_transactions.insert(tx_hash, &record);
+        if !self.native_inbound.contains(tx_hash) {
+            self.pending_inbound_amount -= u128::from(record.transaction.amount);
+        }
+        env::storage_usage().saturating_sub(initial_storage_usage) as u128
+            * env::STORAGE_PRICE_PER_BYTE
+    }
+
+    // Returns deposit needed to register recipient on token (0 if it isn't requested)
+    fn check_registration_gas(&self, register_recipient: Option<bool>) -> Balance {
+        if !register_recipient.unwrap_or(false) {
+            return 0;
+        }
+        if env::prepaid_gas() < GAS_FOR_FULFILL_WITH_REGISTRATION {
+            env::panic_str("Not enough gas prepaid, at least 120 Tgas is needed for recipient registration");
+        }
+        MIN_TOKEN_STORAGE_DEPOSIT
+    }
+
+    // NEAR reserved to fund the recipient if it's an implicit account being registered. A
+    // contract can't look up accounts, so the signer is known to exist and any other account is
+    // funded only after the token confirms it isn't registered yet
+    fn get_recipient_funding(&self, to_user: &AccountId, registration_deposit: Balance) -> Balance {
+        if registration_deposit != 0
+            && is_implicit_account(to_user)
+            && to_user != &env::signer_account_id()
+        {
+            self.implicit_account_funding
+        } else {
+            0
+        }
+    }
+
+    // Validates inbound transaction fields and returns its recipient
+    fn check_inbound_transaction(&self, transaction: &Transaction) -> AccountId {
+        let to_user = AccountId::try_from(transaction.to_user.clone()).unwrap_or_else(|_| {
+            env::panic_str("Not convertible transaction.to field to AccountId type")
+        });
+        if transaction.to_chain != CURRENT_CHAIN {
+            env::panic_str("Wrong 'toChain' in tx struct");
+        }
+        if !self.is_available_chain(transaction.from_chain.clone()) {
+            env::panic_str("Not supported fromChain in tx struct");
+        }
+        to_user
+    }
+
+    // Charges storage for one fulfill. If the caller attached more than 1 yoctoNEAR, storage and
+    // recipient registration (with funding of implicit recipient) are paid from the attached
+    // deposit (so unregistered recipients can receive funds) and the caller is returned as the
+    // storage payer, otherwise storage is taken from recipient's storage paid and registration
+    // from the registration pool
+    fn charge_storage_for_fulfill(
+        &mut self,
+        to_user: &AccountId,
+        storage_paid_for_fulfill: Balance,
+        reserved_deposit: Balance,
+    ) -> Option<AccountId> {
+        let attached_near = env::attached_deposit();
+        if attached_near > 1 {
+            let required_near = storage_paid_for_fulfill + reserved_deposit;
+            if attached_near < required_near {
+                env::panic_str("Not enough NEAR attached");
+            }
+            let caller = env::predecessor_account_id();
+            if attached_near > required_near {
+                Promise::new(caller.clone()).transfer(attached_near - required_near);
+            }
+            return Some(caller);
+        }
+
+        if reserved_deposit > self.registration_pool {
+            env::panic_str("Not enough NEAR in registration pool");
+        }
+        self.registration_pool -= reserved_deposit;
+
+        let user_storage_paid = self
+            .storage_paid
+            .get(to_user)
+            .unwrap_or_else(|| env::panic_str("Not storage paid"));
+        if user_storage_paid < storage_paid_for_fulfill {
+            env::panic_str("Not enough storage paid");
+        }
+
+        // Update storage paid
+        let new_storage_paid =
+            user_storage_paid - storage_paid_for_fulfill;
+        self.storage_paid.insert(to_user, &new_storage_paid);
+        self.total_storage_paid -= storage_paid_for_fulfill;
+        None
+    }
+
+    fn calculate_fee(&self, transaction: &Transaction) -> u128 {
+        u128::from(transaction.amount) * self.fee_numerator as u128 / FEE_DENOMINATOR as u128
+    }
+
+    // Hash the relayer signs for the transaction, it covers msg or call delivered with it
+    fn get_signed_hash(
+        &self,
+        transaction: &Transaction,
+        msg: Option<&String>,
+        call: Option<&MessageCall>,
+    ) -> Vec<u8> {
+        match (msg, call) {
+            (Some(msg), _) => self.get_tx_hash_with_msg(transaction, msg.clone()),
+            (None, Some(call)) => self.get_tx_hash_with_call(transaction, call.clone()),
+            (None, None) => self.get_tx_hash(transaction),
+        }
+    }
 
-        let current_fee =
-            u128::from(transaction.amount) * self.fee_numerator as u128 / FEE_DENOMINATOR as u128;
+    // Panics if the message isn't signed by the relayer role
+    fn verify_relayer_signature(&self, message: &[u8], signature: &[u8]) {
+        verify_signature(&self.relayer_role, message, signature);
+    }
 
-        if current_fee != 0 as u128 {
+    // Takes the fee (if any) and dispenses the rest of verified transaction to the recipient
+    fn process_fulfill(&mut self, transaction: &Transaction, context: FulfillContext) {
+        if context.fee != 0 {
             token_storage::ext(self.token.clone())
                 .storage_balance_of(self.fee_wallet.clone())
-                .then(Self::ext(env::current_account_id()).resolve_fulfill(
-                    current_fee,
-                    &transaction,
-                    &tx_hash,
-                    to_user.clone(),
-                    storage_paid_for_fulfill
-                ));
+                .then(Self::ext(env::current_account_id()).resolve_fulfill(transaction, context));
+        } else {
+            self.dispense_ft_to_user(transaction, context);
+        }
+    }
+
+    // Callback for fulfill
+    #[private]
+    pub fn resolve_fulfill(
+        &mut self,
+        #[callback_result] callback_result: Result<Option<StorageBalance>, PromiseError>,
+        transaction: &Transaction,
+        context: FulfillContext
+    ) {
+        match callback_result {
+            Err(_) => {
+                env::log_str("fee wallet didn't storage deposit to token (you can call storage_deposit function on token contract for fee_wallet)");
+                self.rollback_state(context, FailureReason::FeeWalletNotRegistered);
+            }
+            Ok(result) => {
+                let result = result.unwrap_or(StorageBalance {
+                    total: U128(0),
+                    available: U128(0),
+                });
+                if result.total < U128::from(MIN_TOKEN_STORAGE_DEPOSIT) {
+                    env::log_str("fee wallet didn't storage deposit to token (you can call storage_deposit function on token contract for fee_wallet)");
+                    self.rollback_state(context, FailureReason::FeeWalletNotRegistered);
+                } else {
+                    self.dispense_ft_to_user(transaction, context);
+                }
+            }
+        }
+    }
+
+    #[private]
+    pub fn dispense_ft_to_user(&mut self, transaction: &Transaction, context: FulfillContext) {
+        if context.registration_deposit != 0 {
+            token_storage::ext(self.token.clone())
+                .storage_balance_of(context.to_user.clone())
+                .then(
+                    Self::ext(env::current_account_id())
+                        .resolve_recipient_storage(transaction, context),
+                );
         } else {
-            self.dispense_ft_to_user(&transaction, &tx_hash, current_fee, to_user.clone(), storage_paid_for_fulfill);
+            self.transfer_ft_to_user(transaction, context);
+        }
+    }
+
+    // Callback for storage_balance_of of to_user on token
+    #[private]
+    pub fn resolve_recipient_storage(
+        &mut self,
+        #[callback_result] callback_result: Result<Option<StorageBalance>, PromiseError>,
+        transaction: &Transaction,
+        context: FulfillContext
+    ) {
+        match callback_result {
+            Err(_) => {
+                env::log_str("storage_balance_of promise failed for to_user in tx struct");
+                self.rollback_state(context, FailureReason::RecipientStorageCheckFailed);
+            }
+            Ok(Some(_)) => {
+                // Already registered, so the reserved deposits aren't needed
+                self.return_registration_deposit(&context);
+                let context = FulfillContext {
+                    registration_deposit: 0,
+                    funding_deposit: 0,
+                    ..context
+                };
+                self.transfer_ft_to_user(transaction, context);
+            }
+            Ok(None) => {
+                token_storage::ext(self.token.clone())
+                    .with_attached_deposit(context.registration_deposit)
+                    .storage_deposit(Some(context.to_user.clone()), Some(true))
+                    .then(
+                        Self::ext(env::current_account_id())
+                            .resolve_recipient_registration(transaction, context),
+                    );
+            }
+        }
+    }
+
+    // Callback for storage_deposit of to_user on token
+    #[private]
+    pub fn resolve_recipient_registration(
+        &mut self,
+        #[callback_result] callback_result: Result<StorageBalance, PromiseError>,
+        transaction: &Transaction,
+        context: FulfillContext
+    ) {
+        let storage_balance = match callback_result {
+            Ok(storage_balance) => storage_balance,
+            Err(_) => {
+                env::log_str("storage_deposit promise failed for to_user in tx struct");
+                self.rollback_state(context, FailureReason::RecipientRegistrationFailed);
+                return;
+            }
+        };
+        // Token keeps its minimum balance of registration only deposit and refunds the rest to
+        // the bridge, so the refund is passed on to whoever paid the deposit
+        let refunded_deposit = context
+            .registration_deposit
+            .saturating_sub(u128::from(storage_balance.total));
+        self.return_to_payer(&context, refunded_deposit);
+        let log = format!(
+            "Registered {} on token for {} yoctoNEAR",
+            context.to_user,
+            context.registration_deposit - refunded_deposit
+        );
+        env::log_str(&log);
+        // Transfer creates the implicit account if it doesn't exist yet
+        if context.funding_deposit != 0 {
+            Promise::new(context.to_user.clone()).transfer(context.funding_deposit);
+            let log = format!(
+                "Funded {} with {} yoctoNEAR",
+                context.to_user,
+                context.funding_deposit
+            );
+            env::log_str(&log);
+        }
+        // Deposits are spent, so they can't be returned on rollback anymore
+        let context = FulfillContext {
+            registration_deposit: 0,
+            funding_deposit: 0,
+            ..context
+        };
+        self.transfer_ft_to_user(transaction, context);
+    }
+
+    fn transfer_ft_to_user(&mut self, transaction: &Transaction, context: FulfillContext) {
+        let dispense_amount = u128::from(transaction.amount) - context.fee;
+
+        let log = format!(
+            "Dispense {} tokens from {} to {} in direction {}->{}",
+            dispense_amount,
+            transaction.from_user,
+            context.to_user,
+            transaction.from_chain.clone(),
+            CURRENT_CHAIN
+        );
+        env::log_str(&log);
+
+        // Transfer FT to recipient contract, unused amount is returned to the bridge by token
+        if let Some(msg) = context.msg.clone() {
+            ext_ft_core::ext(self.token.clone())
+                .with_attached_deposit(1)
+                .ft_transfer_call(
+                    context.to_user.clone(),
+                    U128::from(dispense_amount),
+                    Some("Dispensing from bridge".to_string()),
+                    msg,
+                )
+                .then(Self::ext(env::current_account_id()).resolve_dispense(context));
+            return;
+        }
+
+        // Callback makes the message call, so it's guaranteed gas for it
+        let resolve_gas = self.inbound_messages.get(&context.tx_hash).map_or(Gas(0), |message| {
+            Gas::from(message.call.gas.0) + GAS_FOR_RESOLVE_MESSAGE * 2
+        });
+
+        // Transfer FT to user
+        ext_ft_core::ext(self.token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(
+                context.to_user.clone(),
+                U128::from(dispense_amount),
+                Some("Dispensing from bridge".to_string()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(resolve_gas)
+                    .resolve_dispense(context),
+            );
+    }
+
+    // Callback for dispense_ft_to_user
+    #[private]
+    pub fn resolve_dispense(&mut self, context: FulfillContext) {
+        let (is_reverted, used_amount) = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            // ft_transfer_call returns the amount the recipient used
+            PromiseResult::Successful(result) => (
+                false,
+                context.msg.as_ref().and_then(|_| {
+                    near_sdk::serde_json::from_slice::<U128>(&result).ok().map(u128::from)
+                }),
+            ),
+            PromiseResult::Failed => (true, None),
+        };
+
+        // rollback state if the promise is failed
+        if is_reverted {
+            env::log_str("ft_transfer promise failed (maybe you should call storage_deposit function on token contract for to_user in tx struct)");
+            self.rollback_state(context, FailureReason::TransferFailed);
+        } else {
+            // Else complete the record, return unused storage and dispense fee if it is not 0
+            let initial_storage_usage = env::storage_usage();
+            let refunded_amount = self.complete_inbound_record(&context.tx_hash, used_amount);
+            if refunded_amount != 0 {
+                let log = format!(
+                    "{} tokens are returned by {}, they can be claimed with claim_refunded",
+                    refunded_amount,
+                    context.to_user
+                );
+                env::log_str(&log);
+            }
+            self.record_inbound_stats(&context);
+            let storage_used = context.storage_used
+                + (env::storage_usage() - initial_storage_usage) as u128
+                    * env::STORAGE_PRICE_PER_BYTE;
+            let refund = context.storage_paid_for_call.saturating_sub(storage_used);
+            self.return_storage(&context, refund);
+            if context.fee != 0 {
+                self.transfer_fee(context.fee);
+            }
+            if self.inbound_messages.contains_key(&context.tx_hash) {
+                self.deliver_message(context.tx_hash);
+            }
+        }
+    }
+
+    // Sends the fee to the fee wallet, it's counted as collected once the transfer succeeds
+    fn transfer_fee(&self, fee: Balance) {
+        ext_ft_core::ext(self.token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(
+                self.fee_wallet.clone(),
+                U128::from(fee),
+                Some("Transferring fee".to_string()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_FEE_TRANSFER)
+                    .with_unused_gas_weight(0)
+                    .resolve_fee_transfer(U128::from(fee)),
+            );
+    }
+
+    // Callback for the fee transfer, a failed fee stays with the bridge as surplus
+    #[private]
+    pub fn resolve_fee_transfer(&mut self, fee: U128) {
+        match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => self.total_fees_collected += u128::from(fee),
+            PromiseResult::Failed => {
+                let log = format!(
+                    "Fee transfer of {} tokens to {} failed",
+                    u128::from(fee),
+                    self.fee_wallet
+                );
+                env::log_str(&log);
+            }
+        }
+    }
+
+    fn deliver_message(&mut self, tx_hash: String) {
+        let message = self.inbound_messages.get(&tx_hash).unwrap();
+        let call = message.call;
+        Promise::new(call.receiver_id)
+            .function_call(call.method_name, call.args.into(), 0, Gas::from(call.gas.0))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_MESSAGE)
+                    .resolve_message(tx_hash),
+            );
+    }
+
+    // Callback for the message call of inbound transaction
+    #[private]
+    pub fn resolve_message(&mut self, tx_hash: String) {
+        let mut message = self.inbound_messages.get(&tx_hash).unwrap();
+        message.status = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => MessageStatus::Delivered,
+            PromiseResult::Failed => MessageStatus::Failed,
+        };
+        self.inbound_messages.insert(&tx_hash, &message);
+        let log = if message.status == MessageStatus::Delivered {
+            format!(
+                "Message of {} is delivered to {}",
+                tx_hash, message.call.receiver_id
+            )
+        } else {
+            format!(
+                "Message of {} to {} failed, it can be retried with retry_message",
+                tx_hash, message.call.receiver_id
+            )
+        };
+        env::log_str(&log);
+    }
+
+    // Makes failed message call again, anyone can call it with enough gas for the call
+    pub fn retry_message(&mut self, tx_hash: String) {
+        let mut message = self
+            .inbound_messages
+            .get(&tx_hash)
+            .unwrap_or_else(|| env::panic_str("No message with this hash"));
+        if message.status != MessageStatus::Failed {
+            env::panic_str("Only failed message can be retried");
+        }
+        self.check_message_receiver(&message.call);
+        if env::prepaid_gas() < Gas::from(message.call.gas.0) + GAS_FOR_RESOLVE_MESSAGE * 2 {
+            env::panic_str("Not enough gas prepaid for the message call");
+        }
+        message.status = MessageStatus::Pending;
+        self.inbound_messages.insert(&tx_hash, &message);
+        self.deliver_message(tx_hash);
+    }
+
+    #[private]
+    pub fn rollback_state(&mut self, context: FulfillContext, reason: FailureReason) {
+        self.fulfilled.remove(&context.tx_hash);
+        let reason_storage_cost = self.roll_back_inbound_record(&context.tx_hash, reason);
+        self.return_registration_deposit(&context);
+        // Rolled back record stays in history, so only the rest of storage is returned
+        let refund = context
+            .storage_paid_for_call
+            .saturating_sub(context.record_storage_cost + reason_storage_cost);
+        self.return_storage(&context, refund);
+    }
+
+    fn return_storage(&mut self, context: &FulfillContext, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        match &context.storage_payer {
+            // Storage was paid with attached deposit, so return it to the caller
+            Some(payer) => {
+                Promise::new(payer.clone()).transfer(amount);
+            }
+            None => self.refund_storage(context.to_user.clone(), amount),
+        }
+    }
+
+    // Returns storage to user's storage paid, or directly to the account if it has unregistered
+    fn refund_storage(&mut self, user: AccountId, amount: Balance) {
+        match self.storage_paid.get(&user) {
+            Some(user_storage_paid) => {
+                let new_storage_paid = user_storage_paid + amount;
+                self.storage_paid.insert(&user, &new_storage_paid);
+                self.total_storage_paid += amount;
+            }
+            None => {
+                Promise::new(user).transfer(amount);
+            }
+        }
+    }
+
+    // Returns reserved registration and funding deposits
+    fn return_registration_deposit(&mut self, context: &FulfillContext) {
+        self.return_to_payer(context, context.registration_deposit + context.funding_deposit);
+    }
+
+    // Returns NEAR reserved for registration to the caller that attached it or to the pool
+    fn return_to_payer(&mut self, context: &FulfillContext, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        match &context.storage_payer {
+            Some(payer) => {
+                Promise::new(payer.clone()).transfer(amount);
+            }
+            None => self.registration_pool += amount,
+        }
+    }
+
+    // Volumes are in token units, so sent NEAR is passed as 0 and only counted
+    fn record_outbound_stats(&mut self, sender: &AccountId, to_chain: &str, amount: Balance) {
+        let mut stats = self.chain_stats.get(&to_chain.to_string()).unwrap_or_default();
+        stats.sent_volume = U128::from(u128::from(stats.sent_volume) + amount);
+        stats.sent_count = U64::from(u64::from(stats.sent_count) + 1);
+        self.chain_stats.insert(&to_chain.to_string(), &stats);
+
+        let mut volume = self.user_volumes.get(sender).unwrap_or_default();
+        volume.sent = U128::from(u128::from(volume.sent) + amount);
+        self.user_volumes.insert(sender, &volume);
+        self.outbound_count += 1;
+    }
+
+    fn record_inbound_stats(&mut self, context: &FulfillContext) {
+        let record = self.inbound_transactions.get(&context.tx_hash).unwrap();
+        // Volumes are in token units, so released NEAR is only counted
+        let amount = if self.native_inbound.contains(&context.tx_hash) {
+            0
+        } else {
+            u128::from(record.transaction.amount)
+        };
+        let from_chain = record.transaction.from_chain;
+        let mut stats = self.chain_stats.get(&from_chain).unwrap_or_default();
+        stats.received_volume = U128::from(u128::from(stats.received_volume) + amount);
+        stats.received_count = U64::from(u64::from(stats.received_count) + 1);
+        self.chain_stats.insert(&from_chain, &stats);
+
+        let mut volume = self.user_volumes.get(&context.to_user).unwrap_or_default();
+        volume.received = U128::from(u128::from(volume.received) + amount);
+        self.user_volumes.insert(&context.to_user, &volume);
+        self.inbound_count += 1;
+    }
+
+    /*
+        ---------------------
+        Native NEAR functions
+        ---------------------
+    */
+    // Locks attached NEAR to be sent to another chain, storage is charged from storage paid.
+    // Liabilities and volumes are kept in token units, so native sends only add to the counts
+    #[payable]
+    pub fn send_native(&mut self, recipient: String, chain: String) {
+        let sender_id = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+        if amount == 0 {
+            env::panic_str("Attached deposit should be greater than 0");
+        }
+        if !self.is_available_chain(chain.clone()) {
+            env::panic_str("Chain is not supported");
+        }
+        if !self.get_chain_address_format(chain.clone()).is_valid(&recipient) {
+            env::panic_str(&format!("Recipient is not a valid {} address", chain));
+        }
+        let user_storage_paid = self
+            .storage_paid
+            .get(&sender_id)
+            .unwrap_or_else(|| env::panic_str("Not storage paid"));
+        if amount > self.native_limit_per_send {
+            env::panic_str("Amount is over the limit per 1 send");
+        }
+
+        let tx_data = Transaction {
+            from_user: sender_id.to_string(),
+            to_user: recipient.clone(),
+            amount: U128::from(amount),
+            timestamp: U64::from(env::block_timestamp() / 1_000_000_000),
+            from_chain: String::from(CURRENT_CHAIN),
+            to_chain: chain.clone(),
+            nonce: self.nonce,
+        };
+
+        // Insert tx_data in outbound store and its indexes
+        let initial_storage_usage = env::storage_usage();
+        let nonce = u128::from(tx_data.nonce);
+        let mut nonces = self.get_outbound_nonces(&sender_id);
+        nonces.push(&nonce);
+        self.outbound_by_user.insert(&sender_id, &nonces);
+        self.outbound_by_hash
+            .insert(&hex::encode(self.get_native_tx_hash(&tx_data)), &nonce);
+        let record = OutboundRecord {
+            transaction: tx_data,
+            status: TransactionStatus::Pending,
+            idempotency_hash: None,
+            payload: None,
+            native: false,
+        };
+        self.outbound_transactions.insert(&nonce, &record);
+        self.native_outbound.insert(&nonce);
+        self.record_outbound_stats(&sender_id, &chain, 0);
+        self.total_native_locked += amount;
+        self.pending_native_outbound += amount;
+
+        // Charge the storage actually taken by the send
+        let storage_paid_for_send =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        if user_storage_paid < storage_paid_for_send {
+            env::panic_str("Not enough storage paid");
+        }
+        self.storage_paid
+            .insert(&sender_id, &(user_storage_paid - storage_paid_for_send));
+        self.total_storage_paid -= storage_paid_for_send;
+
+        // Increment nonce
+        self.nonce = U128::from(nonce + 1);
+
+        let log = format!(
+            "Sent {} yoctoNEAR from {} to {} in direction {}->{}",
+            amount, sender_id, recipient, CURRENT_CHAIN, chain
+        );
+        env::log_str(&log);
+    }
+
+    // Releases NEAR of transaction from another chain, its signature is over the native hash
+    #[payable]
+    pub fn fulfill_native(&mut self, transaction: Transaction, signature: Vec<u8>) {
+        if env::attached_deposit() == 0 {
+            env::panic_str("Requires attached deposit of at least 1 yoctoNEAR");
+        }
+        if env::prepaid_gas() < GAS_FOR_FULFILL {
+            env::panic_str("Not enough gas prepaid, at least 80 Tgas is needed");
+        }
+        // Native transactions have no claims, so they can't wait for the optimistic window
+        if self.optimistic_windows.contains_key(&transaction.from_chain) {
+            env::panic_str("Native tx of optimistic chain isn't supported");
+        }
+        let to_user = self.check_inbound_transaction(&transaction);
+
+        // Tx reply check
+        let tx_hash_bytes = self.get_native_tx_hash(&transaction);
+        let tx_hash = hex::encode(&tx_hash_bytes);
+        self.check_tx_replay(&tx_hash);
+
+        // Signature check
+        self.verify_relayer_signature(&tx_hash_bytes, &signature);
+        env::log_str("Signature has been verified");
+        self.check_relayer_bond(&self.relayer_role);
+
+        // NEAR of pending sends is kept, so they still can be refunded. Bridged out NEAR is
+        // released first and the rest is taken from the liquidity
+        let amount = u128::from(transaction.amount);
+        let from_bridged_out = amount.min(self.native_bridged_out);
+        if amount - from_bridged_out > self.total_native_locked - self.pending_native_outbound {
+            env::panic_str("Not enough native liquidity");
+        }
+        self.native_bridged_out -= from_bridged_out;
+        self.total_native_locked -= amount - from_bridged_out;
+
+        let attester = Some(self.relayer_role.clone());
+        let context =
+            self.record_fulfill(&transaction, tx_hash, to_user, 0, None, None, attester, true);
+        let release_amount = amount - context.fee;
+        let log = format!(
+            "Release {} yoctoNEAR from {} to {} in direction {}->{}",
+            release_amount,
+            transaction.from_user,
+            context.to_user,
+            transaction.from_chain,
+            CURRENT_CHAIN
+        );
+        env::log_str(&log);
+
+        Promise::new(context.to_user.clone())
+            .transfer(release_amount)
+            .then(
+                Self::ext(env::current_account_id()).resolve_release(
+                    context,
+                    U128::from(amount),
+                    U128::from(from_bridged_out),
+                ),
+            );
+    }
+
+    // Callback for the NEAR transfer of fulfill_native
+    #[private]
+    pub fn resolve_release(
+        &mut self,
+        context: FulfillContext,
+        amount: U128,
+        from_bridged_out: U128,
+    ) {
+        match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => {
+                let initial_storage_usage = env::storage_usage();
+                self.complete_inbound_record(&context.tx_hash, None);
+                self.record_inbound_stats(&context);
+                let storage_used = context.storage_used
+                    + (env::storage_usage() - initial_storage_usage) as u128
+                        * env::STORAGE_PRICE_PER_BYTE;
+                let refund = context.storage_paid_for_call.saturating_sub(storage_used);
+                self.return_storage(&context, refund);
+                if context.fee != 0 {
+                    Promise::new(self.fee_wallet.clone()).transfer(context.fee);
+                }
+            }
+            PromiseResult::Failed => {
+                // Failed transfer is refunded to the bridge, so the transaction can be retried
+                env::log_str("NEAR transfer to to_user in tx struct failed");
+                self.native_bridged_out += u128::from(from_bridged_out);
+                self.total_native_locked += u128::from(amount) - u128::from(from_bridged_out);
+                self.rollback_state(context, FailureReason::TransferFailed);
+            }
+        }
+    }
+
+    // Adds NEAR for fulfill_native, anyone can top it up
+    #[payable]
+    pub fn fund_native_liquidity(&mut self) {
+        self.total_native_locked += env::attached_deposit();
+    }
+
+    // Withdraws native liquidity, NEAR of pending and completed sends isn't part of it
+    #[payable]
+    pub fn withdraw_native_liquidity(&mut self, receiver: AccountId, amount: U128) {
+        assert_one_yocto();
+        self.only_owner(env::predecessor_account_id());
+        if u128::from(amount) > self.total_native_locked - self.pending_native_outbound {
+            env::panic_str("Amount is more than native liquidity");
+        }
+        self.total_native_locked -= u128::from(amount);
+        Promise::new(receiver).transfer(u128::from(amount));
+    }
+
+    pub fn set_native_limit_per_send(&mut self, limit_per_send: U128) {
+        self.only_owner(env::predecessor_account_id());
+        if Balance::from(limit_per_send) == self.native_limit_per_send {
+            env::panic_str("Current limit is equal to new limit");
+        }
+        self.native_limit_per_send = Balance::from(limit_per_send);
+    }
+
+    /*
+        ----------------
+        Intent functions
+        ----------------
+    */
+    // Credits tokens sent with EscrowMessage to escrow of the sender and sets its intent key,
+    // storage of a new escrow is charged from storage paid
+    fn deposit_to_escrow(&mut self, sender_id: &AccountId, amount: Balance, intent_key: PublicKey) {
+        if intent_key.curve_type() != CurveType::ED25519 {
+            env::log_str("PANIC: Intent key should be ed25519 one");
+            env::panic_str("Intent key should be ed25519 one");
+        }
+        let user_storage_paid = self.storage_paid.get(sender_id).unwrap_or_else(|| {
+            env::log_str("PANIC: Not storage paid");
+            env::panic_str("Not storage paid")
+        });
+
+        let initial_storage_usage = env::storage_usage();
+        let escrow = self.get_or_new_escrow(sender_id);
+        let escrow = Escrow {
+            balance: U128::from(u128::from(escrow.balance) + amount),
+            intent_key: Some(intent_key),
+            ..escrow
+        };
+        self.escrows.insert(sender_id, &escrow);
+        self.total_escrowed += amount;
+
+        let storage_paid_for_escrow =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        if user_storage_paid < storage_paid_for_escrow {
+            env::log_str("PANIC: Not enough storage paid");
+            env::panic_str("Not enough storage paid");
+        }
+        self.storage_paid
+            .insert(sender_id, &(user_storage_paid - storage_paid_for_escrow));
+        self.total_storage_paid -= storage_paid_for_escrow;
+
+        env::log_str(&format!("Escrowed {} tokens of {}", amount, sender_id));
+    }
+
+    // Escrow of the user or a new one. New escrows start their intent nonce at the block
+    // timestamp in nanoseconds, so intents signed for a removed escrow can't be replayed
+    fn get_or_new_escrow(&self, user: &AccountId) -> Escrow {
+        self.escrows.get(user).unwrap_or(Escrow {
+            balance: U128::from(0),
+            intent_key: None,
+            intent_nonce: U64::from(env::block_timestamp()),
+        })
+    }
+
+    // Sends tokens from escrow of the sender on its signed intent, anyone can submit it and
+    // gets relayer_fee credited to its own escrow. Storage of the send is charged from storage
+    // paid of the sender, storage of a new relayer escrow from storage paid of the relayer
+    pub fn send_with_intent(&mut self, intent: SendIntent, signature: Vec<u8>) -> U128 {
+        let relayer_id = env::predecessor_account_id();
+        let escrow = self
+            .escrows
+            .get(&intent.sender)
+            .unwrap_or_else(|| env::panic_str("Sender has no escrow"));
+        let intent_key = escrow
+            .intent_key
+            .clone()
+            .unwrap_or_else(|| env::panic_str("Sender has no intent key"));
+        if env::block_timestamp() / 1_000_000_000 > u64::from(intent.deadline) {
+            env::panic_str("Intent is expired");
+        }
+        if intent.nonce != escrow.intent_nonce {
+            env::panic_str("Wrong intent nonce");
+        }
+        verify_signature(&intent_key, &self.get_intent_hash(&intent), &signature);
+
+        if u128::from(intent.amount) == 0 {
+            env::panic_str("Amount should be greater than 0");
+        }
+        if !self.is_available_chain(intent.chain.clone()) {
+            env::panic_str("Chain is not supported");
+        }
+        if !self.get_chain_address_format(intent.chain.clone()).is_valid(&intent.recipient) {
+            env::panic_str(&format!("Recipient is not a valid {} address", intent.chain));
+        }
+        let amount = u128::from(intent.amount);
+        let relayer_fee = u128::from(intent.relayer_fee);
+        let balance = u128::from(escrow.balance);
+        if balance < amount + relayer_fee {
+            env::panic_str("Not enough escrowed tokens");
+        }
+
+        let escrow = Escrow {
+            balance: U128::from(balance - amount - relayer_fee),
+            intent_nonce: U64::from(u64::from(intent.nonce) + 1),
+            ..escrow
+        };
+        self.escrows.insert(&intent.sender, &escrow);
+        if relayer_fee != 0 {
+            let initial_storage_usage = env::storage_usage();
+            let relayer_escrow = self.get_or_new_escrow(&relayer_id);
+            let relayer_escrow = Escrow {
+                balance: U128::from(u128::from(relayer_escrow.balance) + relayer_fee),
+                ..relayer_escrow
+            };
+            self.escrows.insert(&relayer_id, &relayer_escrow);
+            let storage_paid_for_escrow = (env::storage_usage() - initial_storage_usage) as u128
+                * env::STORAGE_PRICE_PER_BYTE;
+            let relayer_storage_paid = self.storage_paid.get(&relayer_id).unwrap_or(0);
+            if relayer_storage_paid < storage_paid_for_escrow {
+                env::panic_str("Not enough storage paid by relayer");
+            }
+            if storage_paid_for_escrow != 0 {
+                self.storage_paid
+                    .insert(&relayer_id, &(relayer_storage_paid - storage_paid_for_escrow));
+                self.total_storage_paid -= storage_paid_for_escrow;
+            }
+        }
+        self.total_escrowed -= amount;
+
+        let initial_storage_usage = env::storage_usage();
+
+        let message = SendMessage {
+            recipient: intent.recipient,
+            chain: intent.chain,
+            idempotency_key: None,
+            payload: None,
+        };
+        let nonce = self.record_outbound_send(
+            &intent.sender,
+            intent.amount,
+            &message,
+            None,
+            initial_storage_usage,
+        );
+        let log = format!(
+            "Intent {} of {} is sent by {} with fee {}",
+            u64::from(intent.nonce),
+            intent.sender,
+            relayer_id,
+            relayer_fee
+        );
+        env::log_str(&log);
+        U128::from(nonce)
+    }
+
+    // Returns escrowed tokens of the caller, they're escrowed back if the transfer failed
+    #[payable]
+    pub fn withdraw_escrow(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        let user = env::predecessor_account_id();
+        let escrow = self
+            .escrows
+            .get(&user)
+            .unwrap_or_else(|| env::panic_str("Caller has no escrow"));
+        if u128::from(amount) == 0 {
+            env::panic_str("Amount should be greater than 0");
+        }
+        if u128::from(escrow.balance) < u128::from(amount) {
+            env::panic_str("Amount is more than escrowed tokens");
+        }
+        let escrow = Escrow {
+            balance: U128::from(u128::from(escrow.balance) - u128::from(amount)),
+            ..escrow
+        };
+        self.escrows.insert(&user, &escrow);
+        self.total_escrowed -= u128::from(amount);
+        ext_ft_core::ext(self.token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(user.clone(), amount, Some("Withdraw from escrow".to_string()))
+            .then(Self::ext(env::current_account_id()).resolve_withdraw_escrow(user, amount))
+    }
+
+    // Callback for ft_transfer in withdraw_escrow. Emptied escrow is removed and its storage is
+    // returned to the user
+    #[private]
+    pub fn resolve_withdraw_escrow(&mut self, user: AccountId, amount: U128) {
+        let log = match (env::promise_result(0), self.escrows.get(&user)) {
+            (PromiseResult::NotReady, _) => env::abort(),
+            (PromiseResult::Successful(_), escrow) => {
+                if escrow.is_some_and(|escrow| u128::from(escrow.balance) == 0) {
+                    let initial_storage_usage = env::storage_usage();
+                    self.escrows.remove(&user);
+                    let refund = (initial_storage_usage - env::storage_usage()) as u128
+                        * env::STORAGE_PRICE_PER_BYTE;
+                    if let Some(user_storage_paid) = self.storage_paid.get(&user) {
+                        self.storage_paid.insert(&user, &(user_storage_paid + refund));
+                        self.total_storage_paid += refund;
+                    }
+                }
+                format!("Withdrawn {} escrowed tokens to {}", u128::from(amount), user)
+            }
+            (PromiseResult::Failed, None) => format!(
+                "Withdrawal of {} escrowed tokens to {} failed: escrow is removed",
+                u128::from(amount),
+                user
+            ),
+            (PromiseResult::Failed, Some(escrow)) => {
+                let escrow = Escrow {
+                    balance: U128::from(u128::from(escrow.balance) + u128::from(amount)),
+                    ..escrow
+                };
+                self.escrows.insert(&user, &escrow);
+                self.total_escrowed += u128::from(amount);
+                format!(
+                    "Withdrawal of {} escrowed tokens to {} failed: ft_transfer promise failed",
+                    u128::from(amount),
+                    user
+                )
+            }
+        };
+        env::log_str(&log);
+    }
+
+    /*
+        ----------------------
+        Fast relayer functions
+        ----------------------
+    */
+    // Credits tokens sent with FAST_LIQUIDITY_MSG to liquidity of the fast relayer
+    fn deposit_fast_liquidity(&mut self, relayer_id: &AccountId, amount: Balance) {
+        let relayer = self.fast_relayers.get(relayer_id).unwrap_or_else(|| {
+            env::log_str("PANIC: Not a fast relayer");
+            env::panic_str("Not a fast relayer")
+        });
+        let relayer = FastRelayer {
+            liquidity: U128::from(u128::from(relayer.liquidity) + amount),
+            ..relayer
+        };
+        self.fast_relayers.insert(relayer_id, &relayer);
+        self.total_fast_liquidity += amount;
+        env::log_str(&format!("Deposited {} tokens of fast liquidity by {}", amount, relayer_id));
+    }
+
+    // Key of a token transaction by its source chain and nonce, only one can be fronted per key
+    fn get_source_nonce_key(&self, transaction: &Transaction) -> String {
+        self.get_attestation_key(TransactionKind::Token, &transaction.from_chain, transaction.nonce)
+    }
+
+    // The relayer attests only one transaction per key. Native and NFT transactions have their
+    // own nonce sequences, so their keys are prefixed with the hash domain
+    fn get_attestation_key(&self, kind: TransactionKind, from_chain: &str, nonce: U128) -> String {
+        match kind {
+            TransactionKind::Token => format!("{}:{}", from_chain, u128::from(nonce)),
+            TransactionKind::Native => {
+                format!("{}:{}:{}", NATIVE_DOMAIN, from_chain, u128::from(nonce))
+            }
+            TransactionKind::Nft => format!("{}:{}:{}", NFT_DOMAIN, from_chain, u128::from(nonce)),
+        }
+    }
+
+    // Attestation key of a fulfilled token or native record
+    fn get_record_attestation_key(&self, tx_hash: &String, transaction: &Transaction) -> String {
+        let kind = if self.native_inbound.contains(tx_hash) {
+            TransactionKind::Native
+        } else {
+            TransactionKind::Token
+        };
+        self.get_attestation_key(kind, &transaction.from_chain, transaction.nonce)
+    }
+
+    fn record_attestation(&mut self, key: &String, tx_hash: &str, signer: PublicKey) {
+        let attestation = Attestation {
+            tx_hash: tx_hash.to_string(),
+            signer,
+            fulfilled_at: U64::from(env::block_timestamp() / 1_000_000_000),
+        };
+        self.attestations.insert(key, &attestation);
+    }
+
+    // Pays transaction from another chain with liquidity of the calling fast relayer before it's
+    // fully confirmed. The recipient gets amount without the fee and the fast fee, the relayer is
+    // reimbursed with amount without the fee when the canonical transaction is fulfilled or its
+    // bond is slashed if that transaction doesn't match. Attached deposit covers the storage
+    #[payable]
+    pub fn fast_fulfill(&mut self, transaction: Transaction) {
+        let relayer_id = env::predecessor_account_id();
+        if env::prepaid_gas() < GAS_FOR_FULFILL {
+            env::panic_str("Not enough gas prepaid, at least 80 Tgas is needed");
+        }
+        let relayer = self
+            .fast_relayers
+            .get(&relayer_id)
+            .unwrap_or_else(|| env::panic_str("Not a fast relayer"));
+        if u128::from(relayer.bond) < self.fast_min_bond {
+            env::panic_str("Fast relayer bond is less than the minimum");
+        }
+        // Transactions of an optimistic chain can't be paid before their window
+        if self.optimistic_windows.contains_key(&transaction.from_chain) {
+            env::panic_str("Tx of optimistic chain can't be fronted");
+        }
+        let to_user = self.check_inbound_transaction(&transaction);
+        let tx_hash = hex::encode(self.get_tx_hash(&transaction));
+        self.check_tx_replay(&tx_hash);
+        let key = self.get_source_nonce_key(&transaction);
+        if self.fast_fulfills.contains_key(&key) {
+            env::panic_str("Tx with this nonce is already fronted");
+        }
+        let amount = u128::from(transaction.amount);
+        let fast_fee = amount * self.fast_fee_numerator as u128 / FEE_DENOMINATOR as u128;
+        let paid = amount - self.calculate_fee(&transaction) - fast_fee;
+        if u128::from(relayer.liquidity) < paid {
+            env::panic_str("Not enough fast liquidity");
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        let relayer = FastRelayer {
+            liquidity: U128::from(u128::from(relayer.liquidity) - paid),
+            unsettled: U64::from(u64::from(relayer.unsettled) + 1),
+            ..relayer
+        };
+        self.fast_relayers.insert(&relayer_id, &relayer);
+        self.total_fast_liquidity -= paid;
+        let mut front = FastFulfill {
+            tx_hash: tx_hash.clone(),
+            relayer: relayer_id.clone(),
+            paid: U128::from(paid),
+            storage_deposit: U128::from(0),
+            confirmed: false,
+        };
+        self.fast_fulfills.insert(&key, &front);
+        let storage_deposit =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        if env::attached_deposit() < storage_deposit {
+            env::panic_str("Not enough NEAR attached");
+        }
+        front.storage_deposit = U128::from(storage_deposit);
+        self.fast_fulfills.insert(&key, &front);
+        if env::attached_deposit() > storage_deposit {
+            Promise::new(relayer_id.clone()).transfer(env::attached_deposit() - storage_deposit);
+        }
+
+        let log = format!(
+            "Fronted {} tokens of {} by {} to {}",
+            paid, tx_hash, relayer_id, to_user
+        );
+        env::log_str(&log);
+        ext_ft_core::ext(self.token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(to_user, U128::from(paid), Some("Fast fulfill from bridge".to_string()))
+            .then(Self::ext(env::current_account_id()).resolve_fast_fulfill(key));
+    }
+
+    // Callback for ft_transfer in fast_fulfill, liquidity and storage deposit are returned to
+    // the relayer if it failed
+    #[private]
+    pub fn resolve_fast_fulfill(&mut self, key: String) {
+        let front = self.fast_fulfills.get(&key).unwrap();
+        match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => {
+                self.fast_fulfills.insert(&key, &FastFulfill { confirmed: true, ..front });
+            }
+            PromiseResult::Failed => {
+                env::log_str("ft_transfer promise failed for to_user in tx struct");
+                self.fast_fulfills.remove(&key);
+                let paid = u128::from(front.paid);
+                let relayer = self.fast_relayers.get(&front.relayer).unwrap();
+                let relayer = FastRelayer {
+                    liquidity: U128::from(u128::from(relayer.liquidity) + paid),
+                    unsettled: U64::from(u64::from(relayer.unsettled) - 1),
+                    ..relayer
+                };
+                self.fast_relayers.insert(&front.relayer, &relayer);
+                self.total_fast_liquidity += paid;
+                Promise::new(front.relayer).transfer(u128::from(front.storage_deposit));
+            }
+        }
+    }
+
+    // Reimburses the fast relayer that fronted the verified transaction and returns true, so
+    // it's recorded without being dispensed again. The relayer bond is slashed to the fee wallet if it fronted
+    // another transaction with the same nonce, and false is returned. Fronts are compared by
+    // hash of the transaction alone, whatever msg or call the verified one is signed with
+    fn settle_fast_fulfill(&mut self, transaction: &Transaction) -> bool {
+        let tx_hash = &hex::encode(self.get_tx_hash(transaction));
+        let key = self.get_source_nonce_key(transaction);
+        let Some(front) = self.fast_fulfills.get(&key) else {
+            return false;
+        };
+        if !front.confirmed {
+            env::panic_str("Fast fulfill of this tx is in flight");
+        }
+        self.fast_fulfills.remove(&key);
+        let relayer = self.fast_relayers.get(&front.relayer).unwrap();
+        let unsettled = U64::from(u64::from(relayer.unsettled) - 1);
+
+        if front.tx_hash != *tx_hash {
+            let slashed = u128::from(relayer.bond);
+            self.fast_relayers.insert(
+                &front.relayer,
+                &FastRelayer { bond: U128::from(0), unsettled, ..relayer },
+            );
+            self.total_fast_bonds -= slashed;
+            let log = format!(
+                "Fast relayer {} is slashed by {} yoctoNEAR for fronting {} instead of {}",
+                front.relayer, slashed, front.tx_hash, tx_hash
+            );
+            env::log_str(&log);
+            Promise::new(self.fee_wallet.clone())
+                .transfer(slashed + u128::from(front.storage_deposit));
+            return false;
+        }
+
+        let fee = self.calculate_fee(transaction);
+        let reimbursed = u128::from(transaction.amount) - fee;
+        self.fast_relayers.insert(
+            &front.relayer,
+            &FastRelayer {
+                liquidity: U128::from(u128::from(relayer.liquidity) + reimbursed),
+                unsettled,
+                ..relayer
+            },
+        );
+        self.total_fast_liquidity += reimbursed;
+
+        let log = format!(
+            "Settled fast fulfill of {} to {} with {} tokens",
+            tx_hash, front.relayer, reimbursed
+        );
+        env::log_str(&log);
+        Promise::new(front.relayer).transfer(u128::from(front.storage_deposit));
+        true
+    }
+
+    #[payable]
+    pub fn add_fast_relayer(&mut self, relayer: AccountId) {
+        self.only_owner(env::predecessor_account_id());
+        if self.fast_relayers.contains_key(&relayer) {
+            env::panic_str("Fast relayer is already in the list");
+        }
+        let initial_storage_usage = env::storage_usage();
+        let fast_relayer = FastRelayer {
+            liquidity: U128::from(0),
+            bond: U128::from(0),
+            unsettled: U64::from(0),
+        };
+        self.fast_relayers.insert(&relayer, &fast_relayer);
+        if env::attached_deposit()
+            < (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE
+        {
+            env::panic_str("Not enough NEAR attached");
+        }
+    }
+
+    // Only a fast relayer without liquidity, bond and unsettled fronts can be removed
+    pub fn remove_fast_relayer(&mut self, relayer: AccountId) {
+        self.only_owner(env::predecessor_account_id());
+        let fast_relayer = self
+            .fast_relayers
+            .get(&relayer)
+            .unwrap_or_else(|| env::panic_str("Fast relayer is not in the list yet"));
+        if u128::from(fast_relayer.liquidity) != 0
+            || u128::from(fast_relayer.bond) != 0
+            || u64::from(fast_relayer.unsettled) != 0
+        {
+            env::panic_str("Fast relayer has liquidity, bond or unsettled fronts");
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.fast_relayers.remove(&relayer);
+        let repayment =
+            (initial_storage_usage - env::storage_usage()) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        Promise::new(env::predecessor_account_id()).transfer(repayment);
+    }
+
+    #[payable]
+    pub fn fund_fast_bond(&mut self) {
+        let relayer_id = env::predecessor_account_id();
+        let relayer = self
+            .fast_relayers
+            .get(&relayer_id)
+            .unwrap_or_else(|| env::panic_str("Not a fast relayer"));
+        let relayer = FastRelayer {
+            bond: U128::from(u128::from(relayer.bond) + env::attached_deposit()),
+            ..relayer
+        };
+        self.fast_relayers.insert(&relayer_id, &relayer);
+        self.total_fast_bonds += env::attached_deposit();
+    }
+
+    #[payable]
+    pub fn withdraw_fast_bond(&mut self, amount: U128) {
+        assert_one_yocto();
+        let relayer_id = env::predecessor_account_id();
+        let relayer = self
+            .fast_relayers
+            .get(&relayer_id)
+            .unwrap_or_else(|| env::panic_str("Not a fast relayer"));
+        if u64::from(relayer.unsettled) != 0 {
+            env::panic_str("Fast relayer has unsettled fronts");
+        }
+        if u128::from(amount) > u128::from(relayer.bond) {
+            env::panic_str("Amount is more than bond");
+        }
+        let relayer = FastRelayer {
+            bond: U128::from(u128::from(relayer.bond) - u128::from(amount)),
+            ..relayer
+        };
+        self.fast_relayers.insert(&relayer_id, &relayer);
+        self.total_fast_bonds -= u128::from(amount);
+        Promise::new(relayer_id).transfer(u128::from(amount));
+    }
+
+    // Returns fast liquidity of the caller, it's credited back if the transfer failed
+    #[payable]
+    pub fn withdraw_fast_liquidity(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        let relayer_id = env::predecessor_account_id();
+        let relayer = self
+            .fast_relayers
+            .get(&relayer_id)
+            .unwrap_or_else(|| env::panic_str("Not a fast relayer"));
+        if u128::from(amount) == 0 {
+            env::panic_str("Amount should be greater than 0");
+        }
+        if u128::from(amount) > u128::from(relayer.liquidity) {
+            env::panic_str("Amount is more than fast liquidity");
+        }
+        let relayer = FastRelayer {
+            liquidity: U128::from(u128::from(relayer.liquidity) - u128::from(amount)),
+            ..relayer
+        };
+        self.fast_relayers.insert(&relayer_id, &relayer);
+        self.total_fast_liquidity -= u128::from(amount);
+        ext_ft_core::ext(self.token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(
+                relayer_id.clone(),
+                amount,
+                Some("Withdraw fast liquidity from bridge".to_string()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .resolve_withdraw_fast_liquidity(relayer_id, amount),
+            )
+    }
+
+    // Callback for ft_transfer in withdraw_fast_liquidity
+    #[private]
+    pub fn resolve_withdraw_fast_liquidity(&mut self, relayer_id: AccountId, amount: U128) {
+        let log = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => {
+                format!(
+                    "Withdrawn {} tokens of fast liquidity to {}",
+                    u128::from(amount),
+                    relayer_id
+                )
+            }
+            PromiseResult::Failed => {
+                let relayer = self.fast_relayers.get(&relayer_id).unwrap();
+                let relayer = FastRelayer {
+                    liquidity: U128::from(u128::from(relayer.liquidity) + u128::from(amount)),
+                    ..relayer
+                };
+                self.fast_relayers.insert(&relayer_id, &relayer);
+                self.total_fast_liquidity += u128::from(amount);
+                format!(
+                    "Withdrawal of {} tokens of fast liquidity to {} failed: ft_transfer promise failed",
+                    u128::from(amount),
+                    relayer_id
+                )
+            }
+        };
+        env::log_str(&log);
+    }
+
+    pub fn set_fast_fee_numerator(&mut self, fee_numerator: u16) {
+        self.only_owner(env::predecessor_account_id());
+        if fee_numerator == self.fast_fee_numerator {
+            env::panic_str("Current fast fee is equal to new fast fee");
+        }
+        if fee_numerator as u32 + self.fee_numerator as u32 >= FEE_DENOMINATOR as u32 {
+            env::panic_str("Fast fee is to high");
+        }
+        self.fast_fee_numerator = fee_numerator;
+    }
+
+    pub fn set_fast_min_bond(&mut self, min_bond: U128) {
+        self.only_owner(env::predecessor_account_id());
+        if u128::from(min_bond) == self.fast_min_bond {
+            env::panic_str("Current minimum bond is equal to new minimum bond");
+        }
+        self.fast_min_bond = u128::from(min_bond);
+    }
+
+    /*
+        ----------------------
+        Relayer bond functions
+        ----------------------
+    */
+    // Panics if the minimum bond is set and the signing key has neither NEAR nor tokens bonded
+    // enough, the unbonding part isn't counted
+    fn check_relayer_bond(&self, relayer_key: &PublicKey) {
+        if self.min_relayer_bond_near == 0 && self.min_relayer_bond_tokens == 0 {
+            return;
+        }
+        let bond = self
+            .relayer_bonds
+            .get(relayer_key)
+            .unwrap_or_else(|| env::panic_str("Relayer bond is less than the minimum"));
+        let is_bonded = (self.min_relayer_bond_near != 0
+            && u128::from(bond.near) >= self.min_relayer_bond_near)
+            || (self.min_relayer_bond_tokens != 0
+                && u128::from(bond.tokens) >= self.min_relayer_bond_tokens);
+        if !is_bonded {
+            env::panic_str("Relayer bond is less than the minimum");
+        }
+    }
+
+    // Storage of a new bond and its entry in the owner keys is charged from storage paid of the
+    // owner
+    fn add_relayer_bond(
+        &mut self,
+        owner: &AccountId,
+        relayer_key: &PublicKey,
+        near: Balance,
+        tokens: Balance,
+    ) {
+        let user_storage_paid = self.storage_paid.get(owner).unwrap_or_else(|| {
+            env::log_str("PANIC: Not storage paid");
+            env::panic_str("Not storage paid")
+        });
+        let initial_storage_usage = env::storage_usage();
+        let bond = self.relayer_bonds.get(relayer_key).unwrap_or_else(|| {
+            let mut keys = self.relayer_keys.get(owner).unwrap_or_default();
+            keys.push(relayer_key.clone());
+            self.relayer_keys.insert(owner, &keys);
+            RelayerBond {
+                owner: owner.clone(),
+                near: U128::from(0),
+                tokens: U128::from(0),
+                unbonding_near: U128::from(0),
+                unbonding_tokens: U128::from(0),
+                unbonding_at: U64::from(0),
+            }
+        });
+        let bond = RelayerBond {
+            near: U128::from(u128::from(bond.near) + near),
+            tokens: U128::from(u128::from(bond.tokens) + tokens),
+            ..bond
+        };
+        self.relayer_bonds.insert(relayer_key, &bond);
+        self.total_bonded_near += near;
+        self.total_bonded_tokens += tokens;
+
+        let storage_paid_for_bond =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        if user_storage_paid < storage_paid_for_bond {
+            env::log_str("PANIC: Not enough storage paid");
+            env::panic_str("Not enough storage paid");
+        }
+        self.storage_paid
+            .insert(owner, &(user_storage_paid - storage_paid_for_bond));
+        self.total_storage_paid -= storage_paid_for_bond;
+
+        let log = format!(
+            "Bonded {} yoctoNEAR and {} tokens to {} by {}",
+            near,
+            tokens,
+            String::from(relayer_key),
+            owner
+        );
+        env::log_str(&log);
+    }
+
+    // Panics if the caller isn't the owner of the key bond
+    fn get_own_relayer_bond(&self, relayer_key: &PublicKey) -> RelayerBond {
+        let bond = self
+            .relayer_bonds
+            .get(relayer_key)
+            .unwrap_or_else(|| env::panic_str("Relayer key has no bond"));
+        if bond.owner != env::predecessor_account_id() {
+            env::panic_str("Only bond owner can call this method");
+        }
+        bond
+    }
+
+    // Bonds attached NEAR to the relayer key, the signature of get_bond_hash of the caller by the
+    // key proves the caller holds it. The first caller owns the bond, tokens are bonded to it
+    // with RelayerBondMessage
+    #[payable]
+    pub fn bond_relayer(&mut self, relayer_key: PublicKey, signature: Vec<u8>) {
+        if env::attached_deposit() == 0 {
+            env::panic_str("Attached deposit should be greater than 0");
+        }
+        if relayer_key.curve_type() != CurveType::ED25519 {
+            env::panic_str("Relayer key should be ed25519 one");
+        }
+        let owner = env::predecessor_account_id();
+        if let Some(bond) = self.relayer_bonds.get(&relayer_key) {
+            if bond.owner != owner {
+                env::panic_str("Relayer key is bonded by another account");
+            }
+        }
+        verify_signature(&relayer_key, &self.get_bond_hash(owner.clone()), &signature);
+        self.add_relayer_bond(&owner, &relayer_key, env::attached_deposit(), 0);
+    }
+
+    // Moves bonded NEAR and tokens of the key to unbonding, the unbonding period restarts for
+    // all of the unbonding part
+    #[payable]
+    pub fn unbond_relayer(&mut self, relayer_key: PublicKey, near: U128, tokens: U128) {
+        assert_one_yocto();
+        let bond = self.get_own_relayer_bond(&relayer_key);
+        if u128::from(near) > u128::from(bond.near) || u128::from(tokens) > u128::from(bond.tokens)
+        {
+            env::panic_str("Amount is more than bond");
+        }
+        let unbonding_at = env::block_timestamp() / 1_000_000_000 + self.unbonding_period;
+        let bond = RelayerBond {
+            near: U128::from(u128::from(bond.near) - u128::from(near)),
+            tokens: U128::from(u128::from(bond.tokens) - u128::from(tokens)),
+            unbonding_near: U128::from(u128::from(bond.unbonding_near) + u128::from(near)),
+            unbonding_tokens: U128::from(u128::from(bond.unbonding_tokens) + u128::from(tokens)),
+            unbonding_at: U64::from(unbonding_at),
+            ..bond
+        };
+        self.relayer_bonds.insert(&relayer_key, &bond);
+        let log = format!(
+            "Unbonding {} yoctoNEAR and {} tokens of {} until {}",
+            u128::from(near),
+            u128::from(tokens),
+            String::from(&relayer_key),
+            unbonding_at
+        );
+        env::log_str(&log);
+    }
+
+    // Sends the unbonding part of the key to its owner after the unbonding period, tokens are
+    // moved back to unbonding if their transfer failed
+    #[payable]
+    pub fn withdraw_unbonded(&mut self, relayer_key: PublicKey) {
+        assert_one_yocto();
+        let bond = self.get_own_relayer_bond(&relayer_key);
+        let near = u128::from(bond.unbonding_near);
+        let tokens = u128::from(bond.unbonding_tokens);
+        if near == 0 && tokens == 0 {
+            env::panic_str("Nothing is unbonding");
+        }
+        if env::block_timestamp() / 1_000_000_000 < u64::from(bond.unbonding_at) {
+            env::panic_str("Unbonding period hasn't passed yet");
+        }
+        let owner = bond.owner.clone();
+        let bond = RelayerBond {
+            unbonding_near: U128::from(0),
+            unbonding_tokens: U128::from(0),
+            ..bond
+        };
+        self.relayer_bonds.insert(&relayer_key, &bond);
+        self.total_bonded_near -= near;
+        self.total_bonded_tokens -= tokens;
+        if near != 0 {
+            Promise::new(owner.clone()).transfer(near);
+        }
+        if tokens != 0 {
+            ext_ft_core::ext(self.token.clone())
+                .with_attached_deposit(1)
+                .ft_transfer(
+                    owner,
+                    U128::from(tokens),
+                    Some("Withdraw relayer bond from bridge".to_string()),
+                )
+                .then(
+                    Self::ext(env::current_account_id())
+                        .resolve_withdraw_unbonded(relayer_key, U128::from(tokens)),
+                );
+        }
+    }
+
+    // Callback for ft_transfer in withdraw_unbonded
+    #[private]
+    pub fn resolve_withdraw_unbonded(&mut self, relayer_key: PublicKey, tokens: U128) {
+        let log = match (env::promise_result(0), self.relayer_bonds.get(&relayer_key)) {
+            (PromiseResult::NotReady, _) => env::abort(),
+            (PromiseResult::Successful(_), _) => format!(
+                "Withdrawn {} unbonded tokens of {}",
+                u128::from(tokens),
+                String::from(&relayer_key)
+            ),
+            (PromiseResult::Failed, Some(bond)) => {
+                let owner = bond.owner.clone();
+                let bond = RelayerBond {
+                    unbonding_tokens: U128::from(
+                        u128::from(bond.unbonding_tokens) + u128::from(tokens),
+                    ),
+                    ..bond
+                };
+                self.relayer_bonds.insert(&relayer_key, &bond);
+                self.total_bonded_tokens += u128::from(tokens);
+                format!(
+                    "Withdrawal of {} unbonded tokens to {} failed: ft_transfer promise failed",
+                    u128::from(tokens),
+                    owner
+                )
+            }
+            // Bond is removed by forced unregister of its owner, the tokens are burned with it
+            (PromiseResult::Failed, None) => format!(
+                "Withdrawal of {} unbonded tokens of {} failed: bond is removed",
+                u128::from(tokens),
+                String::from(&relayer_key)
+            ),
+        };
+        env::log_str(&log);
+    }
+
+    // Slashes the whole bond of the relayer key that signed a fulfilled transaction if the key
+    // signed another transaction with the same source chain and nonce, anyone can submit it
+    // within the dispute window. Msg or call are passed if the signed hash covers them. Slashed
+    // NEAR goes to the caller and slashed tokens stay with the bridge as surplus
+    pub fn submit_fraud_proof(
+        &mut self,
+        transaction: Transaction,
+        msg: Option<String>,
+        call: Option<MessageCall>,
+        signature: Vec<u8>,
+    ) {
+        let key = self.get_source_nonce_key(&transaction);
+        // Same transaction signed with another msg or call isn't a conflict
+        let tx_hash = hex::encode(self.get_tx_hash(&transaction));
+        let signed_hash = self.get_signed_hash(&transaction, msg.as_ref(), call.as_ref());
+        self.slash_attestation(&key, tx_hash, &signed_hash, &signature);
+    }
+
+    // Fraud proof against a fulfill_native attestation, it's signed over the native hash
+    pub fn submit_native_fraud_proof(&mut self, transaction: Transaction, signature: Vec<u8>) {
+        let key = self.get_attestation_key(
+            TransactionKind::Native,
+            &transaction.from_chain,
+            transaction.nonce,
+        );
+        let signed_hash = self.get_native_tx_hash(&transaction);
+        self.slash_attestation(&key, hex::encode(&signed_hash), &signed_hash, &signature);
+    }
+
+    // Fraud proof against a fulfill_nft attestation, it's signed over the NFT hash
+    pub fn submit_nft_fraud_proof(&mut self, transaction: NftTransaction, signature: Vec<u8>) {
+        let key = self.get_attestation_key(
+            TransactionKind::Nft,
+            &transaction.from_chain,
+            transaction.nonce,
+        );
+        let signed_hash = self.get_nft_tx_hash(&transaction);
+        self.slash_attestation(&key, hex::encode(&signed_hash), &signed_hash, &signature);
+    }
+
+    // Slashes the bond of the attested signer if tx_hash conflicts with the attested one and
+    // the signature of signed_hash is by the signer
+    fn slash_attestation(
+        &mut self,
+        key: &String,
+        tx_hash: String,
+        signed_hash: &[u8],
+        signature: &[u8],
+    ) {
+        let attestation = self
+            .attestations
+            .get(key)
+            .unwrap_or_else(|| env::panic_str("No attestation with this nonce"));
+        if tx_hash == attestation.tx_hash {
+            env::panic_str("Transaction doesn't conflict with the attestation");
+        }
+        if env::block_timestamp() / 1_000_000_000
+            > u64::from(attestation.fulfilled_at) + self.dispute_window
+        {
+            env::panic_str("Dispute window has passed");
+        }
+        verify_signature(&attestation.signer, signed_hash, signature);
+
+        // Attestation is removed, so the bond is slashed once for it
+        self.attestations.remove(key);
+        let (near, tokens) = match self.relayer_bonds.get(&attestation.signer) {
+            Some(bond) => {
+                let near = u128::from(bond.near) + u128::from(bond.unbonding_near);
+                let tokens = u128::from(bond.tokens) + u128::from(bond.unbonding_tokens);
+                let bond = RelayerBond {
+                    near: U128::from(0),
+                    tokens: U128::from(0),
+                    unbonding_near: U128::from(0),
+                    unbonding_tokens: U128::from(0),
+                    ..bond
+                };
+                self.relayer_bonds.insert(&attestation.signer, &bond);
+                (near, tokens)
+            }
+            None => (0, 0),
+        };
+        self.total_bonded_near -= near;
+        self.total_bonded_tokens -= tokens;
+
+        let watcher = env::predecessor_account_id();
+        let log = format!(
+            "Relayer key {} is slashed by {} yoctoNEAR and {} tokens for {} conflicting with {}",
+            String::from(&attestation.signer),
+            near,
+            tokens,
+            tx_hash,
+            attestation.tx_hash
+        );
+        env::log_str(&log);
+        if near != 0 {
+            Promise::new(watcher).transfer(near);
+        }
+    }
+
+    pub fn set_min_relayer_bond(&mut self, near: U128, tokens: U128) {
+        self.only_owner(env::predecessor_account_id());
+        self.min_relayer_bond_near = u128::from(near);
+        self.min_relayer_bond_tokens = u128::from(tokens);
+    }
+
+    // Unbonding period should be longer than dispute window, so bonds can't leave before the
+    // fraud proof of their last fulfill
+    pub fn set_dispute_window(&mut self, dispute_window: U64) {
+        self.only_owner(env::predecessor_account_id());
+        if u64::from(dispute_window) >= self.unbonding_period {
+            env::panic_str("Dispute window should be shorter than unbonding period");
+        }
+        self.dispute_window = u64::from(dispute_window);
+    }
+
+    pub fn set_unbonding_period(&mut self, unbonding_period: U64) {
+        self.only_owner(env::predecessor_account_id());
+        if u64::from(unbonding_period) <= self.dispute_window {
+            env::panic_str("Unbonding period should be longer than dispute window");
+        }
+        self.unbonding_period = u64::from(unbonding_period);
+    }
+
+    /*
+        --------------------
+        Optimistic functions
+        --------------------
+    */
+    // Stores the claim with its storage paid from attached deposit, the excess is returned
+    fn record_optimistic_claim(&mut self, tx_hash: String, claim: OptimisticClaim) {
+        let initial_storage_usage = env::storage_usage();
+        self.optimistic_claims.insert(&tx_hash, &claim);
+        let storage_deposit =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        if env::attached_deposit() < storage_deposit {
+            env::panic_str("Not enough NEAR attached");
+        }
+        let claim = OptimisticClaim {
+            storage_deposit: U128::from(storage_deposit),
+            ..claim
+        };
+        self.optimistic_claims.insert(&tx_hash, &claim);
+        if env::attached_deposit() > storage_deposit {
+            Promise::new(claim.relayer.clone())
+                .transfer(env::attached_deposit() - storage_deposit);
+        }
+        let log = format!(
+            "Claimed {} by {}, it can be finalized after {}",
+            tx_hash,
+            claim.relayer,
+            u64::from(claim.finalize_after)
+        );
+        env::log_str(&log);
+    }
+
+    // Dispenses the claim after its challenge window the same way as fulfill does, anyone can
+    // call it. Storage is paid the same way as well
+    #[payable]
+    pub fn finalize(&mut self, tx_hash: String) {
+        if env::attached_deposit() == 0 {
+            env::panic_str("Requires attached deposit of at least 1 yoctoNEAR");
+        }
+        let claim = self
+            .optimistic_claims
+            .get(&tx_hash)
+            .unwrap_or_else(|| env::panic_str("No claim with this hash"));
+        if claim.status == ClaimStatus::Challenged {
+            env::panic_str("Claim is challenged");
+        }
+        if self.fulfilled.contains(&tx_hash) {
+            env::panic_str("Tx has already been fulfilled");
+        }
+        if env::block_timestamp() / 1_000_000_000 < u64::from(claim.finalize_after) {
+            env::panic_str("Challenge window hasn't passed yet");
+        }
+        self.check_fulfill_gas(claim.msg.as_ref(), claim.call.as_ref());
+        if let Some(call) = &claim.call {
+            self.check_message_receiver(call);
+        }
+        let to_user = self.check_inbound_transaction(&claim.transaction);
+        let registration_deposit = self.check_registration_gas(claim.register_recipient);
+
+        self.optimistic_claims.remove(&tx_hash);
+        Promise::new(claim.relayer.clone()).transfer(u128::from(claim.storage_deposit));
+        env::log_str(&format!("Finalized {}", tx_hash));
+        self.start_fulfill(
+            &claim.transaction,
+            tx_hash,
+            to_user,
+            registration_deposit,
+            claim.msg,
+            claim.call,
+            claim.signer,
+        );
+    }
+
+    // Stops the pending claim from being finalized until a guardian resolves the challenge,
+    // challenge bond should be attached. A claim can be challenged only once
+    #[payable]
+    pub fn challenge(&mut self, tx_hash: String) {
+        let claim = self
+            .optimistic_claims
+            .get(&tx_hash)
+            .unwrap_or_else(|| env::panic_str("No claim with this hash"));
+        if claim.challenger.is_some() {
+            env::panic_str("Claim has already been challenged");
+        }
+        if self.guardians.is_empty() {
+            env::panic_str("No guardian can resolve the challenge");
+        }
+        if env::block_timestamp() / 1_000_000_000 >= u64::from(claim.finalize_after) {
+            env::panic_str("Challenge window has passed");
+        }
+        if env::attached_deposit() < self.challenge_bond {
+            env::panic_str("Attached deposit is less than challenge bond");
+        }
+        let challenger = env::predecessor_account_id();
+        let claim = OptimisticClaim {
+            status: ClaimStatus::Challenged,
+            challenger: Some(challenger.clone()),
+            challenge_bond: U128::from(env::attached_deposit()),
+            ..claim
+        };
+        self.optimistic_claims.insert(&tx_hash, &claim);
+        self.total_challenge_bonds += env::attached_deposit();
+        env::log_str(&format!("Claim {} is challenged by {}", tx_hash, challenger));
+    }
+
+    // Guardian rejects the pending or challenged claim, so the transaction can't be fulfilled.
+    // Challenge bond is returned to the challenger and the claim storage to its relayer, except
+    // the cost of the vetoed hash
+    pub fn veto(&mut self, tx_hash: String) {
+        self.only_guardian(env::predecessor_account_id());
+        let claim = self
+            .optimistic_claims
+            .remove(&tx_hash)
+            .unwrap_or_else(|| env::panic_str("No claim with this hash"));
+        self.vetoed.insert(&tx_hash);
+        if let Some(challenger) = claim.challenger.clone() {
+            if u128::from(claim.challenge_bond) != 0 {
+                self.total_challenge_bonds -= u128::from(claim.challenge_bond);
+                Promise::new(challenger).transfer(u128::from(claim.challenge_bond));
+            }
+        }
+        let vetoed_cost = map_entry_bytes(StorageKey::Vetoed, &tx_hash, &()) as u128
+            * env::STORAGE_PRICE_PER_BYTE;
+        let refund = u128::from(claim.storage_deposit).saturating_sub(vetoed_cost);
+        if refund != 0 {
+            Promise::new(claim.relayer).transfer(refund);
+        }
+        env::log_str(&format!("Claim {} is vetoed", tx_hash));
+    }
+
+    // Guardian rejects the challenge, the claim can be finalized after its window and the
+    // challenge bond is paid to its relayer. The challenger is kept, so it can't be challenged
+    // again
+    pub fn dismiss_challenge(&mut self, tx_hash: String) {
+        self.only_guardian(env::predecessor_account_id());
+        let claim = self
+            .optimistic_claims
+            .get(&tx_hash)
+            .unwrap_or_else(|| env::panic_str("No claim with this hash"));
+        if claim.status != ClaimStatus::Challenged {
+            env::panic_str("Claim isn't challenged");
+        }
+        if u128::from(claim.challenge_bond) != 0 {
+            self.total_challenge_bonds -= u128::from(claim.challenge_bond);
+            Promise::new(claim.relayer.clone()).transfer(u128::from(claim.challenge_bond));
+        }
+        let claim = OptimisticClaim {
+            status: ClaimStatus::Pending,
+            challenge_bond: U128::from(0),
+            ..claim
+        };
+        self.optimistic_claims.insert(&tx_hash, &claim);
+        env::log_str(&format!("Challenge of claim {} is dismissed", tx_hash));
+    }
+
+    pub fn only_guardian(&self, caller: AccountId) {
+        if !self.guardians.contains(&caller) {
+            env::panic_str("Only guardian can call this method");
+        }
+    }
+
+    // Transactions of the chain are claimed by fulfill and dispensed by finalize after the
+    // window, None disables it for new fulfills and refunds the entry storage. The window needs
+    // challenge bond and guardians, so challenges aren't free and can be resolved
+    #[payable]
+    pub fn set_optimistic_window(&mut self, chain: String, window: Option<U64>) {
+        self.only_owner(env::predecessor_account_id());
+        if !self.available_chains.contains(&chain) {
+            env::panic_str("Chain is not in the list yet");
+        }
+        if window.is_some() && (self.challenge_bond == 0 || self.guardians.is_empty()) {
+            env::panic_str("Challenge bond and guardians should be set first");
+        }
+        let initial_storage_usage = env::storage_usage();
+        match window {
+            Some(window) => self.optimistic_windows.insert(&chain, &u64::from(window)),
+            None => self.optimistic_windows.remove(&chain),
+        };
+        let final_storage_usage = env::storage_usage();
+        if final_storage_usage > initial_storage_usage {
+            let storage_cost = (final_storage_usage - initial_storage_usage) as u128
+                * env::STORAGE_PRICE_PER_BYTE;
+            if env::attached_deposit() < storage_cost {
+                env::panic_str("Not enough NEAR attached");
+            }
+        } else if final_storage_usage < initial_storage_usage {
+            let repayment = (initial_storage_usage - final_storage_usage) as u128
+                * env::STORAGE_PRICE_PER_BYTE;
+            Promise::new(env::predecessor_account_id()).transfer(repayment);
+        }
+    }
+
+    #[payable]
+    pub fn add_guardian(&mut self, guardian: AccountId) {
+        self.only_owner(env::predecessor_account_id());
+        if self.guardians.contains(&guardian) {
+            env::panic_str("Guardian is already in the list");
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.guardians.insert(&guardian);
+        if env::attached_deposit()
+            < (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE
+        {
+            env::panic_str("Not enough NEAR attached");
+        }
+    }
+
+    pub fn remove_guardian(&mut self, guardian: AccountId) {
+        self.only_owner(env::predecessor_account_id());
+        if !self.guardians.contains(&guardian) {
+            env::panic_str("Guardian is not in the list yet");
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.guardians.remove(&guardian);
+        let repayment =
+            (initial_storage_usage - env::storage_usage()) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        Promise::new(env::predecessor_account_id()).transfer(repayment);
+    }
+
+    pub fn set_challenge_bond(&mut self, challenge_bond: U128) {
+        self.only_owner(env::predecessor_account_id());
+        if u128::from(challenge_bond) == 0 {
+            env::panic_str("Challenge bond should be greater than 0");
+        }
+        if u128::from(challenge_bond) == self.challenge_bond {
+            env::panic_str("Current challenge bond is equal to new challenge bond");
+        }
+        self.challenge_bond = u128::from(challenge_bond);
+    }
+
+    /*
+        -------------
+        NFT functions
+        -------------
+    */
+    // NFT transactions are kept apart from the token history: sends are stored only by their
+    // own nonce and unlocks only by fulfilled hash. They have no status, user index or pruning
+    // and aren't shown by history views or counted in stats
+    // Callback for nft_token in nft_on_transfer, records the send and keeps the NFT locked.
+    // Returns true if the NFT should be returned
+    #[private]
+    pub fn resolve_nft_send(
+        &mut self,
+        #[callback_result] callback_result: Result<Option<Token>, PromiseError>,
+        owner_id: AccountId,
+        transaction: NftTransaction,
+    ) -> bool {
+        let token = match callback_result {
+            Ok(Some(token)) => token,
+            _ => {
+                env::log_str("PANIC: NFT token is unavailable");
+                return true;
+            }
+        };
+        let metadata = near_sdk::serde_json::to_vec(&token.metadata).unwrap();
+        let transaction = NftTransaction {
+            metadata_hash: hex::encode(env::sha256(&metadata)),
+            nonce: self.nft_nonce,
+            ..transaction
+        };
+
+        let initial_storage_usage = env::storage_usage();
+        let nonce = u128::from(transaction.nonce);
+        self.nft_outbound.insert(&nonce, &transaction);
+        let storage_paid_for_send =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        let user_storage_paid = self.storage_paid.get(&owner_id).unwrap_or(0);
+        if user_storage_paid < storage_paid_for_send {
+            env::log_str("PANIC: Not enough storage paid");
+            env::panic_str("Not enough storage paid");
+        }
+        self.storage_paid
+            .insert(&owner_id, &(user_storage_paid - storage_paid_for_send));
+        self.total_storage_paid -= storage_paid_for_send;
+        self.nft_nonce = U128::from(nonce + 1);
+
+        let log = format!(
+            "Sent NFT {} of {} from {} to {} in direction {}->{}",
+            transaction.token_id,
+            transaction.nft_contract,
+            transaction.from_user,
+            transaction.to_user,
+            CURRENT_CHAIN,
+            transaction.to_chain
+        );
+        env::log_str(&log);
+        false
+    }
+
+    // Unlocks NFT of transaction from another chain, it's signed the same way as token ones
+    // with its own domain
+    #[payable]
+    pub fn fulfill_nft(&mut self, transaction: NftTransaction, signature: Vec<u8>) {
+        if env::attached_deposit() == 0 {
+            env::panic_str("Requires attached deposit of at least 1 yoctoNEAR");
+        }
+        if env::prepaid_gas() < GAS_FOR_FULFILL {
+            env::panic_str("Not enough gas prepaid, at least 80 Tgas is needed");
+        }
+        let to_user = AccountId::try_from(transaction.to_user.clone()).unwrap_or_else(|_| {
+            env::panic_str("Not convertible transaction.to field to AccountId type")
+        });
+        if transaction.to_chain != CURRENT_CHAIN {
+            env::panic_str("Wrong 'toChain' in tx struct");
+        }
+        if !self.is_available_chain(transaction.from_chain.clone()) {
+            env::panic_str("Not supported fromChain in tx struct");
         }
-    }
-
-    // Callback for fulfill
-    #[private]
-    pub fn resolve_fulfill(
-        &mut self,
-        #[callback_result] callback_result: Result<Option<StorageBalance>, PromiseError>,
-        current_fee: u128,
-        transaction: &Transaction,
-        tx_hash: &String,
-        to_user: AccountId,
-        storage_paid_for_call: Balance
-    ) {
-        if callback_result.is_err() {
-            env::log_str("fee wallet didn't storage deposit to token (you can call storage_deposit function on token contract for fee_wallet)");
-            self.rollback_state(&tx_hash, to_user.clone(), storage_paid_for_call);
-        } else {
-            let result = callback_result.unwrap().unwrap_or(StorageBalance {
-                total: U128(0),
-                available: U128(0),
-            });
-            if result.total < U128::from(MIN_TOKEN_STORAGE_DEPOSIT) {
-                env::log_str("fee wallet didn't storage deposit to token (you can call storage_deposit function on token contract for fee_wallet)");
-                self.rollback_state(&tx_hash, to_user.clone(), storage_paid_for_call);
-            } else {
-                self.dispense_ft_to_user(&transaction, &tx_hash, current_fee, to_user.clone(), storage_paid_for_call);
-            }
+        if !self.nft_contracts.contains(&transaction.nft_contract) {
+            env::panic_str("Not supported NFT contract");
+        }
+        // NFT transactions have no claims, so they can't wait for the optimistic window
+        if self.optimistic_windows.contains_key(&transaction.from_chain) {
+            env::panic_str("NFT tx of optimistic chain isn't supported");
         }
-    }
 
-    #[private]
-    pub fn dispense_ft_to_user(
-        &mut self,
-        transaction: &Transaction,
-        tx_hash: &String,
-        current_fee: u128,
-        to_user: AccountId,
-        storage_paid_for_call: Balance
-    ) {
-        let dispense_amount = u128::from(transaction.amount) - current_fee;
+        // Tx reply check
+        let tx_hash_bytes = self.get_nft_tx_hash(&transaction);
+        let tx_hash = hex::encode(&tx_hash_bytes);
+        self.check_tx_replay(&tx_hash);
+
+        // Signature check
+        self.verify_relayer_signature(&tx_hash_bytes, &signature);
+        env::log_str("Signature has been verified");
+        self.check_relayer_bond(&self.relayer_role);
+
+        let attestation_key = self.get_attestation_key(
+            TransactionKind::Nft,
+            &transaction.from_chain,
+            transaction.nonce,
+        );
+        let initial_storage_usage = env::storage_usage();
+        self.fulfilled.insert(&tx_hash);
+        self.record_attestation(&attestation_key, &tx_hash, self.relayer_role.clone());
+        let storage_used =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        let storage_payer = self.charge_storage_for_fulfill(&to_user, storage_used, 0);
 
         let log = format!(
-            "Dispense {} tokens from {} to {} in direction {}->{}",
-            dispense_amount,
+            "Unlock NFT {} of {} from {} to {} in direction {}->{}",
+            transaction.token_id,
+            transaction.nft_contract,
             transaction.from_user,
             to_user,
-            transaction.from_chain.clone(),
+            transaction.from_chain,
             CURRENT_CHAIN
         );
         env::log_str(&log);
 
-        // Transfer FT to user
-        ext_ft_core::ext(self.token.clone())
+        let context = FulfillContext {
+            tx_hash,
+            to_user: to_user.clone(),
+            fee: 0,
+            storage_paid_for_call: storage_used,
+            storage_used,
+            storage_payer,
+            registration_deposit: 0,
+            funding_deposit: 0,
+            record_storage_cost: 0,
+            msg: None,
+        };
+        nft_contract::ext(transaction.nft_contract)
             .with_attached_deposit(1)
-            .ft_transfer(
-                to_user.clone(),
-                U128::from(dispense_amount),
-                Some("Dispensing from bridge".to_string()),
+            .nft_transfer(
+                to_user,
+                transaction.token_id,
+                None,
+                Some("Unlocking from bridge".to_string()),
             )
-            .then(Self::ext(env::current_account_id()).resolve_dispense(
-                &tx_hash,
-                &transaction,
-                to_user.clone(),
-                current_fee,
-                storage_paid_for_call
-            ));
+            .then(
+                Self::ext(env::current_account_id())
+                    .resolve_nft_unlock(context, attestation_key),
+            );
     }
 
-    // Callback for dispense_ft_to_user
+    // Callback for nft_transfer of fulfill_nft
     #[private]
-    pub fn resolve_dispense(
-        &mut self,
-        tx_hash: &String,
-        tx: &Transaction,
-        to_user: AccountId,
-        fee: u128,
-        storage_paid_for_call: Balance
-    ) {
-        let is_reverted = match env::promise_result(0) {
+    pub fn resolve_nft_unlock(&mut self, context: FulfillContext, attestation_key: String) {
+        match env::promise_result(0) {
             PromiseResult::NotReady => env::abort(),
-            PromiseResult::Successful(_) => false,
-            PromiseResult::Failed => true,
-        };
-
-        // rollback state if the promise is failed
-        if is_reverted {
-            env::log_str("ft_transfer promise failed (maybe you should call storage_deposit function on token contract for to_user in tx struct)");
-            self.rollback_state(&tx_hash, to_user.clone(), storage_paid_for_call);
-        } else {
-            // Else add tx in list and dispense fee if it is not equal to 0
-            let mut tx_vector = self.transactions.get(&tx.from_user).unwrap_or_else(|| {
-                Vector::new(StorageKey::TransactionsInner {
-                    account_id_hash: env::sha256_array(tx.from_user.as_bytes()),
-                })
-            });
-            tx_vector.push(&tx);
-            self.transactions.insert(&tx.from_user, &tx_vector);
-            if fee != 0 as u128 {
-                ext_ft_core::ext(self.token.clone())
-                    .with_attached_deposit(1)
-                    .ft_transfer(
-                        self.fee_wallet.clone(),
-                        U128::from(fee),
-                        Some("Transferring fee".to_string()),
-                    );
+            PromiseResult::Successful(_) => {}
+            PromiseResult::Failed => {
+                env::log_str("nft_transfer promise failed for to_user in tx struct");
+                self.fulfilled.remove(&context.tx_hash);
+                // Attestation may be already slashed or replaced by another transaction
+                if self
+                    .attestations
+                    .get(&attestation_key)
+                    .is_some_and(|attestation| attestation.tx_hash == context.tx_hash)
+                {
+                    self.attestations.remove(&attestation_key);
+                }
+                self.return_storage(&context, context.storage_paid_for_call);
             }
         }
     }
 
-    #[private]
-    pub fn rollback_state(&mut self, tx_hash: &String, to_user: AccountId, storage_paid_for_call: Balance) {
-        self.fulfilled.remove(tx_hash);
-        let user_storage_paid = self.storage_paid.get(&to_user).unwrap();
-        let new_storage_paid =
-            user_storage_paid + storage_paid_for_call;
-        self.storage_paid.insert(&to_user, &new_storage_paid);
-        self.total_storage_paid = self.total_storage_paid + storage_paid_for_call;
+    #[payable]
+    pub fn add_nft_contract(&mut self, nft_contract: AccountId) {
+        self.only_owner(env::predecessor_account_id());
+        if self.nft_contracts.contains(&nft_contract) {
+            env::panic_str("NFT contract is already in the list");
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.nft_contracts.insert(&nft_contract);
+        if env::attached_deposit()
+            < (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE
+        {
+            env::panic_str("Not enough NEAR attached");
+        }
+    }
+
+    // Locked NFTs of the removed contract can be unlocked after it's added back
+    pub fn remove_nft_contract(&mut self, nft_contract: AccountId) {
+        self.only_owner(env::predecessor_account_id());
+        if !self.nft_contracts.contains(&nft_contract) {
+            env::panic_str("NFT contract is not in the list yet");
+        }
+        let initial_storage_usage = env::storage_usage();
+        self.nft_contracts.remove(&nft_contract);
+        let repayment =
+            (initial_storage_usage - env::storage_usage()) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        Promise::new(env::predecessor_account_id()).transfer(repayment);
+    }
+
+    /*
+        --------------------
+        Checkpoint functions
+        --------------------
+    */
+    // Posts merkle root of transfers signed by the relayer role, anyone can submit it
+    #[payable]
+    pub fn submit_checkpoint(&mut self, checkpoint: Checkpoint, signature: Vec<u8>) {
+        if !self.is_available_chain(checkpoint.chain.clone()) {
+            env::panic_str("Chain is not supported");
+        }
+        if u64::from(checkpoint.from_block) > u64::from(checkpoint.to_block) {
+            env::panic_str("Wrong block range in checkpoint");
+        }
+        let root = hex::decode(&checkpoint.root)
+            .unwrap_or_else(|_| env::panic_str("Checkpoint root should be a hex string"));
+        if root.len() != 32 {
+            env::panic_str("Checkpoint root should be 32 bytes long");
+        }
+        if self.checkpoints.contains_key(&hex::encode(&root)) {
+            env::panic_str("Checkpoint is already submitted");
+        }
+        let from_block = u64::from(checkpoint.from_block);
+        if self
+            .last_checkpoint_block
+            .get(&checkpoint.chain)
+            .is_some_and(|last_block| from_block <= last_block)
+        {
+            env::panic_str("Checkpoint overlaps blocks of a previous checkpoint");
+        }
+
+        let checkpoint_hash = self.get_checkpoint_hash(&checkpoint);
+        self.verify_relayer_signature(&checkpoint_hash, &signature);
+
+        // Roots are kept in lowercase to match hashes computed from merkle proofs
+        let checkpoint = Checkpoint {
+            root: hex::encode(root),
+            ..checkpoint
+        };
+        let attached_near = env::attached_deposit();
+        let initial_storage_usage = env::storage_usage();
+        self.checkpoints.insert(&checkpoint.root, &checkpoint);
+        self.last_checkpoint_block
+            .insert(&checkpoint.chain, &u64::from(checkpoint.to_block));
+        let storage_cost =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        if attached_near < storage_cost {
+            env::panic_str("Not enough NEAR attached");
+        }
+        if attached_near > storage_cost {
+            Promise::new(env::predecessor_account_id()).transfer(attached_near - storage_cost);
+        }
+
+        let log = format!(
+            "Checkpoint {} submitted for blocks {}..{} of {}",
+            checkpoint.root,
+            u64::from(checkpoint.from_block),
+            u64::from(checkpoint.to_block),
+            checkpoint.chain
+        );
+        env::log_str(&log);
+    }
+
+    // Folds the proof into the root, pairs are hashed sorted as in OpenZeppelin MerkleProof.
+    // Leaves are hashed with their own domain, so an inner node can't pass for a transaction
+    fn compute_merkle_root(&self, leaf: &[u8], merkle_proof: &[String]) -> Vec<u8> {
+        merkle_proof.iter().fold(leaf.to_vec(), |node, proof_element| {
+            let sibling = hex::decode(proof_element).unwrap_or_else(|_| {
+                env::panic_str("Merkle proof element should be a hex string")
+            });
+            if sibling.len() != 32 {
+                env::panic_str("Merkle proof element should be 32 bytes long");
+            }
+            if node <= sibling {
+                env::keccak256(&[node, sibling].concat())
+            } else {
+                env::keccak256(&[sibling, node].concat())
+            }
+        })
     }
 
     /*
@@ -427,85 +3731,372 @@ impl BridgeAssist {
         self.bytes_for_register = env::storage_usage() - initial_storage_usage;
         self.storage_paid.remove(&tmp_account_id);
 
+        // Upper estimates for ft_on_transfer and fulfill, the calls are charged by real storage
         // for one call ft_on_transfer
         let initial_storage_usage = env::storage_usage();
-        let to_addr = String::from("a".repeat(ETH_ADDRESS_LENGTH.into()));
+        let to_addr = "a".repeat(MAX_ADDRESS_LENGTH);
         let tx_data = Transaction {
             from_user: tmp_account_id.to_string(),
             to_user: to_addr.clone(),
             amount: U128::from(0u128),
             timestamp: U64::from(0),
-            from_chain: String::from("a".repeat(64)),
-            to_chain: String::from("a".repeat(64)),
+            from_chain: "a".repeat(64),
+            to_chain: "a".repeat(64),
             nonce: U128::from(0),
         };
-        let mut tx_vector = self
-            .transactions
-            .get(&tx_data.from_user)
-            .unwrap_or_else(|| {
-                Vector::new(StorageKey::TransactionsInner {
-                    account_id_hash: env::sha256_array(tmp_account_id.as_bytes()),
-                })
-            });
-        tx_vector.push(&tx_data);
-        self.transactions.insert(&tx_data.from_user, &tx_vector);
+        let nonce = u128::from(tx_data.nonce);
+        let mut nonces = self.get_outbound_nonces(&tmp_account_id);
+        nonces.push(&nonce);
+        self.outbound_by_user.insert(&tmp_account_id, &nonces);
+        let tx_hash = hex::encode(self.get_tx_hash(&tx_data));
+        self.outbound_by_hash.insert(&tx_hash, &nonce);
+        let idempotency_hash = self.get_idempotency_hash(&tmp_account_id, "");
+        let record = OutboundRecord {
+            transaction: tx_data.clone(),
+            status: TransactionStatus::Pending,
+            idempotency_hash: Some(idempotency_hash),
+            payload: None,
+            native: false,
+        };
+        self.outbound_transactions.insert(&nonce, &record);
+        self.user_volumes.insert(&tmp_account_id, &UserVolume::default());
+        self.idempotency_keys.insert(&idempotency_hash, &nonce);
         self.bytes_for_ft_on_transfer = env::storage_usage() - initial_storage_usage;
-        self.transactions.remove(&tx_data.from_user);
+        self.user_volumes.remove(&tmp_account_id);
+        self.idempotency_keys.remove(&idempotency_hash);
+        self.get_outbound_nonces(&tmp_account_id).clear();
+        self.outbound_by_user.remove(&tmp_account_id);
+        self.outbound_by_hash.remove(&tx_hash);
+        self.outbound_transactions.remove(&nonce);
 
-        // for successful fulfill
+        // for successful fulfill, a native one that also stores its flag
         let initial_storage_usage = env::storage_usage();
-        let tx_hash_bytes = env::keccak256(
-            &bincode::serialize(&tx_data)
-                .unwrap_or_else(|_| env::panic_str("Serializing transaction field is failed")),
-        );
-        let tx_hash = hex::encode(tx_hash_bytes);
         self.fulfilled.insert(&tx_hash);
-        self.bytes_for_fulfill =
-            env::storage_usage() - initial_storage_usage + self.bytes_for_ft_on_transfer;
+        self.record_inbound_transaction(
+            &tx_data,
+            &tx_hash,
+            &tmp_account_id,
+            0,
+            None,
+            None,
+            None,
+            true,
+        );
+        self.roll_back_inbound_record(&tx_hash, FailureReason::TransferFailed);
+        self.user_volumes.insert(&tmp_account_id, &UserVolume::default());
+        self.bytes_for_fulfill = env::storage_usage() - initial_storage_usage;
+        self.user_volumes.remove(&tmp_account_id);
+        self.native_inbound.remove(&tx_hash);
         self.fulfilled.remove(&tx_hash);
+        self.get_inbound_hashes(&tmp_account_id).clear();
+        self.inbound_by_user.remove(&tmp_account_id);
+        self.inbound_transactions.remove(&tx_hash);
 
         // for add chain
         let initial_storage_usage = env::storage_usage();
-        let average_chain = String::from("A".repeat(5));
+        let average_chain = "A".repeat(5);
         self.available_chains.insert(&average_chain);
+        self.chain_stats.insert(&average_chain, &ChainStats::default());
         self.bytes_for_add_chain = env::storage_usage() - initial_storage_usage;
         self.available_chains.remove(&average_chain);
+        self.chain_stats.remove(&average_chain);
+
+        // for submit checkpoint
+        let initial_storage_usage = env::storage_usage();
+        let checkpoint = Checkpoint {
+            chain: "a".repeat(64),
+            from_block: U64::from(0),
+            to_block: U64::from(0),
+            root: hex::encode([0u8; 32]),
+        };
+        self.checkpoints.insert(&checkpoint.root, &checkpoint);
+        self.last_checkpoint_block.insert(&checkpoint.chain, &0);
+        self.bytes_for_checkpoint = env::storage_usage() - initial_storage_usage;
+        self.checkpoints.remove(&checkpoint.root);
+        self.last_checkpoint_block.remove(&checkpoint.chain);
     }
 
-    #[payable]
-    pub fn storage_deposit(&mut self) {
-        let user = env::predecessor_account_id();
-        let attached_near = env::attached_deposit();
-        if !self.storage_paid.contains_key(&user) {
-            if attached_near < self.bytes_for_register as u128 * env::STORAGE_PRICE_PER_BYTE {
-                env::panic_str("Not enough NEAR attached");
+    /*
+        ------------------------
+        History pruning functions
+        ------------------------
+    */
+    // Removes up to `limit` oldest finished transactions of the user in the given direction that
+    // are older than retention period and refunds the freed storage to whoever paid for it: the
+    // sender of outbound ones, the fulfill caller of inbound ones it paid with attached deposit
+    // and the recipient of the rest. Indexes of the rest are shifted
+    pub fn prune_transactions(
+        &mut self,
+        user: AccountId,
+        direction: TransactionDirection,
+        limit: U64,
+    ) {
+        let limit = u64::from(limit) as usize;
+        let initial_storage_usage = env::storage_usage();
+        let (pruned, refunded_to_payers) = match direction {
+            TransactionDirection::Outbound => (self.prune_outbound_transactions(&user, limit), 0),
+            TransactionDirection::Inbound => self.prune_inbound_transactions(&user, limit),
+        };
+        if pruned == 0 {
+            env::panic_str("No transactions to prune");
+        }
+        let refund =
+            (initial_storage_usage - env::storage_usage()) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        self.refund_storage(user.clone(), refund.saturating_sub(refunded_to_payers));
+
+        let log = format!(
+            "Pruned {} transactions of {}, {} yoctoNEAR of storage refunded",
+            pruned,
+            user,
+            refund
+        );
+        env::log_str(&log);
+    }
+
+    fn prune_outbound_transactions(&mut self, user: &AccountId, limit: usize) -> usize {
+        let mut nonces = self.get_outbound_nonces(user);
+        let pruned: Vec<u128> = nonces
+            .iter()
+            .take(limit)
+            .take_while(|nonce| {
+                let record = self.outbound_transactions.get(nonce).unwrap();
+                self.is_prunable(&record.transaction, record.status)
+            })
+            .collect();
+        nonces.remove_first(pruned.len() as u64);
+        if nonces.is_empty() {
+            self.outbound_by_user.remove(user);
+        } else {
+            self.outbound_by_user.insert(user, &nonces);
+        }
+        for nonce in pruned.iter() {
+            self.outbound_payloads.remove(nonce);
+            let record = self.outbound_transactions.remove(nonce).unwrap();
+            self.outbound_by_hash
+                .remove(&self.get_outbound_hash(&record.transaction));
+            // Later send with the same key takes the entry over
+            if let Some(hash) = record.idempotency_hash {
+                if self.idempotency_keys.get(&hash) == Some(*nonce) {
+                    self.idempotency_keys.remove(&hash);
+                }
             }
-            let excess =
-                attached_near - self.bytes_for_register as u128 * env::STORAGE_PRICE_PER_BYTE;
-            self.storage_paid.insert(&user, &excess);
-            self.total_storage_paid = self.total_storage_paid + excess;
+            self.native_outbound.remove(nonce);
+        }
+        pruned.len()
+    }
+
+    // Fulfilled hashes are kept, so pruned transactions still can't be fulfilled again. Storage
+    // of records paid by fulfill callers is refunded to them, returns the count of pruned
+    // transactions and the amount refunded to the callers
+    fn prune_inbound_transactions(&mut self, user: &AccountId, limit: usize) -> (usize, Balance) {
+        let mut hashes = self.get_inbound_hashes(user);
+        let pruned: Vec<String> = hashes
+            .iter()
+            .take(limit)
+            .take_while(|tx_hash| {
+                let record = self.inbound_transactions.get(tx_hash).unwrap();
+                self.is_inbound_prunable(tx_hash, &record)
+            })
+            .collect();
+        hashes.remove_first(pruned.len() as u64);
+        if hashes.is_empty() {
+            self.inbound_by_user.remove(user);
         } else {
-            let new_storage_balance = self.storage_paid.get(&user).unwrap() + attached_near;
-            self.storage_paid.insert(&user, &new_storage_balance);
-            self.total_storage_paid = self.total_storage_paid + attached_near;
+            self.inbound_by_user.insert(user, &hashes);
         }
+        let mut refunded_to_payers = 0;
+        for tx_hash in pruned.iter() {
+            let initial_storage_usage = env::storage_usage();
+            let record = self.inbound_transactions.remove(tx_hash).unwrap();
+            self.transfer_calls.remove(tx_hash);
+            self.inbound_messages.remove(tx_hash);
+            let key = self.get_record_attestation_key(tx_hash, &record.transaction);
+            self.native_inbound.remove(tx_hash);
+            if self
+                .attestations
+                .get(&key)
+                .is_some_and(|attestation| attestation.tx_hash == *tx_hash)
+            {
+                self.attestations.remove(&key);
+            }
+            // Payer gets storage freed by the record itself, it can't exceed the real refund
+            if let Some(payer) = record.storage_payer {
+                let user_list = StorageKey::InboundByUserInner {
+                    account_id_hash: env::sha256_array(user.as_bytes()),
+                };
+                let freed_bytes = initial_storage_usage - env::storage_usage()
+                    + vector_element_bytes(user_list, tx_hash);
+                let storage_cost = freed_bytes as u128 * env::STORAGE_PRICE_PER_BYTE;
+                self.refund_storage(payer, storage_cost);
+                refunded_to_payers += storage_cost;
+            }
+        }
+        (pruned.len(), refunded_to_payers)
     }
 
-    pub fn storage_withdraw(&mut self, amount: U128) {
-        let amount = u128::from(amount);
-        let user = env::predecessor_account_id();
-        let user_storage_paid = self
-            .storage_paid
-            .get(&user)
-            .unwrap_or_else(|| env::panic_str("No storage paid"));
-        if amount > user_storage_paid {
-            env::panic_str("Amount is more than your storage paid");
+    // Record with a refund that isn't claimed yet is kept, as the claim needs its recipient
+    fn is_inbound_prunable(&self, tx_hash: &String, record: &InboundRecord) -> bool {
+        self.is_prunable(&record.transaction, record.status)
+            && self
+                .transfer_calls
+                .get(tx_hash)
+                .map_or(true, |transfer_call| transfer_call.refunded_amount.0 == 0)
+    }
+
+    fn is_prunable(&self, tx: &Transaction, status: TransactionStatus) -> bool {
+        status != TransactionStatus::Pending
+            && u64::from(tx.timestamp) + self.retention_period
+                <= env::block_timestamp() / 1_000_000_000
+    }
+
+    // Cost of the records holding outbound transaction in the store, user's list and hash index
+    fn outbound_storage_cost(&self, user: &AccountId, record: &OutboundRecord) -> Balance {
+        let nonce = u128::from(record.transaction.nonce);
+        let tx_hash = self.get_outbound_hash(&record.transaction);
+        let user_list = StorageKey::OutboundByUserInner {
+            account_id_hash: env::sha256_array(user.as_bytes()),
+        };
+        let payload_bytes = self.outbound_payloads.get(&nonce).map_or(0, |payload| {
+            map_entry_bytes(StorageKey::OutboundPayloads, &nonce, &payload)
+        });
+        // LookupSet entry has an empty value
+        let native_bytes = if self.native_outbound.contains(&nonce) {
+            map_entry_bytes(StorageKey::NativeOutbound, &nonce, &())
         } else {
-            Promise::new(user.clone()).transfer(amount);
-            self.storage_paid
-                .insert(&user, &(user_storage_paid - amount));
-            self.total_storage_paid = self.total_storage_paid - amount;
+            0
+        };
+        let bytes = map_entry_bytes(StorageKey::OutboundTransactions, &nonce, record)
+            + map_entry_bytes(StorageKey::OutboundByHash, &tx_hash, &nonce)
+            + vector_element_bytes(user_list, &nonce)
+            + payload_bytes
+            + native_bytes;
+        bytes as u128 * env::STORAGE_PRICE_PER_BYTE
+    }
+
+    // Cost of the records holding inbound transaction in the store and user's list
+    fn inbound_storage_cost(
+        &self,
+        user: &AccountId,
+        tx_hash: &String,
+        record: &InboundRecord,
+    ) -> Balance {
+        let user_list = StorageKey::InboundByUserInner {
+            account_id_hash: env::sha256_array(user.as_bytes()),
+        };
+        let transfer_call_bytes = self.transfer_calls.get(tx_hash).map_or(0, |transfer_call| {
+            map_entry_bytes(StorageKey::TransferCalls, tx_hash, &transfer_call)
+        });
+        let message_bytes = self.inbound_messages.get(tx_hash).map_or(0, |message| {
+            map_entry_bytes(StorageKey::InboundMessages, tx_hash, &message)
+        });
+        // LookupSet entry has an empty value
+        let native_bytes = if self.native_inbound.contains(tx_hash) {
+            map_entry_bytes(StorageKey::NativeInbound, tx_hash, &())
+        } else {
+            0
+        };
+        let key = self.get_record_attestation_key(tx_hash, &record.transaction);
+        let attestation_bytes = self
+            .attestations
+            .get(&key)
+            .filter(|attestation| attestation.tx_hash == *tx_hash)
+            .map_or(0, |attestation| {
+                map_entry_bytes(StorageKey::Attestations, &key, &attestation)
+            });
+        let bytes = map_entry_bytes(StorageKey::InboundTransactions, tx_hash, record)
+            + vector_element_bytes(user_list, tx_hash)
+            + transfer_call_bytes
+            + message_bytes
+            + native_bytes
+            + attestation_bytes;
+        bytes as u128 * env::STORAGE_PRICE_PER_BYTE
+    }
+
+    /*
+        -----------------
+        Upgrade functions
+        -----------------
+    */
+    // Stages wasm passed as raw input, it can be deployed after the upgrade delay. Its storage
+    // is paid with attached deposit and returned when the upgrade is deployed or cancelled
+    #[payable]
+    pub fn stage_upgrade(&mut self) {
+        self.only_owner(env::predecessor_account_id());
+        if self.staged_upgrade.is_some() {
+            env::panic_str("Upgrade is already staged");
+        }
+        let code = env::input().unwrap_or_else(|| env::panic_str("No code is passed"));
+        if code.is_empty() {
+            env::panic_str("No code is passed");
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        env::storage_write(STAGED_CODE_KEY, &code);
+        let staged_upgrade = StagedUpgrade {
+            code_hash: hex::encode(env::sha256(&code)),
+            deploy_after: U64::from(env::block_timestamp() / 1_000_000_000 + UPGRADE_DELAY),
+        };
+        let log = format!(
+            "Upgrade with code hash {} is staged, it can be deployed after {}",
+            staged_upgrade.code_hash,
+            u64::from(staged_upgrade.deploy_after)
+        );
+        self.staged_upgrade = Some(staged_upgrade);
+        let storage_cost =
+            (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        let attached_near = env::attached_deposit();
+        if attached_near < storage_cost {
+            env::panic_str("Not enough NEAR attached");
+        }
+        if attached_near > storage_cost {
+            Promise::new(env::predecessor_account_id()).transfer(attached_near - storage_cost);
+        }
+        env::log_str(&log);
+    }
+
+    pub fn cancel_upgrade(&mut self) {
+        self.only_owner(env::predecessor_account_id());
+        let (staged_upgrade, _) = self.take_staged_upgrade();
+        let log = format!("Upgrade with code hash {} is cancelled", staged_upgrade.code_hash);
+        env::log_str(&log);
+    }
+
+    // Deploys staged code and migrates the state in the same receipt, so a failed migration
+    // reverts the deployment as well
+    pub fn deploy_upgrade(&mut self) -> Promise {
+        self.only_owner(env::predecessor_account_id());
+        if env::prepaid_gas() < GAS_FOR_DEPLOY_UPGRADE {
+            env::panic_str("Not enough gas prepaid, at least 150 Tgas is needed");
+        }
+        let deploy_after = self
+            .staged_upgrade
+            .as_ref()
+            .map(|staged_upgrade| u64::from(staged_upgrade.deploy_after))
+            .unwrap_or_else(|| env::panic_str("No upgrade is staged"));
+        if env::block_timestamp() / 1_000_000_000 < deploy_after {
+            env::panic_str("Upgrade delay has not passed yet");
         }
+        let (staged_upgrade, code) = self.take_staged_upgrade();
+        let log = format!("Upgrade with code hash {} is deployed", staged_upgrade.code_hash);
+        env::log_str(&log);
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), Vec::new(), 0, GAS_FOR_MIGRATE)
+    }
+
+    // Removes staged upgrade and returns its storage to the owner
+    fn take_staged_upgrade(&mut self) -> (StagedUpgrade, Vec<u8>) {
+        let staged_upgrade = self
+            .staged_upgrade
+            .take()
+            .unwrap_or_else(|| env::panic_str("No upgrade is staged"));
+        let initial_storage_usage = env::storage_usage();
+        let code = env::storage_read(STAGED_CODE_KEY).unwrap();
+        env::storage_remove(STAGED_CODE_KEY);
+        let refund =
+            (initial_storage_usage - env::storage_usage()) as u128 * env::STORAGE_PRICE_PER_BYTE;
+        Promise::new(self.owner.clone()).transfer(refund);
+        (staged_upgrade, code)
     }
 
     /*
@@ -532,6 +4123,10 @@ impl BridgeAssist {
         }
         let initial_storage_usage = env::storage_usage();
         self.available_chains.insert(&chain);
+        // Stats of a chain that was removed before are kept
+        if !self.chain_stats.contains_key(&chain) {
+            self.chain_stats.insert(&chain, &ChainStats::default());
+        }
         if attached_near
             < (env::storage_usage() - initial_storage_usage) as u128 * env::STORAGE_PRICE_PER_BYTE
         {
@@ -546,11 +4141,45 @@ impl BridgeAssist {
         }
         let initial_storage_usage = env::storage_usage();
         self.available_chains.remove(&chain);
+        self.chain_address_formats.remove(&chain);
+        self.optimistic_windows.remove(&chain);
         let repayment =
             (initial_storage_usage - env::storage_usage()) as u128 * env::STORAGE_PRICE_PER_BYTE;
         Promise::new(env::predecessor_account_id()).transfer(repayment);
     }
 
+    // EVM format is the default one, so setting it removes the entry and refunds its storage
+    #[payable]
+    pub fn set_chain_address_format(&mut self, chain: String, format: AddressFormat) {
+        self.only_owner(env::predecessor_account_id());
+        if !self.available_chains.contains(&chain) {
+            env::panic_str("Chain is not in the list yet");
+        }
+        if let AddressFormat::Cosmos { hrp } = &format {
+            if hrp.is_empty() || hrp.bytes().any(|byte| !byte.is_ascii_lowercase()) {
+                env::panic_str("Human readable part should be lowercase letters");
+            }
+        }
+        let initial_storage_usage = env::storage_usage();
+        if format == AddressFormat::Evm {
+            self.chain_address_formats.remove(&chain);
+        } else {
+            self.chain_address_formats.insert(&chain, &format);
+        }
+        let final_storage_usage = env::storage_usage();
+        if final_storage_usage > initial_storage_usage {
+            let storage_cost = (final_storage_usage - initial_storage_usage) as u128
+                * env::STORAGE_PRICE_PER_BYTE;
+            if env::attached_deposit() < storage_cost {
+                env::panic_str("Not enough NEAR attached");
+            }
+        } else if final_storage_usage < initial_storage_usage {
+            let repayment = (initial_storage_usage - final_storage_usage) as u128
+                * env::STORAGE_PRICE_PER_BYTE;
+            Promise::new(env::predecessor_account_id()).transfer(repayment);
+        }
+    }
+
     pub fn transfer_ownership(&mut self, owner: AccountId) {
         self.only_owner(env::predecessor_account_id());
         if owner == self.owner {
@@ -586,6 +4215,136 @@ impl BridgeAssist {
         self.limit_per_send = Balance::from(limit_per_send);
     }
 
+    pub fn set_retention_period(&mut self, retention_period: U64) {
+        self.only_owner(env::predecessor_account_id());
+        if u64::from(retention_period) == self.retention_period {
+            env::panic_str("Current retention period is equal to new retention period");
+        }
+        self.retention_period = u64::from(retention_period);
+    }
+
+    // Implicit recipients registered by fulfill get this amount of NEAR from the registration
+    // pool or the caller, 0 disables funding
+    pub fn set_implicit_account_funding(&mut self, amount: U128) {
+        self.only_owner(env::predecessor_account_id());
+        let amount = u128::from(amount);
+        if amount == self.implicit_account_funding {
+            env::panic_str("Current implicit account funding is equal to new funding");
+        }
+        if amount != 0 && amount < MIN_IMPLICIT_ACCOUNT_FUNDING {
+            env::panic_str("Implicit account funding should cover the account storage");
+        }
+        self.implicit_account_funding = amount;
+    }
+
+    // Marks how the outbound transaction ended on destination chain. Tokens of a completed send
+    // keep backing the bridged tokens, tokens of a refunded or rolled back one are returned to
+    // the sender and the send is pending again if the transfer fails
+    pub fn set_outbound_status(&mut self, nonce: U128, status: TransactionStatus) {
+        self.only_owner(env::predecessor_account_id());
+        if status == TransactionStatus::Pending {
+            env::panic_str("Outbound transaction can't be marked as pending");
+        }
+        let mut record = self
+            .outbound_transactions
+            .get(&u128::from(nonce))
+            .unwrap_or_else(|| env::panic_str("No outbound transaction with this nonce"));
+        if record.status != TransactionStatus::Pending {
+            env::panic_str("Outbound transaction is already finished");
+        }
+        record.status = status;
+        self.outbound_transactions.insert(&u128::from(nonce), &record);
+        let amount = u128::from(record.transaction.amount);
+        let native = self.native_outbound.contains(&u128::from(nonce));
+        if native {
+            self.pending_native_outbound -= amount;
+            self.total_native_locked -= amount;
+        } else {
+            self.pending_outbound_amount -= amount;
+        }
+        if status == TransactionStatus::Completed {
+            if native {
+                self.native_bridged_out += amount;
+            } else {
+                self.bridged_out_amount += amount;
+            }
+            return;
+        }
+        if env::prepaid_gas() < GAS_FOR_WITHDRAW {
+            env::panic_str("Not enough gas prepaid, at least 50 Tgas is needed");
+        }
+        let sender = AccountId::new_unchecked(record.transaction.from_user);
+        if native {
+            Promise::new(sender.clone())
+                .transfer(amount)
+                .then(
+                    Self::ext(env::current_account_id()).resolve_outbound_refund(
+                        nonce,
+                        sender,
+                        U128::from(amount),
+                        native,
+                    ),
+                );
+            return;
+        }
+        // Reserved until the refund is resolved, so it isn't withdrawn as surplus
+        self.withdrawals_in_flight += amount;
+        ext_ft_core::ext(self.token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(
+                sender.clone(),
+                U128::from(amount),
+                Some("Refund of bridge transfer".to_string()),
+            )
+            .then(Self::ext(env::current_account_id()).resolve_outbound_refund(
+                nonce,
+                sender,
+                U128::from(amount),
+                native,
+            ));
+    }
+
+    // Callback for the refund transfer in set_outbound_status
+    #[private]
+    pub fn resolve_outbound_refund(
+        &mut self,
+        nonce: U128,
+        sender: AccountId,
+        amount: U128,
+        native: bool,
+    ) {
+        let amount = u128::from(amount);
+        if !native {
+            self.withdrawals_in_flight -= amount;
+        }
+        let log = match (env::promise_result(0), self.outbound_transactions.get(&nonce.0)) {
+            (PromiseResult::NotReady, _) => env::abort(),
+            (PromiseResult::Successful(_), _) => {
+                format!("Refunded {} of transaction {} to {}", amount, nonce.0, sender)
+            }
+            (PromiseResult::Failed, Some(mut record)) => {
+                // The send is pending again, so the owner can retry the refund
+                record.status = TransactionStatus::Pending;
+                self.outbound_transactions.insert(&nonce.0, &record);
+                if native {
+                    self.total_native_locked += amount;
+                    self.pending_native_outbound += amount;
+                } else {
+                    self.pending_outbound_amount += amount;
+                }
+                format!(
+                    "Refund of transaction {} to {} failed: transfer promise failed",
+                    nonce.0, sender
+                )
+            }
+            (PromiseResult::Failed, None) => format!(
+                "Refund of transaction {} to {} failed: transaction is pruned",
+                nonce.0, sender
+            ),
+        };
+        env::log_str(&log);
+    }
+
     pub fn set_relayer_role(&mut self, relayer: String) {
         self.only_owner(env::predecessor_account_id());
         let relayer: PublicKey = relayer
@@ -602,15 +4361,199 @@ impl BridgeAssist {
 
     #[payable]
     pub fn withdraw(&mut self, amount: U128) -> Promise {
+        self.withdraw_to(self.owner.clone(), amount)
+    }
+
+    // Withdraws bridged tokens above liabilities, the amount is checked against bridge balance
+    // in the callback, so the result is false if it's more than surplus
+    #[payable]
+    pub fn withdraw_to(&mut self, receiver: AccountId, amount: U128) -> Promise {
         assert_one_yocto();
         self.only_owner(env::predecessor_account_id());
+        if env::prepaid_gas() < GAS_FOR_WITHDRAW {
+            env::panic_str("Not enough gas prepaid, at least 50 Tgas is needed");
+        }
+        if u128::from(amount) == 0 {
+            env::panic_str("Amount should be greater than 0");
+        }
+        // Reserved until the withdrawal is resolved, so parallel ones can't exceed surplus
+        self.withdrawals_in_flight += u128::from(amount);
+        ext_ft_core::ext(self.token.clone())
+            .ft_balance_of(env::current_account_id())
+            .then(
+                Self::ext(env::current_account_id())
+                    .resolve_withdraw_balance(receiver, amount),
+            )
+    }
+
+    // Callback for ft_balance_of of the bridge in withdraw_to
+    #[private]
+    pub fn resolve_withdraw_balance(
+        &mut self,
+        #[callback_result] callback_result: Result<U128, PromiseError>,
+        receiver: AccountId,
+        amount: U128,
+    ) -> PromiseOrValue<bool> {
+        let balance = match callback_result {
+            Ok(balance) => u128::from(balance),
+            Err(_) => {
+                self.withdrawals_in_flight -= u128::from(amount);
+                let log = format!(
+                    "Withdrawal of {} tokens to {} failed: bridge balance is unavailable",
+                    u128::from(amount),
+                    receiver
+                );
+                env::log_str(&log);
+                return PromiseOrValue::Value(false);
+            }
+        };
+        // Balance still includes every reserved withdrawal, this one as well
+        let liabilities = self.pending_outbound_amount
+            + self.bridged_out_amount
+            + self.pending_inbound_amount
+            + self.withdrawals_in_flight
+            + self.unclaimed_refunds
+            + self.total_escrowed
+            + self.total_fast_liquidity
+            + self.total_bonded_tokens;
+        if balance < liabilities {
+            self.withdrawals_in_flight -= u128::from(amount);
+            let log = format!(
+                "Withdrawal of {} tokens to {} failed: amount is more than surplus",
+                u128::from(amount),
+                receiver
+            );
+            env::log_str(&log);
+            return PromiseOrValue::Value(false);
+        }
         ext_ft_core::ext(self.token.clone())
             .with_attached_deposit(1)
             .ft_transfer(
-                self.owner.clone(),
-                U128::from(amount),
+                receiver.clone(),
+                amount,
                 Some("Withdraw from bridge".to_string()),
             )
+            .then(Self::ext(env::current_account_id()).resolve_withdraw(receiver, amount))
+            .into()
+    }
+
+    // Callback for ft_transfer in withdraw_to
+    #[private]
+    pub fn resolve_withdraw(&mut self, receiver: AccountId, amount: U128) -> bool {
+        self.withdrawals_in_flight -= u128::from(amount);
+        let (log, is_withdrawn) = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => (
+                format!("Withdrawn {} tokens to {}", u128::from(amount), receiver),
+                true,
+            ),
+            PromiseResult::Failed => (
+                format!(
+                    "Withdrawal of {} tokens to {} failed: ft_transfer promise failed",
+                    u128::from(amount),
+                    receiver
+                ),
+                false,
+            ),
+        };
+        env::log_str(&log);
+        is_withdrawn
+    }
+
+    // Sends tokens the recipient contract returned from ft_transfer_call to it with plain
+    // ft_transfer, anyone can call it
+    #[payable]
+    pub fn claim_refunded(&mut self, tx_hash: String) -> Promise {
+        assert_one_yocto();
+        let mut transfer_call = self
+            .transfer_calls
+            .get(&tx_hash)
+            .unwrap_or_else(|| env::panic_str("No transfer call with this hash"));
+        let amount = u128::from(transfer_call.refunded_amount);
+        if amount == 0 {
+            env::panic_str("No refunded tokens to claim");
+        }
+        let record = self.inbound_transactions.get(&tx_hash).unwrap();
+        let recipient = AccountId::new_unchecked(record.transaction.to_user);
+        transfer_call.refunded_amount = U128::from(0);
+        self.transfer_calls.insert(&tx_hash, &transfer_call);
+        self.unclaimed_refunds -= amount;
+        ext_ft_core::ext(self.token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(
+                recipient.clone(),
+                U128::from(amount),
+                Some("Refunded transfer call from bridge".to_string()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .resolve_claim_refunded(tx_hash, recipient, U128::from(amount)),
+            )
+    }
+
+    // Callback for ft_transfer in claim_refunded, the refund can be claimed again if it failed
+    #[private]
+    pub fn resolve_claim_refunded(&mut self, tx_hash: String, recipient: AccountId, amount: U128) {
+        let log = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => {
+                format!("Claimed {} refunded tokens to {}", u128::from(amount), recipient)
+            }
+            PromiseResult::Failed => {
+                let mut transfer_call = self.transfer_calls.get(&tx_hash).unwrap();
+                transfer_call.refunded_amount = amount;
+                self.transfer_calls.insert(&tx_hash, &transfer_call);
+                self.unclaimed_refunds += u128::from(amount);
+                format!(
+                    "Claim of {} refunded tokens to {} failed: ft_transfer promise failed",
+                    u128::from(amount),
+                    recipient
+                )
+            }
+        };
+        env::log_str(&log);
+    }
+
+    // Returns tokens other than the bridged one that were sent to the bridge by mistake
+    #[payable]
+    pub fn rescue_ft(&mut self, token: AccountId, receiver: AccountId, amount: U128) -> Promise {
+        assert_one_yocto();
+        self.only_owner(env::predecessor_account_id());
+        if token == self.token {
+            env::panic_str("Bridged token can't be rescued");
+        }
+        if u128::from(amount) == 0 {
+            env::panic_str("Amount should be greater than 0");
+        }
+        ext_ft_core::ext(token.clone())
+            .with_attached_deposit(1)
+            .ft_transfer(
+                receiver.clone(),
+                amount,
+                Some("Rescue from bridge".to_string()),
+            )
+            .then(Self::ext(env::current_account_id()).resolve_rescue(token, receiver, amount))
+    }
+
+    // Callback for rescue_ft
+    #[private]
+    pub fn resolve_rescue(&mut self, token: AccountId, receiver: AccountId, amount: U128) {
+        let log = match env::promise_result(0) {
+            PromiseResult::NotReady => env::abort(),
+            PromiseResult::Successful(_) => format!(
+                "Rescued {} tokens of {} to {}",
+                u128::from(amount),
+                token,
+                receiver
+            ),
+            PromiseResult::Failed => format!(
+                "Rescue of {} tokens of {} to {} failed",
+                u128::from(amount),
+                token,
+                receiver
+            ),
+        };
+        env::log_str(&log);
     }
 
     #[payable]
@@ -620,9 +4563,34 @@ impl BridgeAssist {
         if u128::from(amount) > env::account_balance() {
             env::panic_str("Amount is more than contract balance");
         }
-        if env::account_balance() - u128::from(amount) < self.total_storage_paid {
-            env::panic_str("Left contract balance is less than users total storage paid");
+        // NEAR held for users, relayers, challengers and registrations can't be withdrawn as fees
+        let reserved = self.total_storage_paid
+            + self.registration_pool
+            + self.total_native_locked
+            + self.native_bridged_out
+            + self.total_fast_bonds
+            + self.total_bonded_near
+            + self.total_challenge_bonds;
+        if env::account_balance() - u128::from(amount) < reserved {
+            env::panic_str("Left contract balance is less than reserved NEAR");
+        }
+        Promise::new(self.owner.clone()).transfer(u128::from(amount));
+    }
+
+    // Funds registration of fulfill recipients on token, anyone can top it up
+    #[payable]
+    pub fn fund_registration_pool(&mut self) {
+        self.registration_pool += env::attached_deposit();
+    }
+
+    #[payable]
+    pub fn withdraw_registration_pool(&mut self, amount: U128) {
+        assert_one_yocto();
+        self.only_owner(env::predecessor_account_id());
+        if u128::from(amount) > self.registration_pool {
+            env::panic_str("Amount is more than registration pool");
         }
+        self.registration_pool -= u128::from(amount);
         Promise::new(self.owner.clone()).transfer(u128::from(amount));
     }
 
@@ -631,6 +4599,14 @@ impl BridgeAssist {
         View functions
         --------------
     */
+    pub fn get_staged_upgrade(&self) -> Option<StagedUpgrade> {
+        self.staged_upgrade.clone()
+    }
+
+    pub fn get_state_version(&self) -> u32 {
+        read_state_version()
+    }
+
     pub fn get_owner(&self) -> AccountId {
         self.owner.clone()
     }
@@ -655,43 +4631,321 @@ impl BridgeAssist {
         self.nonce
     }
 
-    pub fn get_transaction_by_user(&self, user: String, index: U64) -> Transaction {
-        let txs = self.transactions.get(&user).unwrap_or_else(|| {
-            Vector::new(StorageKey::TransactionsInner {
-                account_id_hash: env::sha256_array(user.as_bytes()),
-            })
-        });
-        txs.get(u64::from(index))
-            .unwrap_or_else(|| env::panic_str("Index out of range"))
+    // None if there is no outbound transaction with this nonce or it has been pruned
+    pub fn get_transaction_by_nonce(&self, nonce: U128) -> Option<OutboundRecord> {
+        self.get_outbound_record(u128::from(nonce))
+    }
+
+    // Outbound transactions with nonces in [from, to), pruned ones are skipped
+    pub fn get_transactions_in_nonce_range(&self, from: U128, to: U128) -> Vec<OutboundRecord> {
+        let (from, to) = (u128::from(from), u128::from(to));
+        if to > from && to - from > DEFAULT_PAGE_LIMIT as u128 {
+            env::panic_str("Nonce range is too wide");
+        }
+        (from..to.min(u128::from(self.nonce)))
+            .filter_map(|nonce| self.get_outbound_record(nonce))
+            .collect()
+    }
+
+    // Nonce of the last outbound transaction, None if nothing was sent yet
+    pub fn get_latest_nonce(&self) -> Option<U128> {
+        u128::from(self.nonce).checked_sub(1).map(U128::from)
+    }
+
+    pub fn get_outbound_transaction_by_hash(&self, tx_hash: String) -> Option<OutboundRecord> {
+        self.outbound_by_hash
+            .get(&tx_hash)
+            .and_then(|nonce| self.get_outbound_record(nonce))
+    }
+
+    pub fn get_inbound_transaction_by_hash(&self, tx_hash: String) -> Option<InboundRecord> {
+        self.get_inbound_record(&tx_hash)
+    }
+
+    // Outbound transactions sent by the user
+    pub fn get_transaction_by_user(&self, user: AccountId, index: U64) -> OutboundRecord {
+        let nonce = self
+            .get_outbound_nonces(&user)
+            .get(u64::from(index))
+            .unwrap_or_else(|| env::panic_str("Index out of range"));
+        self.get_outbound_record(nonce).unwrap()
+    }
+
+    // Oldest DEFAULT_PAGE_LIMIT transactions only, so a long history can't exceed the view gas.
+    // The paginated views read the rest
+    pub fn get_transactions_by_user(&self, user: AccountId) -> Vec<OutboundRecord> {
+        self.get_transactions_by_user_paginated(user, None, None)
+    }
+
+    pub fn get_transactions_by_user_paginated(
+        &self,
+        user: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<OutboundRecord> {
+        self.get_filtered_transactions_by_user(
+            user,
+            TransactionFilter::default(),
+            from_index,
+            limit,
+            Some(false),
+        )
+        .records
+    }
+
+    // Newest transactions first, from_index is counted from the newest one
+    pub fn get_transactions_by_user_reversed(
+        &self,
+        user: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<OutboundRecord> {
+        self.get_filtered_transactions_by_user(
+            user,
+            TransactionFilter::default(),
+            from_index,
+            limit,
+            Some(true),
+        )
+        .records
+    }
+
+    // Scans the user's list from from_index (counted from the newest transaction if reverse) and
+    // returns up to limit transactions that match the filter with the index to continue from
+    pub fn get_filtered_transactions_by_user(
+        &self,
+        user: AccountId,
+        filter: TransactionFilter,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+        reverse: Option<bool>,
+    ) -> TransactionPage<OutboundRecord> {
+        let nonces = self.get_outbound_nonces(&user);
+        select_page(
+            nonces.len(),
+            |index| self.get_outbound_record(nonces.get(index).unwrap()).unwrap(),
+            |record: &OutboundRecord| filter.matches(&record.transaction, record.status),
+            from_index,
+            limit,
+            reverse,
+        )
+    }
+
+    pub fn get_transactions_amount_by_user(&self, user: AccountId) -> U64 {
+        U64(self.get_outbound_nonces(&user).len())
+    }
+
+    // Inbound transactions fulfilled to the user
+    pub fn get_inbound_transaction_by_user(&self, user: AccountId, index: U64) -> InboundRecord {
+        let tx_hash = self
+            .get_inbound_hashes(&user)
+            .get(u64::from(index))
+            .unwrap_or_else(|| env::panic_str("Index out of range"));
+        self.get_inbound_record(&tx_hash).unwrap()
+    }
+
+    pub fn get_inbound_transactions_by_user(
+        &self,
+        user: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<InboundRecord> {
+        self.get_filtered_inbound_transactions_by_user(
+            user,
+            TransactionFilter::default(),
+            from_index,
+            limit,
+            Some(false),
+        )
+        .records
+    }
+
+    pub fn get_filtered_inbound_transactions_by_user(
+        &self,
+        user: AccountId,
+        filter: TransactionFilter,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+        reverse: Option<bool>,
+    ) -> TransactionPage<InboundRecord> {
+        let hashes = self.get_inbound_hashes(&user);
+        select_page(
+            hashes.len(),
+            |index| self.get_inbound_record(&hashes.get(index).unwrap()).unwrap(),
+            |record: &InboundRecord| filter.matches(&record.transaction, record.status),
+            from_index,
+            limit,
+            reverse,
+        )
+    }
+
+    pub fn get_inbound_transactions_amount_by_user(&self, user: AccountId) -> U64 {
+        U64(self.get_inbound_hashes(&user).len())
+    }
+
+    // Sends made before the upgrade that split records by direction, they have no status
+    pub fn get_legacy_transactions_by_user(
+        &self,
+        user: String,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<Transaction> {
+        let Some(transactions) = self.legacy_transactions.get(&user) else {
+            return Vec::new();
+        };
+        select_page(
+            transactions.len(),
+            |index| transactions.get(index).unwrap(),
+            |_| true,
+            from_index,
+            limit,
+            Some(false),
+        )
+        .records
+    }
+
+    // Outbound record with its payload for views
+    fn get_outbound_record(&self, nonce: u128) -> Option<OutboundRecord> {
+        self.outbound_transactions.get(&nonce).map(|record| OutboundRecord {
+            payload: self.outbound_payloads.get(&nonce),
+            native: self.native_outbound.contains(&nonce),
+            ..record
+        })
+    }
+
+    // Inbound record with its native flag for views
+    fn get_inbound_record(&self, tx_hash: &String) -> Option<InboundRecord> {
+        self.inbound_transactions.get(tx_hash).map(|record| InboundRecord {
+            native: self.native_inbound.contains(tx_hash),
+            ..record
+        })
+    }
+
+    // Hash outbound transaction is indexed by, native ones are domain separated
+    fn get_outbound_hash(&self, transaction: &Transaction) -> String {
+        let nonce = u128::from(transaction.nonce);
+        if self.native_outbound.contains(&nonce) {
+            hex::encode(self.get_native_tx_hash(transaction))
+        } else {
+            hex::encode(self.get_tx_hash(transaction))
+        }
+    }
+
+    // True if the account has history, escrow or relayer bonds paid from its storage balance
+    fn has_storage_data(&self, user: &AccountId) -> bool {
+        self.outbound_by_user.contains_key(user)
+            || self.inbound_by_user.contains_key(user)
+            || self.legacy_transactions.contains_key(&user.to_string())
+            || self.escrows.contains_key(user)
+            || self.relayer_keys.contains_key(user)
+    }
+
+    // Removes data of the account for forced unregister. Its history indexes are cleared while
+    // the records stay for their status, escrowed tokens and relayer bonds are burned
+    fn remove_storage_data(&mut self, user: &AccountId) {
+        if let Some(mut nonces) = self.outbound_by_user.remove(user) {
+            nonces.clear();
+        }
+        if let Some(mut hashes) = self.inbound_by_user.remove(user) {
+            hashes.clear();
+        }
+        if let Some(mut transactions) = self.legacy_transactions.remove(&user.to_string()) {
+            transactions.clear();
+        }
+        if let Some(escrow) = self.escrows.remove(user) {
+            self.total_escrowed -= u128::from(escrow.balance);
+        }
+        for relayer_key in self.relayer_keys.remove(user).unwrap_or_default() {
+            let bond = self.relayer_bonds.remove(&relayer_key).unwrap();
+            self.total_bonded_near -= u128::from(bond.near) + u128::from(bond.unbonding_near);
+            self.total_bonded_tokens -=
+                u128::from(bond.tokens) + u128::from(bond.unbonding_tokens);
+        }
+        env::log_str(&format!("Data of {} is removed by forced unregister", user));
     }
 
-    pub fn get_transactions_by_user(&self, user: String) -> Vec<Transaction> {
-        let txs = self.transactions.get(&user).unwrap_or_else(|| {
-            Vector::new(StorageKey::TransactionsInner {
+    fn get_outbound_nonces(&self, user: &AccountId) -> HistoryList<u128> {
+        self.outbound_by_user.get(user).unwrap_or_else(|| {
+            HistoryList::new(StorageKey::OutboundByUserInner {
                 account_id_hash: env::sha256_array(user.as_bytes()),
             })
-        });
-        txs.to_vec()
+        })
     }
 
-    pub fn get_transactions_amount_by_user(&self, user: String) -> U64 {
-        let txs = self.transactions.get(&user).unwrap_or_else(|| {
-            Vector::new(StorageKey::TransactionsInner {
+    fn get_inbound_hashes(&self, user: &AccountId) -> HistoryList<String> {
+        self.inbound_by_user.get(user).unwrap_or_else(|| {
+            HistoryList::new(StorageKey::InboundByUserInner {
                 account_id_hash: env::sha256_array(user.as_bytes()),
             })
-        });
-        U64(txs.len())
+        })
+    }
+
+    pub fn get_retention_period(&self) -> U64 {
+        U64::from(self.retention_period)
+    }
+
+    // Number of transactions prune_transactions can remove now and storage it would refund
+    pub fn get_reclaimable_storage(
+        &self,
+        user: AccountId,
+        direction: TransactionDirection,
+    ) -> (U64, U128) {
+        let (costs, list_len, list_cost): (Vec<Balance>, u64, Balance) = match direction {
+            TransactionDirection::Outbound => {
+                let nonces = self.get_outbound_nonces(&user);
+                let costs = nonces
+                    .iter()
+                    .map(|nonce| self.outbound_transactions.get(&nonce).unwrap())
+                    .take_while(|record| self.is_prunable(&record.transaction, record.status))
+                    .map(|record| self.outbound_storage_cost(&user, &record))
+                    .collect();
+                let list_bytes = map_entry_bytes(StorageKey::OutboundByUser, &user, &nonces);
+                (costs, nonces.len(), list_bytes as u128 * env::STORAGE_PRICE_PER_BYTE)
+            }
+            TransactionDirection::Inbound => {
+                let hashes = self.get_inbound_hashes(&user);
+                let costs = hashes
+                    .iter()
+                    .map(|tx_hash| (self.inbound_transactions.get(&tx_hash).unwrap(), tx_hash))
+                    .take_while(|(record, tx_hash)| self.is_inbound_prunable(tx_hash, record))
+                    .map(|(record, tx_hash)| self.inbound_storage_cost(&user, &tx_hash, &record))
+                    .collect();
+                let list_bytes = map_entry_bytes(StorageKey::InboundByUser, &user, &hashes);
+                (costs, hashes.len(), list_bytes as u128 * env::STORAGE_PRICE_PER_BYTE)
+            }
+        };
+        let count = costs.len() as u64;
+        let mut amount: Balance = costs.iter().sum();
+        // The list itself is removed too when all of its transactions are pruned
+        if count > 0 && count == list_len {
+            amount += list_cost;
+        }
+        (U64(count), U128(amount))
     }
 
     pub fn is_tx_fulfilled(&self, tx_hash: String) -> bool {
         self.fulfilled.contains(&tx_hash)
     }
 
+    // None if the transaction has never been fulfilled or its record has been pruned
+    pub fn get_fulfill_info(&self, tx_hash: String) -> Option<FulfillInfo> {
+        self.inbound_transactions.get(&tx_hash).map(|record| FulfillInfo {
+            status: record.status,
+            fulfilled_at: record.fulfilled_at,
+            recipient: record.transaction.to_user,
+            dispensed_amount: record.dispensed_amount,
+            fee: record.fee,
+            failure_reason: record.failure_reason,
+            transfer_call: self.transfer_calls.get(&tx_hash),
+            message: self.inbound_messages.get(&tx_hash),
+        })
+    }
+
     pub fn get_storage_paid_info(&self, user: AccountId) -> (bool, U128, U128, U128, U128, U128) {
         let storage_cost = env::STORAGE_PRICE_PER_BYTE;
         (
             self.storage_paid.contains_key(&user),
-            U128::from(self.storage_paid.get(&user).unwrap_or(0 as u128)),
+            U128::from(self.storage_paid.get(&user).unwrap_or(0)),
             U128::from(self.bytes_for_register as u128 * storage_cost),
             U128::from(self.bytes_for_ft_on_transfer as u128 * storage_cost),
             U128::from(self.bytes_for_fulfill as u128 * storage_cost),
@@ -699,14 +4953,161 @@ impl BridgeAssist {
         )
     }
 
+    pub fn get_registration_pool(&self) -> U128 {
+        U128::from(self.registration_pool)
+    }
+
+    pub fn get_implicit_account_funding(&self) -> U128 {
+        U128::from(self.implicit_account_funding)
+    }
+
+    // Empty escrow with zero nonce for users who haven't escrowed tokens
+    pub fn get_escrow(&self, user: AccountId) -> Escrow {
+        self.escrows.get(&user).unwrap_or(Escrow {
+            balance: U128::from(0),
+            intent_key: None,
+            intent_nonce: U64::from(0),
+        })
+    }
+
+    pub fn get_optimistic_claim(&self, tx_hash: String) -> Option<OptimisticClaim> {
+        self.optimistic_claims.get(&tx_hash)
+    }
+
+    pub fn is_tx_vetoed(&self, tx_hash: String) -> bool {
+        self.vetoed.contains(&tx_hash)
+    }
+
+    pub fn get_optimistic_window(&self, chain: String) -> Option<U64> {
+        self.optimistic_windows.get(&chain).map(U64::from)
+    }
+
+    pub fn get_guardians(&self) -> Vec<AccountId> {
+        self.guardians.to_vec()
+    }
+
+    pub fn get_challenge_bond(&self) -> U128 {
+        U128::from(self.challenge_bond)
+    }
+
+    // None if the key has never been bonded
+    pub fn get_relayer_bond(&self, relayer_key: PublicKey) -> Option<RelayerBond> {
+        self.relayer_bonds.get(&relayer_key)
+    }
+
+    // Attestation of a token transaction if kind isn't passed
+    pub fn get_attestation(
+        &self,
+        from_chain: String,
+        nonce: U128,
+        kind: Option<TransactionKind>,
+    ) -> Option<Attestation> {
+        let kind = kind.unwrap_or(TransactionKind::Token);
+        self.attestations.get(&self.get_attestation_key(kind, &from_chain, nonce))
+    }
+
+    // Minimum NEAR and tokens bond, dispute window and unbonding period in seconds
+    pub fn get_relayer_bond_info(&self) -> (U128, U128, U64, U64) {
+        (
+            U128::from(self.min_relayer_bond_near),
+            U128::from(self.min_relayer_bond_tokens),
+            U64::from(self.dispute_window),
+            U64::from(self.unbonding_period),
+        )
+    }
+
+    pub fn get_fast_relayer(&self, relayer: AccountId) -> Option<FastRelayer> {
+        self.fast_relayers.get(&relayer)
+    }
+
+    pub fn get_fast_fulfill(&self, from_chain: String, nonce: U128) -> Option<FastFulfill> {
+        self.fast_fulfills.get(&format!("{}:{}", from_chain, u128::from(nonce)))
+    }
+
+    // Fast fee numerator and minimum bond of fast relayers
+    pub fn get_fast_fee_info(&self) -> (u16, U128) {
+        (self.fast_fee_numerator, U128::from(self.fast_min_bond))
+    }
+
+    pub fn get_nft_contracts(&self) -> Vec<AccountId> {
+        self.nft_contracts.to_vec()
+    }
+
+    pub fn get_nft_nonce(&self) -> U128 {
+        self.nft_nonce
+    }
+
+    pub fn get_nft_transaction_by_nonce(&self, nonce: U128) -> Option<NftTransaction> {
+        self.nft_outbound.get(&u128::from(nonce))
+    }
+
+    // NEAR held for native bridging, the part backing pending sends, the limit per send and NEAR
+    // of completed sends
+    pub fn get_native_info(&self) -> (U128, U128, U128, U128) {
+        (
+            U128::from(self.total_native_locked),
+            U128::from(self.pending_native_outbound),
+            U128::from(self.native_limit_per_send),
+            U128::from(self.native_bridged_out),
+        )
+    }
+
+    pub fn get_pay_for_registration(&self) -> U128 {
+        U128::from(MIN_TOKEN_STORAGE_DEPOSIT)
+    }
+
     pub fn get_pay_for_add_chain(&self) -> U128 {
         U128::from(self.bytes_for_add_chain as u128 * env::STORAGE_PRICE_PER_BYTE)
     }
 
+    pub fn get_pay_for_checkpoint(&self) -> U128 {
+        U128::from(self.bytes_for_checkpoint as u128 * env::STORAGE_PRICE_PER_BYTE)
+    }
+
+    pub fn get_checkpoint(&self, root: String) -> Option<Checkpoint> {
+        self.checkpoints.get(&root)
+    }
+
+    // Zero stats if nothing has been bridged with the chain yet
+    pub fn get_chain_stats(&self, chain: String) -> ChainStats {
+        self.chain_stats.get(&chain).unwrap_or_default()
+    }
+
+    pub fn get_user_volume(&self, user: AccountId) -> UserVolume {
+        self.user_volumes.get(&user).unwrap_or_default()
+    }
+
+    pub fn get_liabilities(&self) -> Liabilities {
+        Liabilities {
+            pending_outbound: U128::from(self.pending_outbound_amount),
+            bridged_out: U128::from(self.bridged_out_amount),
+            pending_inbound: U128::from(self.pending_inbound_amount),
+            withdrawals_in_flight: U128::from(self.withdrawals_in_flight),
+            unclaimed_refunds: U128::from(self.unclaimed_refunds),
+            escrowed: U128::from(self.total_escrowed),
+            fast_liquidity: U128::from(self.total_fast_liquidity),
+            relayer_bonds: U128::from(self.total_bonded_tokens),
+        }
+    }
+
+    pub fn get_bridge_stats(&self) -> BridgeStats {
+        BridgeStats {
+            outbound_count: U64::from(self.outbound_count),
+            inbound_count: U64::from(self.inbound_count),
+            total_fees_collected: U128::from(self.total_fees_collected),
+        }
+    }
+
     pub fn is_available_chain(&self, chain: String) -> bool {
         self.available_chains.contains(&chain)
     }
 
+    pub fn get_chain_address_format(&self, chain: String) -> AddressFormat {
+        self.chain_address_formats
+            .get(&chain)
+            .unwrap_or(AddressFormat::Evm)
+    }
+
     pub fn supported_chain_list(&self) -> Vec<String> {
         self.available_chains.to_vec()
     }
@@ -717,4 +5118,146 @@ impl BridgeAssist {
                 .unwrap_or_else(|_| env::panic_str("Serializing transaction field is failed")),
         )
     }
+
+    // Hash the relayer key signs to bond it for the owner on this bridge account
+    pub fn get_bond_hash(&self, owner: AccountId) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&(BOND_DOMAIN, env::current_account_id(), owner))
+                .unwrap_or_else(|_| env::panic_str("Serializing bond field is failed")),
+        )
+    }
+
+    // Bound to this bridge account, so the intent can't be replayed on another deployment
+    pub fn get_intent_hash(&self, intent: &SendIntent) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&(INTENT_DOMAIN, env::current_account_id(), intent))
+                .unwrap_or_else(|_| env::panic_str("Serializing intent field is failed")),
+        )
+    }
+
+    pub fn get_nft_tx_hash(&self, transaction: &NftTransaction) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&(NFT_DOMAIN, transaction))
+                .unwrap_or_else(|_| env::panic_str("Serializing transaction field is failed")),
+        )
+    }
+
+    // Hash of a transaction that sends NEAR, both outbound and inbound ones
+    pub fn get_native_tx_hash(&self, transaction: &Transaction) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&(NATIVE_DOMAIN, transaction))
+                .unwrap_or_else(|_| env::panic_str("Serializing transaction field is failed")),
+        )
+    }
+
+    // Hash the relayer signs for a transaction delivered with ft_transfer_call
+    pub fn get_tx_hash_with_msg(&self, transaction: &Transaction, msg: String) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&(transaction, msg))
+                .unwrap_or_else(|_| env::panic_str("Serializing transaction field is failed")),
+        )
+    }
+
+    // Hash the relayer signs for a transaction delivered with a message call
+    pub fn get_tx_hash_with_call(&self, transaction: &Transaction, call: MessageCall) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&(transaction, call))
+                .unwrap_or_else(|_| env::panic_str("Serializing transaction field is failed")),
+        )
+    }
+
+    // Leaf of the transaction in checkpoint merkle trees, it differs from the signed tx hash
+    pub fn get_checkpoint_leaf(&self, transaction: &Transaction) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&(CHECKPOINT_LEAF_DOMAIN, transaction))
+                .unwrap_or_else(|_| env::panic_str("Serializing transaction field is failed")),
+        )
+    }
+
+    pub fn get_checkpoint_hash(&self, checkpoint: &Checkpoint) -> Vec<u8> {
+        env::keccak256(
+            &bincode::serialize(&checkpoint)
+                .unwrap_or_else(|_| env::panic_str("Serializing checkpoint field is failed")),
+        )
+    }
+}
+
+// Panics if the message isn't signed by the ed25519 public key
+fn verify_signature(public_key: &PublicKey, message: &[u8], signature: &[u8]) {
+    let signature = ed25519_dalek::Signature::try_from(signature)
+        .unwrap_or_else(|_| env::panic_str("Signature should be a valid array of 64 bytes"));
+    let public_key_without_prefix = &String::from(public_key)[8..];
+    let public_key = ed25519_dalek_PublicKey::from_bytes(
+        &bs58::decode(public_key_without_prefix).into_vec().unwrap(),
+    )
+    .unwrap();
+
+    if public_key.verify(message, &signature).is_err() {
+        env::panic_str("Wrong signature");
+    }
+}
+
+fn is_valid_payload(payload: &str) -> bool {
+    payload.starts_with("0x")
+        && payload.len() % 2 == 0
+        && payload.len() - 2 <= MAX_PAYLOAD_LENGTH * 2
+        && payload[2..].bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Implicit account id is 64 lowercase hexadecimal characters of its ed25519 public key
+fn is_implicit_account(account_id: &AccountId) -> bool {
+    account_id.as_str().len() == IMPLICIT_ACCOUNT_ID_LENGTH
+        && account_id
+            .as_str()
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// Reads records of a list of len by index starting from from_index, until limit of them match
+// or MAX_SCANNED_RECORDS are read. Records before from_index aren't read at all
+fn select_page<T>(
+    len: u64,
+    get: impl Fn(u64) -> T,
+    matches: impl Fn(&T) -> bool,
+    from_index: Option<U64>,
+    limit: Option<U64>,
+    reverse: Option<bool>,
+) -> TransactionPage<T> {
+    let from_index = from_index.map_or(0, u64::from).min(len);
+    let limit = limit.map_or(DEFAULT_PAGE_LIMIT, u64::from) as usize;
+    let scan_end = len.min(from_index.saturating_add(MAX_SCANNED_RECORDS));
+    let mut records = Vec::new();
+    let mut next_index = from_index;
+    while next_index < scan_end && records.len() < limit {
+        let index = if reverse.unwrap_or(false) {
+            len - 1 - next_index
+        } else {
+            next_index
+        };
+        let record = get(index);
+        if matches(&record) {
+            records.push(record);
+        }
+        next_index += 1;
+    }
+    TransactionPage {
+        records,
+        next_index: (next_index < len).then_some(U64(next_index)),
+    }
+}
+
+// Storage taken by one LookupMap entry, its key is the map prefix followed by the serialized key
+fn map_entry_bytes<K: BorshSerialize, V: BorshSerialize>(
+    prefix: StorageKey,
+    key: &K,
+    value: &V,
+) -> u64 {
+    let key_len = prefix.try_to_vec().unwrap().len() + key.try_to_vec().unwrap().len();
+    STORAGE_RECORD_OVERHEAD + (key_len + value.try_to_vec().unwrap().len()) as u64
+}
+
+// Storage taken by one HistoryList element, its key is the list prefix followed by u64 position
+fn vector_element_bytes<V: BorshSerialize>(prefix: StorageKey, value: &V) -> u64 {
+    let key_len = prefix.try_to_vec().unwrap().len() + std::mem::size_of::<u64>();
+    STORAGE_RECORD_OVERHEAD + (key_len + value.try_to_vec().unwrap().len()) as u64
 }
@@ -0,0 +1,136 @@
+use crate::*;
+
+// Key of the state layout version, it's kept outside of the contract struct, so it can be read
+// before the state itself is deserialized
+pub(crate) const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+pub(crate) const STATE_VERSION: u32 = 2;
+
+// Layout of BridgeAssist before upgrades were supported, it has no version key in storage
+#[derive(BorshDeserialize)]
+pub(crate) struct BridgeAssistV1 {
+    bytes_for_register: StorageUsage,
+    bytes_for_ft_on_transfer: StorageUsage,
+    bytes_for_fulfill: StorageUsage,
+    bytes_for_add_chain: StorageUsage,
+    owner: AccountId,
+    relayer_role: PublicKey,
+    token: AccountId,
+    fee_wallet: AccountId,
+    limit_per_send: Balance,
+    nonce: U128,
+    fee_numerator: u16,
+    transactions: LookupMap<String, Vector<Transaction>>,
+    fulfilled: LookupSet<String>,
+    storage_paid: LookupMap<AccountId, Balance>,
+    total_storage_paid: Balance,
+    available_chains: UnorderedSet<String>,
+}
+
+impl From<BridgeAssistV1> for BridgeAssist {
+    fn from(old: BridgeAssistV1) -> Self {
+        Self {
+            bytes_for_register: old.bytes_for_register,
+            bytes_for_ft_on_transfer: old.bytes_for_ft_on_transfer,
+            bytes_for_fulfill: old.bytes_for_fulfill,
+            bytes_for_add_chain: old.bytes_for_add_chain,
+            // Measured by migrate after the conversion
+            bytes_for_checkpoint: 0,
+            owner: old.owner,
+            relayer_role: old.relayer_role,
+            token: old.token,
+            fee_wallet: old.fee_wallet,
+            limit_per_send: old.limit_per_send,
+            nonce: old.nonce,
+            fee_numerator: old.fee_numerator,
+            retention_period: DEFAULT_RETENTION_PERIOD,
+            outbound_by_user: LookupMap::new(StorageKey::OutboundByUser),
+            outbound_transactions: LookupMap::new(StorageKey::OutboundTransactions),
+            outbound_by_hash: LookupMap::new(StorageKey::OutboundByHash),
+            inbound_transactions: LookupMap::new(StorageKey::InboundTransactions),
+            inbound_by_user: LookupMap::new(StorageKey::InboundByUser),
+            fulfilled: old.fulfilled,
+            storage_paid: old.storage_paid,
+            total_storage_paid: old.total_storage_paid,
+            registration_pool: 0,
+            available_chains: old.available_chains,
+            checkpoints: LookupMap::new(StorageKey::Checkpoints),
+            last_checkpoint_block: LookupMap::new(StorageKey::LastCheckpointBlocks),
+            chain_stats: LookupMap::new(StorageKey::ChainStats),
+            user_volumes: LookupMap::new(StorageKey::UserVolumes),
+            // Every send took one nonce
+            outbound_count: u128::from(old.nonce) as u64,
+            inbound_count: 0,
+            total_fees_collected: 0,
+            pending_outbound_amount: 0,
+            pending_inbound_amount: 0,
+            withdrawals_in_flight: 0,
+            // Sends of the old layout weren't tracked, so their tokens aren't counted
+            bridged_out_amount: 0,
+            staged_upgrade: None,
+            // Sends of the old layout stay in their per-user vectors and are only read
+            legacy_transactions: old.transactions,
+            idempotency_keys: LookupMap::new(StorageKey::IdempotencyKeys),
+            chain_address_formats: LookupMap::new(StorageKey::ChainAddressFormats),
+            implicit_account_funding: 0,
+            transfer_calls: LookupMap::new(StorageKey::TransferCalls),
+            unclaimed_refunds: 0,
+            outbound_payloads: LookupMap::new(StorageKey::OutboundPayloads),
+            inbound_messages: LookupMap::new(StorageKey::InboundMessages),
+            native_outbound: LookupSet::new(StorageKey::NativeOutbound),
+            total_native_locked: 0,
+            pending_native_outbound: 0,
+            native_limit_per_send: 0,
+            native_inbound: LookupSet::new(StorageKey::NativeInbound),
+            nft_contracts: UnorderedSet::new(StorageKey::NftContracts),
+            nft_outbound: LookupMap::new(StorageKey::NftOutbound),
+            nft_nonce: U128::from(0),
+            escrows: LookupMap::new(StorageKey::Escrows),
+            total_escrowed: 0,
+            fast_relayers: LookupMap::new(StorageKey::FastRelayers),
+            fast_fulfills: LookupMap::new(StorageKey::FastFulfills),
+            fast_fee_numerator: 0,
+            fast_min_bond: 0,
+            total_fast_liquidity: 0,
+            total_fast_bonds: 0,
+            relayer_bonds: LookupMap::new(StorageKey::RelayerBonds),
+            attestations: LookupMap::new(StorageKey::Attestations),
+            min_relayer_bond_near: 0,
+            min_relayer_bond_tokens: 0,
+            dispute_window: DEFAULT_DISPUTE_WINDOW,
+            unbonding_period: DEFAULT_UNBONDING_PERIOD,
+            total_bonded_near: 0,
+            total_bonded_tokens: 0,
+            optimistic_windows: LookupMap::new(StorageKey::OptimisticWindows),
+            optimistic_claims: LookupMap::new(StorageKey::OptimisticClaims),
+            guardians: UnorderedSet::new(StorageKey::Guardians),
+            challenge_bond: 0,
+            vetoed: LookupSet::new(StorageKey::Vetoed),
+            total_challenge_bonds: 0,
+            relayer_keys: LookupMap::new(StorageKey::RelayerKeys),
+            native_bridged_out: 0,
+        }
+    }
+}
+
+// Reads state of an older version and converts it to the current layout. The baseline layout is
+// the only one deployed before versioning, later layouts are added here when a version with
+// state is deployed
+pub(crate) fn read_old_state(version: u32) -> BridgeAssist {
+    match version {
+        1 => env::state_read::<BridgeAssistV1>()
+            .unwrap_or_else(|| env::panic_str("Failed to read old state"))
+            .into(),
+        _ => env::panic_str("Unknown state version"),
+    }
+}
+
+pub(crate) fn read_state_version() -> u32 {
+    env::storage_read(STATE_VERSION_KEY).map_or(1, |version| {
+        u32::try_from_slice(&version)
+            .unwrap_or_else(|_| env::panic_str("State version is corrupted"))
+    })
+}
+
+pub(crate) fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &STATE_VERSION.try_to_vec().unwrap());
+}